@@ -0,0 +1,110 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+extern crate image;
+extern crate visual_odometry_rs as vors;
+
+use std::{env, error::Error, path::PathBuf};
+
+use vors::core::calibration::{self, Config};
+use vors::misc::interop;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if let Err(error) = my_run(&args) {
+        eprintln!("{:?}", error);
+    }
+}
+
+const USAGE: &str = "Usage: ./vors_calibrate nb_rows nb_cols square_size image_file...";
+
+fn my_run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    // Check that the arguments are correct.
+    let valid_args = check_args(args)?;
+
+    // Setup calibration configuration.
+    let config = Config {
+        pattern_size: valid_args.pattern_size,
+        square_size: valid_args.square_size,
+        fix_k3: true,
+        lm_config: calibration::DEFAULT_LM_CONFIG,
+    };
+
+    // Detect the checkerboard in every image.
+    let mut corners = Vec::new();
+    let mut detected_paths = Vec::new();
+    for path in valid_args.image_paths.iter() {
+        let img = interop::matrix_from_image(image::open(path)?.to_luma());
+        match config.detect(&img) {
+            Some(img_corners) => {
+                corners.push(img_corners);
+                detected_paths.push(path);
+            }
+            None => eprintln!("Checkerboard not found: {}", path.display()),
+        }
+    }
+    eprintln!(
+        "Checkerboard found in {} of {} images",
+        corners.len(),
+        valid_args.image_paths.len()
+    );
+
+    // Calibrate the camera.
+    let calibration = config.calibrate(&corners)?;
+    for (path, error) in detected_paths
+        .iter()
+        .zip(calibration.reprojection_errors.iter())
+    {
+        eprintln!("Reprojection error of {}: {} px", path.display(), error);
+    }
+    eprintln!("Number of iterations: {}", calibration.nb_iterations);
+
+    // Print to stdout the calibrated camera.
+    let camera = &calibration.camera;
+    let (fu, fv) = camera.pinhole.focal;
+    let (cu, cv) = camera.pinhole.principal_point;
+    let (k1, k2, k3) = camera.radial;
+    let (p1, p2) = camera.tangential;
+    println!("fu fv cu cv: {} {} {} {}", fu, fv, cu, cv);
+    println!("k1 k2 p1 p2 k3: {} {} {} {} {}", k1, k2, p1, p2, k3);
+    println!("RMS reprojection error: {} px", calibration.rms_error);
+
+    Ok(())
+}
+
+struct Args {
+    pattern_size: (usize, usize),
+    square_size: f32,
+    image_paths: Vec<PathBuf>,
+}
+
+/// Verify that command line arguments are correct.
+fn check_args(args: &[String]) -> Result<Args, String> {
+    if let [_, nb_rows, nb_cols, square_size, image_paths @ ..] = args {
+        let parse_error = |s: &str| {
+            eprintln!("{}", USAGE);
+            format!("Invalid number: {}", s)
+        };
+        let nb_rows = nb_rows.parse().map_err(|_| parse_error(nb_rows))?;
+        let nb_cols = nb_cols.parse().map_err(|_| parse_error(nb_cols))?;
+        let square_size = square_size.parse().map_err(|_| parse_error(square_size))?;
+        let image_paths: Vec<PathBuf> = image_paths.iter().map(PathBuf::from).collect();
+        if let Some(missing) = image_paths.iter().find(|p| !p.is_file()) {
+            eprintln!("{}", USAGE);
+            Err(format!(
+                "The image file does not exist or is not reachable: {}",
+                missing.display()
+            ))
+        } else {
+            Ok(Args {
+                pattern_size: (nb_rows, nb_cols),
+                square_size,
+                image_paths,
+            })
+        }
+    } else {
+        eprintln!("{}", USAGE);
+        Err("Wrong number of arguments".to_string())
+    }
+}
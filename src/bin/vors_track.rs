@@ -10,8 +10,13 @@ use na::DMatrix;
 use std::{env, error::Error, fs, io::BufReader, io::Read, path::Path, path::PathBuf};
 
 use vors::core::camera::Intrinsics;
-use vors::core::track::inverse_compositional as track;
+use vors::core::depth_noise::{self, DepthNoiseModel};
+use vors::core::inverse_depth;
+use vors::core::track::{
+    inverse_compositional as track, keyframe, lm_optimizer::LMConfig, motion_model::MotionModel,
+};
 use vors::dataset::tum_rgbd;
+use vors::math::robust;
 use vors::misc::{helper, interop};
 
 fn main() {
@@ -31,12 +36,26 @@ fn my_run(args: &[String]) -> Result<(), Box<Error>> {
     let associations = parse_associations(valid_args.associations_file_path)?;
 
     // Setup tracking configuration.
+    let nb_levels = 6;
     let config = track::Config {
-        nb_levels: 6,
+        nb_levels,
         candidates_diff_threshold: 7,
         depth_scale: tum_rgbd::DEPTH_SCALE,
         intrinsics: valid_args.intrinsics,
-        idepth_variance: 0.0001,
+        depth_noise: valid_args.depth_noise,
+        idepth_fusion: inverse_depth::strategy_dso_mean,
+        robust_loss: Box::new(robust::Squared),
+        fix_affine_brightness: false,
+        keyframe_policy: Box::new(keyframe::OpticalFlow { threshold: 1.0 }),
+        motion_model: MotionModel::Static,
+        lm_configs: vec![LMConfig::default(); nb_levels],
+        depth_residual_weight: None,
+        refine_keyframe_idepth: false,
+        sliding_window: None,
+        inertial: None,
+        readout_time: None,
+        photometric: None,
+        intensity_variance: None,
     };
 
     // Initialize tracker with first depth and color image.
@@ -51,15 +70,22 @@ fn my_run(args: &[String]) -> Result<(), Box<Error>> {
         let (depth_map, img) = read_images(assoc)?;
 
         // Track the rgb-d image.
-        tracker.track(
+        let result = tracker.track(
             assoc.depth_timestamp,
             &depth_map,
             assoc.color_timestamp,
             img,
         );
+        if result.status != track::TrackingStatus::Ok {
+            eprintln!("Tracking failure: {:?}", result.status);
+        }
+        eprintln!("Optical_flow: {}", result.optical_flow);
+        if result.keyframe_created {
+            eprintln!("Changing keyframe at: {}", assoc.depth_timestamp);
+        }
 
         // Print to stdout the frame pose.
-        let (timestamp, pose) = tracker.current_frame();
+        let (timestamp, pose, _) = tracker.current_frame();
         println!("{}", (tum_rgbd::Frame { timestamp, pose }).to_string());
     }
 
@@ -69,6 +95,7 @@ fn my_run(args: &[String]) -> Result<(), Box<Error>> {
 struct Args {
     associations_file_path: PathBuf,
     intrinsics: Intrinsics,
+    depth_noise: Box<dyn DepthNoiseModel>,
 }
 
 /// Verify that command line arguments are correct.
@@ -76,10 +103,12 @@ fn check_args(args: &[String]) -> Result<Args, String> {
     // eprintln!("{:?}", args);
     if let [_, camera_id, associations_file_path_str] = args {
         let intrinsics = create_camera(camera_id)?;
+        let depth_noise = create_depth_noise(camera_id);
         let associations_file_path = PathBuf::from(associations_file_path_str);
         if associations_file_path.is_file() {
             Ok(Args {
                 intrinsics,
+                depth_noise,
                 associations_file_path,
             })
         } else {
@@ -109,6 +138,16 @@ fn create_camera(camera_id: &str) -> Result<Intrinsics, String> {
     }
 }
 
+/// Create the depth noise model depending on `camera_id` command line argument.
+/// TUM RGB-D sequences are recorded with a Kinect v1,
+/// and ICL-NUIM depth maps are synthetic.
+fn create_depth_noise(camera_id: &str) -> Box<dyn DepthNoiseModel> {
+    match camera_id {
+        "icl" => Box::new(depth_noise::ConstantIdepth(tum_rgbd::VARIANCE_ICL_NUIM)),
+        _ => Box::new(depth_noise::KINECT_V1),
+    }
+}
+
 /// Open an association file and parse it into a vector of Association.
 fn parse_associations<P: AsRef<Path>>(
     file_path: P,
@@ -0,0 +1,523 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Detection of the inner corners of a checkerboard in an image.
+//!
+//! Inner corners are saddle points of the image intensity,
+//! detected where the determinant of the hessian of the smoothed image is strongly negative.
+//! The strongest saddle points are then organized into a grid,
+//! grown from a seed corner and its four nearest neighbors,
+//! by predicting each next corner from the previous ones along the same grid line.
+//! Finally, each corner is refined with subpixel accuracy,
+//! as the point where the image gradients around it are orthogonal to the directions to it.
+
+use nalgebra::DMatrix;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use crate::core::gradient;
+use crate::misc::type_aliases::{Float, Mat2, Point2, Vec2};
+
+/// Number of smoothing passes before the computation of the hessian.
+const NB_SMOOTHING: usize = 2;
+
+/// Radius of the non-maximum suppression of the saddle response.
+const NMS_RADIUS: usize = 3;
+
+/// Radius of the circle checked around a saddle point for alternating squares.
+const RING_RADIUS: Float = 3.0;
+
+/// Number of samples on the circle checked around a saddle point.
+const RING_SAMPLES: usize = 16;
+
+/// Min saddle response, relative to the max response of the image.
+const MIN_RELATIVE_RESPONSE: Float = 0.05;
+
+/// Max distance between a predicted corner and a detected one,
+/// relative to the distance between neighbor corners.
+const MAX_PREDICTION_ERROR: Float = 0.35;
+
+/// Number of candidate seeds tried to grow the grid.
+const NB_SEEDS: usize = 10;
+
+/// Max number of iterations of the subpixel refinement.
+const REFINE_MAX_ITERATIONS: usize = 10;
+
+/// Min displacement (in pixels) to continue the subpixel refinement.
+const REFINE_MIN_STEP: Float = 0.01;
+
+/// Detect the inner corners of a checkerboard with `pattern_size = (nb_rows, nb_cols)`
+/// inner corners, and refine them with subpixel accuracy.
+///
+/// Corners are returned row by row, such that grid columns go along the image x axis
+/// and grid rows along the image y axis (as much as possible).
+/// Return `None` if the full pattern is not found.
+pub fn detect(img: &DMatrix<u8>, pattern_size: (usize, usize)) -> Option<Vec<Point2>> {
+    let candidates = saddle_points(img);
+    let (grid, spacing) = (0..NB_SEEDS.min(candidates.len()))
+        .filter_map(|seed| grow_grid(&candidates, seed, pattern_size))
+        .next()?;
+    let (grad_x, grad_y) = gradient::centered(img);
+    let radius = ((0.3 * spacing).round() as usize).max(2);
+    Some(
+        grid.iter()
+            .map(|corner| refine_corner(&grad_x, &grad_y, corner, radius))
+            .collect(),
+    )
+}
+
+/// Saddle points of the smoothed image, strongest first.
+#[allow(clippy::cast_precision_loss)]
+#[allow(clippy::similar_names)]
+pub fn saddle_points(img: &DMatrix<u8>) -> Vec<Point2> {
+    let (nb_rows, nb_cols) = img.shape();
+    let mut smooth = img.map(Float::from);
+    for _ in 0..NB_SMOOTHING {
+        smooth = smooth_121(&smooth);
+    }
+
+    // Saddle response: the opposite of the hessian determinant, when positive.
+    let mut response = DMatrix::zeros(nb_rows, nb_cols);
+    for x in 1..nb_cols.saturating_sub(1) {
+        for y in 1..nb_rows.saturating_sub(1) {
+            let center = smooth[(y, x)];
+            let ixx = smooth[(y, x + 1)] - 2.0 * center + smooth[(y, x - 1)];
+            let iyy = smooth[(y + 1, x)] - 2.0 * center + smooth[(y - 1, x)];
+            let ixy = 0.25
+                * (smooth[(y + 1, x + 1)] - smooth[(y - 1, x + 1)] - smooth[(y + 1, x - 1)]
+                    + smooth[(y - 1, x - 1)]);
+            response[(y, x)] = (ixy * ixy - ixx * iyy).max(0.0);
+        }
+    }
+
+    // Non-maximum suppression.
+    let threshold = MIN_RELATIVE_RESPONSE * response.max();
+    let mut points = Vec::new();
+    let margin = NMS_RADIUS.max(RING_RADIUS.ceil() as usize);
+    for x in margin..nb_cols.saturating_sub(margin) {
+        for y in margin..nb_rows.saturating_sub(margin) {
+            let r = response[(y, x)];
+            if r > threshold && is_local_max(&response, x, y) && is_x_junction(&smooth, x, y) {
+                points.push((r, Point2::new(x as Float, y as Float)));
+            }
+        }
+    }
+    points.sort_by(|(r1, _), (r2, _)| r2.partial_cmp(r1).unwrap());
+    points.into_iter().map(|(_, p)| p).collect()
+}
+
+/// Whether a response is the max of its neighborhood.
+/// Ties are broken by keeping the first pixel in column major order.
+fn is_local_max(response: &DMatrix<Float>, x: usize, y: usize) -> bool {
+    let r = response[(y, x)];
+    for nx in x - NMS_RADIUS..=x + NMS_RADIUS {
+        for ny in y - NMS_RADIUS..=y + NMS_RADIUS {
+            let neighbor = response[(ny, nx)];
+            if neighbor > r || (neighbor == r && (nx, ny) < (x, y)) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Whether the intensity on a circle around a pixel alternates
+/// between dark and bright exactly four times, as around the inner corners of a checkerboard.
+/// This discards the corners at the border of the board.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_precision_loss)]
+#[allow(clippy::cast_sign_loss)]
+fn is_x_junction(smooth: &DMatrix<Float>, x: usize, y: usize) -> bool {
+    let ring: Vec<Float> = (0..RING_SAMPLES)
+        .map(|k| {
+            let angle = 2.0 * PI * k as Float / RING_SAMPLES as Float;
+            let sx = (x as Float + RING_RADIUS * angle.cos()).round() as usize;
+            let sy = (y as Float + RING_RADIUS * angle.sin()).round() as usize;
+            smooth[(sy, sx)]
+        })
+        .collect();
+    let min = ring.iter().cloned().fold(Float::INFINITY, Float::min);
+    let max = ring.iter().cloned().fold(Float::NEG_INFINITY, Float::max);
+    let middle = 0.5 * (min + max);
+    let nb_changes = (0..RING_SAMPLES)
+        .filter(|&k| (ring[k] > middle) != (ring[(k + 1) % RING_SAMPLES] > middle))
+        .count();
+    nb_changes == 4
+}
+
+/// Separable [1 2 1] / 4 smoothing, borders are left unchanged.
+fn smooth_121(img: &DMatrix<Float>) -> DMatrix<Float> {
+    let (nb_rows, nb_cols) = img.shape();
+    let mut horizontal = img.clone();
+    for x in 1..nb_cols.saturating_sub(1) {
+        for y in 0..nb_rows {
+            horizontal[(y, x)] = 0.25 * (img[(y, x - 1)] + 2.0 * img[(y, x)] + img[(y, x + 1)]);
+        }
+    }
+    let mut smooth = horizontal.clone();
+    for x in 0..nb_cols {
+        for y in 1..nb_rows.saturating_sub(1) {
+            smooth[(y, x)] =
+                0.25 * (horizontal[(y - 1, x)] + 2.0 * horizontal[(y, x)] + horizontal[(y + 1, x)]);
+        }
+    }
+    smooth
+}
+
+/// Grow a grid of corners from a seed candidate.
+/// Return the corners ordered row by row, and the mean distance between neighbors,
+/// if the grid has exactly the expected size.
+#[allow(clippy::cast_possible_wrap)]
+#[allow(clippy::cast_sign_loss)]
+#[allow(clippy::cast_precision_loss)]
+fn grow_grid(
+    candidates: &[Point2],
+    seed: usize,
+    (nb_rows, nb_cols): (usize, usize),
+) -> Option<(Vec<Point2>, Float)> {
+    let (axis_i, axis_j) = seed_axes(candidates, seed)?;
+    let mut used = vec![false; candidates.len()];
+    let mut grid: HashMap<(i32, i32), usize> = HashMap::new();
+    grid.insert((0, 0), seed);
+    used[seed] = true;
+    let mut queue = vec![(0, 0)];
+    while let Some((i, j)) = queue.pop() {
+        for &(di, dj) in &[(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            let next = (i + di, j + dj);
+            if grid.contains_key(&next) {
+                continue;
+            }
+            let base_step = if di == 0 {
+                axis_j * dj as Float
+            } else {
+                axis_i * di as Float
+            };
+            let step = local_step(candidates, &grid, (i, j), (di, dj)).unwrap_or(base_step);
+            let predicted = candidates[grid[&(i, j)]] + step;
+            let max_distance = MAX_PREDICTION_ERROR * step.norm();
+            let nearest = candidates
+                .iter()
+                .enumerate()
+                .filter(|(k, _)| !used[*k])
+                .map(|(k, p)| (k, (p - predicted).norm()))
+                .filter(|(_, d)| *d < max_distance)
+                .min_by(|(_, d1), (_, d2)| d1.partial_cmp(d2).unwrap());
+            if let Some((k, _)) = nearest {
+                used[k] = true;
+                grid.insert(next, k);
+                queue.push(next);
+            }
+        }
+    }
+
+    // Check that the grid is exactly the expected pattern.
+    let min_i = grid.keys().map(|k| k.0).min()?;
+    let max_i = grid.keys().map(|k| k.0).max()?;
+    let min_j = grid.keys().map(|k| k.1).min()?;
+    let max_j = grid.keys().map(|k| k.1).max()?;
+    let size = ((max_i - min_i + 1) as usize, (max_j - min_j + 1) as usize);
+    if grid.len() != nb_rows * nb_cols || (size != (nb_cols, nb_rows) && size != (nb_rows, nb_cols))
+    {
+        return None;
+    }
+
+    // Orient the grid such that columns go along x and rows along y.
+    let i_is_x = if nb_rows == nb_cols {
+        axis_i.x.abs() >= axis_j.x.abs()
+    } else {
+        size.0 == nb_cols
+    };
+    let (axis_x, axis_y) = if i_is_x {
+        (axis_i, axis_j)
+    } else {
+        (axis_j, axis_i)
+    };
+    let flip_x = axis_x.x < 0.0;
+    let flip_y = axis_y.y < 0.0;
+    let mut corners = Vec::with_capacity(grid.len());
+    for row in 0..nb_rows as i32 {
+        for col in 0..nb_cols as i32 {
+            let x = if flip_x {
+                nb_cols as i32 - 1 - col
+            } else {
+                col
+            };
+            let y = if flip_y {
+                nb_rows as i32 - 1 - row
+            } else {
+                row
+            };
+            let key = if i_is_x {
+                (min_i + x, min_j + y)
+            } else {
+                (min_i + y, min_j + x)
+            };
+            corners.push(candidates[*grid.get(&key)?]);
+        }
+    }
+    let spacing = 0.5 * (axis_i.norm() + axis_j.norm());
+    Some((corners, spacing))
+}
+
+/// Two grid axes at a seed candidate, from its four nearest neighbors,
+/// which must form two pairs of opposite directions.
+fn seed_axes(candidates: &[Point2], seed: usize) -> Option<(Vec2, Vec2)> {
+    let center = candidates[seed];
+    let mut neighbors: Vec<Vec2> = candidates
+        .iter()
+        .enumerate()
+        .filter(|(k, _)| *k != seed)
+        .map(|(_, p)| p - center)
+        .collect();
+    if neighbors.len() < 4 {
+        return None;
+    }
+    neighbors.sort_by(|a, b| a.norm_squared().partial_cmp(&b.norm_squared()).unwrap());
+    let first = neighbors[0];
+    let opposite = (1..4).min_by(|&a, &b| {
+        let da = neighbors[a].dot(&first);
+        let db = neighbors[b].dot(&first);
+        da.partial_cmp(&db).unwrap()
+    })?;
+    let others: Vec<Vec2> = (1..4)
+        .filter(|&k| k != opposite)
+        .map(|k| neighbors[k])
+        .collect();
+    let pairs = [(first, neighbors[opposite]), (others[0], others[1])];
+    for (a, b) in pairs.iter() {
+        if (a + b).norm() > MAX_PREDICTION_ERROR * a.norm().max(b.norm()) {
+            return None;
+        }
+    }
+    let axis_i = 0.5 * (first - neighbors[opposite]);
+    let axis_j = 0.5 * (others[0] - others[1]);
+    Some((axis_i, axis_j))
+}
+
+/// Step from a grid node to its neighbor in direction `(di, dj)`,
+/// from already found corners of the same grid line or of a parallel one.
+fn local_step(
+    candidates: &[Point2],
+    grid: &HashMap<(i32, i32), usize>,
+    (i, j): (i32, i32),
+    (di, dj): (i32, i32),
+) -> Option<Vec2> {
+    let step_between = |a: (i32, i32), b: (i32, i32)| -> Option<Vec2> {
+        Some(candidates[*grid.get(&b)?] - candidates[*grid.get(&a)?])
+    };
+    step_between((i - di, j - dj), (i, j))
+        .or_else(|| step_between((i + dj, j + di), (i + dj + di, j + di + dj)))
+        .or_else(|| step_between((i - dj, j - di), (i - dj + di, j - di + dj)))
+}
+
+/// Subpixel refinement of a corner, with the gradients in a window around it.
+///
+/// For each pixel `p` of the window, the gradient `g` is orthogonal to `p - q`
+/// at the corner `q`, since `p` is either in a flat area or on an edge through `q`.
+/// The corner is thus the solution of `sum(w g g^T) q = sum(w g g^T p)`,
+/// iterated with windows centered on the new estimate.
+/// Weights `w = 1 / |g|` make pixels count proportionally to their gradient norm,
+/// which centers them on anti-aliased edges, where squared norms would bias
+/// the corner toward the pixels closest to the edges.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_possible_wrap)]
+#[allow(clippy::cast_sign_loss)]
+#[allow(clippy::cast_precision_loss)]
+pub fn refine_corner(
+    grad_x: &DMatrix<i16>,
+    grad_y: &DMatrix<i16>,
+    corner: &Point2,
+    radius: usize,
+) -> Point2 {
+    let (nb_rows, nb_cols) = grad_x.shape();
+    let radius = radius as i64;
+    let mut q = *corner;
+    for _ in 0..REFINE_MAX_ITERATIONS {
+        let (cx, cy) = (q.x.round() as i64, q.y.round() as i64);
+        let mut a = Mat2::zeros();
+        let mut b = Vec2::zeros();
+        for y in (cy - radius).max(1)..=(cy + radius).min(nb_rows as i64 - 2) {
+            for x in (cx - radius).max(1)..=(cx + radius).min(nb_cols as i64 - 2) {
+                let (ux, uy) = (x as usize, y as usize);
+                let g = Vec2::new(Float::from(grad_x[(uy, ux)]), Float::from(grad_y[(uy, ux)]));
+                let norm = g.norm();
+                if norm == 0.0 {
+                    continue;
+                }
+                let ggt = g * g.transpose() / norm;
+                a += ggt;
+                b += ggt * Vec2::new(x as Float, y as Float);
+            }
+        }
+        let new_q = match a.try_inverse() {
+            Some(a_inverse) => Point2::from(a_inverse * b),
+            None => return q,
+        };
+        if (new_q - corner).norm() > radius as Float {
+            return *corner;
+        }
+        let step = (new_q - q).norm();
+        q = new_q;
+        if step < REFINE_MIN_STEP {
+            break;
+        }
+    }
+    q
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    // Max distance (in pixels) between detected and true corners.
+    const MAX_CORNER_ERROR: Float = 0.1;
+
+    #[test]
+    fn detect_non_square_pattern() {
+        let (img, corners) = render_board((4, 6), 15.0, 0.0);
+        assert_corners(&detect(&img, (4, 6)), &corners);
+    }
+
+    #[test]
+    fn detect_rotated_boards() {
+        // Corners keep the image order, whatever the rotation of the board.
+        for &angle in [FRAC_PI_2, PI, -FRAC_PI_2].iter() {
+            let (img, corners) = render_board((5, 5), 15.0, angle);
+            assert_corners(&detect(&img, (5, 5)), &image_order(&corners));
+            // The pattern size is given as seen in the image.
+            let (img, corners) = render_board((4, 6), 15.0, angle);
+            let pattern_size = if angle == PI { (4, 6) } else { (6, 4) };
+            assert_corners(&detect(&img, pattern_size), &image_order(&corners));
+        }
+        // Slightly rotated board, with columns still along x.
+        let (img, corners) = render_board((4, 6), 15.0, 0.2);
+        assert_corners(&detect(&img, (4, 6)), &corners);
+    }
+
+    #[test]
+    fn partial_board_not_detected() {
+        let (img, _) = render_board((4, 6), 15.0, 0.0);
+        assert!(detect(&img, (5, 6)).is_none());
+        assert!(detect(&img, (4, 7)).is_none());
+        // Board cut by the right border of the image.
+        let cropped = img.columns(0, 100).into_owned();
+        assert!(detect(&cropped, (4, 6)).is_none());
+    }
+
+    #[test]
+    fn seed_axes_of_grid() {
+        let (candidates, _) = gen_candidates();
+        // Seed inside the grid.
+        let (axis_i, axis_j) = seed_axes(&candidates, 0).unwrap();
+        let axes = [axis_i.abs(), axis_j.abs()];
+        assert!(axes.contains(&Vec2::new(10.0, 0.0)) && axes.contains(&Vec2::new(0.0, 10.0)));
+        // Seeds on the border of the grid have no opposite neighbors.
+        assert!(seed_axes(&candidates, 1).is_none());
+        assert!(seed_axes(&candidates[..4], 0).is_none());
+    }
+
+    #[test]
+    fn grow_grid_orders_corners() {
+        let (candidates, grid) = gen_candidates();
+        let (corners, spacing) = grow_grid(&candidates, 0, (3, 4)).unwrap();
+        assert_eq!(corners, grid);
+        assert_eq!(spacing, 10.0);
+        assert!(grow_grid(&candidates, 0, (4, 4)).is_none());
+        assert!(grow_grid(&candidates, 0, (3, 5)).is_none());
+    }
+
+    #[test]
+    fn refine_corner_subpixel() {
+        let (img, corners) = render_board((4, 6), 15.0, 0.3);
+        let (grad_x, grad_y) = gradient::centered(&img);
+        for corner in corners.iter() {
+            let initial = corner + Vec2::new(1.4, -1.2);
+            let refined = refine_corner(&grad_x, &grad_y, &initial, 4);
+            assert!((refined - corner).norm() < MAX_CORNER_ERROR);
+        }
+    }
+
+    fn assert_corners(detected: &Option<Vec<Point2>>, expected: &[Point2]) {
+        let detected = detected.as_ref().expect("Checkerboard not detected");
+        assert_eq!(detected.len(), expected.len());
+        for (corner, expected) in detected.iter().zip(expected.iter()) {
+            assert!((corner - expected).norm() < MAX_CORNER_ERROR);
+        }
+    }
+
+    // GENERATORS ####################################################
+
+    /// Render an anti-aliased checkerboard with `(nb_rows, nb_cols)` inner corners,
+    /// centered at subpixel coordinates in a 160x200 image and rotated by `angle`.
+    /// Also return the true inner corners, row by row in the board frame.
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::cast_sign_loss)]
+    fn render_board(
+        (nb_rows, nb_cols): (usize, usize),
+        square: Float,
+        angle: Float,
+    ) -> (DMatrix<u8>, Vec<Point2>) {
+        let center = Vec2::new(99.3, 79.6);
+        let (cos, sin) = (angle.cos(), angle.sin());
+        let rotation = Mat2::new(cos, -sin, sin, cos);
+        let half_size = Vec2::new(nb_cols as Float - 1.0, nb_rows as Float - 1.0) * 0.5;
+        let to_image = |board: Vec2| Point2::from(center + rotation * (board - half_size) * square);
+        let is_dark = |x: Float, y: Float| {
+            let (dx, dy) = (x - center.x, y - center.y);
+            let u = ((cos * dx + sin * dy) / square + half_size.x).floor();
+            let v = ((cos * dy - sin * dx) / square + half_size.y).floor();
+            let inside = u >= -1.0 && u < nb_cols as Float && v >= -1.0 && v < nb_rows as Float;
+            inside && (u + v) as i32 % 2 == 0
+        };
+        // Supersampling of each pixel.
+        let nb_samples = 8;
+        let img = DMatrix::from_fn(160, 200, |y, x| {
+            let mut nb_dark = 0;
+            for sy in 0..nb_samples {
+                for sx in 0..nb_samples {
+                    let dx = (sx as Float + 0.5) / nb_samples as Float - 0.5;
+                    let dy = (sy as Float + 0.5) / nb_samples as Float - 0.5;
+                    if is_dark(x as Float + dx, y as Float + dy) {
+                        nb_dark += 1;
+                    }
+                }
+            }
+            let dark_ratio = nb_dark as Float / (nb_samples * nb_samples) as Float;
+            (220.0 - 190.0 * dark_ratio).round() as u8
+        });
+        let corners = (0..nb_rows)
+            .flat_map(|r| (0..nb_cols).map(move |c| (r, c)))
+            .map(|(r, c)| to_image(Vec2::new(c as Float, r as Float)))
+            .collect();
+        (img, corners)
+    }
+
+    /// Corners of an axis aligned grid, ordered row by row in the image.
+    #[allow(clippy::cast_possible_truncation)]
+    fn image_order(corners: &[Point2]) -> Vec<Point2> {
+        let mut ordered = corners.to_vec();
+        ordered.sort_by_key(|p| (p.y.round() as i32, p.x.round() as i32));
+        ordered
+    }
+
+    /// Shuffled corners of a 3x4 grid with a spacing of 10 pixels, and a few outliers,
+    /// such that the first candidate is inside the grid and the second on its border.
+    /// Also return the grid corners row by row.
+    #[allow(clippy::cast_precision_loss)]
+    fn gen_candidates() -> (Vec<Point2>, Vec<Point2>) {
+        let grid: Vec<Point2> = (0..3)
+            .flat_map(|r| {
+                (0..4).map(move |c| Point2::new(50.0 + 10.0 * c as Float, 30.0 + 10.0 * r as Float))
+            })
+            .collect();
+        let order = [5, 0, 11, 3, 8, 1, 10, 6, 2, 9, 4, 7];
+        let mut candidates: Vec<Point2> = order.iter().map(|&k| grid[k]).collect();
+        candidates.insert(4, Point2::new(12.0, 80.0));
+        candidates.push(Point2::new(95.0, 5.0));
+        (candidates, grid)
+    }
+}
@@ -0,0 +1,198 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Calibration of a radial-tangential camera with images of a checkerboard.
+//!
+//! Inner corners of the checkerboard are detected in each image,
+//! then the pinhole intrinsics and board poses are initialized with Zhang's method,
+//! and finally the intrinsics, distortion and poses are jointly refined
+//! by minimizing the reprojection errors of the corners.
+
+pub mod checkerboard;
+pub mod optimizer;
+pub mod zhang;
+
+use nalgebra::DMatrix;
+
+use crate::core::calibration::optimizer::{CalibrationOptimizerState, Model, Obs};
+use crate::core::camera::RadialTangential;
+use crate::core::track::lm_optimizer::LMConfig;
+use crate::math::optimizer::State as _;
+use crate::misc::type_aliases::{Float, Iso3, Point2, Point3};
+
+/// Default parameters of the Levenberg-Marquardt optimizer of the calibration.
+pub const DEFAULT_LM_CONFIG: LMConfig = LMConfig {
+    initial_coef: 1e-3,
+    coef_increase_factor: 10.0,
+    coef_decrease_factor: 0.1,
+    max_iterations: 100,
+    min_energy_decrease: 0.0,
+    min_relative_energy_decrease: 1e-7,
+    min_step_norm: 1e-7,
+};
+
+/// Configuration of the calibration.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Config {
+    /// Number of inner corners `(nb_rows, nb_cols)` of the checkerboard.
+    pub pattern_size: (usize, usize),
+    /// Side length of the checkerboard squares, in the unit of the poses translations.
+    pub square_size: Float,
+    /// Keep the third radial coefficient `k3` null,
+    /// usually recommended for lenses with a moderate distortion.
+    pub fix_k3: bool,
+    /// Parameters of the Levenberg-Marquardt optimizer.
+    pub lm_config: LMConfig,
+}
+
+/// Result of a calibration.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Calibration {
+    /// Calibrated camera.
+    pub camera: RadialTangential,
+    /// Pose of the board in the camera frame, for each image.
+    pub poses: Vec<Iso3>,
+    /// Root mean square reprojection error (in pixels) of each image.
+    pub reprojection_errors: Vec<Float>,
+    /// Root mean square reprojection error (in pixels) of all images.
+    pub rms_error: Float,
+    /// Number of Levenberg-Marquardt iterations.
+    pub nb_iterations: usize,
+}
+
+impl Config {
+    /// Inner corners of the checkerboard in its own frame, on the plane `z = 0`,
+    /// in the order of the corners detected by `checkerboard::detect`.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn board_points(&self) -> Vec<Point3> {
+        let (nb_rows, nb_cols) = self.pattern_size;
+        let mut points = Vec::with_capacity(nb_rows * nb_cols);
+        for row in 0..nb_rows {
+            for col in 0..nb_cols {
+                let x = col as Float * self.square_size;
+                let y = row as Float * self.square_size;
+                points.push(Point3::new(x, y, 0.0));
+            }
+        }
+        points
+    }
+
+    /// Detect the checkerboard corners in an image.
+    /// Return `None` if the full pattern is not found.
+    pub fn detect(&self, img: &DMatrix<u8>) -> Option<Vec<Point2>> {
+        checkerboard::detect(img, self.pattern_size)
+    }
+
+    /// Calibrate a camera with the corners detected in at least three images.
+    pub fn calibrate(&self, corners: &[Vec<Point2>]) -> Result<Calibration, String> {
+        if corners.len() < 3 {
+            return Err("At least three images are required for the calibration".to_string());
+        }
+        let board = self.board_points();
+        if corners.iter().any(|c| c.len() != board.len()) {
+            return Err("Each image must have all corners of the pattern".to_string());
+        }
+        let (intrinsics, poses) =
+            zhang::init(&board, corners).ok_or("Degenerate initialization of the intrinsics")?;
+        let model = Model {
+            camera: RadialTangential {
+                pinhole: intrinsics,
+                radial: (0.0, 0.0, 0.0),
+                tangential: (0.0, 0.0),
+            },
+            poses,
+        };
+        let obs = Obs {
+            board: &board,
+            corners,
+            fix_k3: self.fix_k3,
+            lm_config: self.lm_config,
+        };
+        let (state, nb_iterations) = CalibrationOptimizerState::iterative_solve(&obs, model)?;
+        let Model { camera, poses } = state.eval_data.model;
+        let reprojection_errors = poses
+            .iter()
+            .zip(corners.iter())
+            .map(|(pose, img_corners)| {
+                rms(&optimizer::reprojection_errors(
+                    &camera,
+                    pose,
+                    &board,
+                    img_corners,
+                ))
+            })
+            .collect::<Vec<_>>();
+        let rms_error = rms(&reprojection_errors);
+        Ok(Calibration {
+            camera,
+            poses,
+            reprojection_errors,
+            rms_error,
+            nb_iterations,
+        })
+    }
+}
+
+/// Root mean square of errors.
+#[allow(clippy::cast_precision_loss)]
+fn rms(errors: &[Float]) -> Float {
+    let sum: Float = errors.iter().map(|e| e * e).sum();
+    (sum / errors.len() as Float).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::core::camera::{CameraModel, Intrinsics};
+    use crate::math::so3;
+    use crate::misc::type_aliases::Vec3;
+    use nalgebra::Translation3;
+
+    #[test]
+    #[allow(clippy::cast_precision_loss)]
+    fn calibrate_with_exact_corners() {
+        let camera = RadialTangential {
+            pinhole: Intrinsics {
+                principal_point: (322.0, 236.0),
+                focal: (505.0, 500.0),
+                skew: 0.0,
+            },
+            radial: (-0.25, 0.08, 0.0),
+            tangential: (0.001, -0.0007),
+        };
+        let config = Config {
+            pattern_size: (6, 9),
+            square_size: 0.03,
+            fix_k3: true,
+            lm_config: DEFAULT_LM_CONFIG,
+        };
+        let board = config.board_points();
+        let center = Vec3::new(0.12, 0.075, 0.0);
+        let corners: Vec<Vec<Point2>> = [
+            (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.45)),
+            (Vec3::new(0.4, 0.0, 0.1), Vec3::new(0.08, 0.05, 0.4)),
+            (Vec3::new(0.0, 0.4, -0.1), Vec3::new(-0.1, 0.06, 0.5)),
+            (Vec3::new(-0.3, 0.3, 0.3), Vec3::new(0.1, -0.08, 0.45)),
+            (Vec3::new(0.3, -0.3, 1.4), Vec3::new(-0.05, -0.05, 0.35)),
+        ]
+        .iter()
+        .map(|(w, t)| {
+            let rotation = so3::exp(*w);
+            let pose = Iso3::from_parts(Translation3::from(t - rotation * center), rotation);
+            board
+                .iter()
+                .map(|p| camera.project(&(pose * p)).unwrap())
+                .collect()
+        })
+        .collect();
+        let calibration = config.calibrate(&corners).unwrap();
+        let (fu, fv) = calibration.camera.pinhole.focal;
+        let (cu, cv) = calibration.camera.pinhole.principal_point;
+        assert!(calibration.rms_error < 1e-2);
+        assert!((fu - 505.0).abs() < 0.5 && (fv - 500.0).abs() < 0.5);
+        assert!((cu - 322.0).abs() < 0.5 && (cv - 236.0).abs() < 0.5);
+        assert!((calibration.camera.radial.0 + 0.25).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,401 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Levenberg-Marquardt implementation of the `optimizer::State` trait
+//! for the joint refinement of a radial-tangential camera and the board poses.
+//!
+//! Residuals are the differences between the projections of the board points
+//! and the detected corners, in pixels.
+//! Camera parameters are ordered as `[fu, fv, cu, cv, k1, k2, p1, p2, k3]`,
+//! followed by the twist of each board pose, updated as `exp(-delta) * pose`.
+//! The skew of the camera is kept null.
+
+use nalgebra::{DMatrix, DVector, Matrix2x6, MatrixMN, VectorN, U2, U3, U6, U9};
+
+use crate::core::camera::{CameraModel, Intrinsics, RadialTangential};
+use crate::core::track::lm_optimizer::{self, LMConfig};
+use crate::math::optimizer::{self, Continue};
+use crate::math::{se3, so3};
+use crate::misc::type_aliases::{Float, Iso3, Point2, Point3, Vec2, Vec6};
+
+/// Number of camera parameters.
+const NB_CAMERA_PARAMS: usize = 9;
+
+/// Index of `k3` in the parameters.
+const K3_INDEX: usize = 8;
+
+/// Camera parameters vector.
+type CameraParams = VectorN<Float, U9>;
+
+/// Jacobian of a projection with respect to the camera parameters.
+type CameraJacobian = MatrixMN<Float, U2, U9>;
+
+/// Camera and board poses (in the camera frame) of each image.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Model {
+    /// Radial-tangential camera.
+    pub camera: RadialTangential,
+    /// Pose of the board in each image.
+    pub poses: Vec<Iso3>,
+}
+
+/// State of the Levenberg-Marquardt optimizer.
+pub struct CalibrationOptimizerState {
+    /// Parameters of the optimizer.
+    pub config: LMConfig,
+    /// Levenberg-Marquardt hessian diagonal coefficient.
+    pub lm_coef: Float,
+    /// Data resulting of a successful model evaluation.
+    pub eval_data: EvalData,
+}
+
+/// Either a successfully constructed `EvalData`
+/// or an error containing the energy of a given model.
+///
+/// The error is returned when the new computed energy
+/// is higher than the previous iteration energy.
+pub type EvalState = Result<EvalData, Float>;
+
+/// Data resulting of a successful model evaluation.
+pub struct EvalData {
+    /// The hessian matrix of the camera parameters and poses.
+    pub hessian: DMatrix<Float>,
+    /// The gradient of the camera parameters and poses.
+    pub gradient: DVector<Float>,
+    /// Energy associated with the current model.
+    pub energy: Float,
+    /// Camera and poses at the current state of iterations.
+    pub model: Model,
+}
+
+/// Precomputed data available for the optimizer iterations:
+pub struct Obs<'a> {
+    /// Points of the board, in the board frame.
+    pub board: &'a [Point3],
+    /// Detected corners in each image, in the order of the board points.
+    pub corners: &'a [Vec<Point2>],
+    /// Whether `k3` is fixed to its initial value.
+    pub fix_k3: bool,
+    /// Parameters of the optimizer.
+    pub lm_config: LMConfig,
+}
+
+impl CalibrationOptimizerState {
+    /// Compute the energy of a model.
+    /// It is infinite if a board point cannot be projected.
+    fn eval_energy(obs: &Obs, model: &Model) -> Float {
+        let mut energy = 0.0;
+        for (pose, img_corners) in model.poses.iter().zip(obs.corners.iter()) {
+            for (point, corner) in obs.board.iter().zip(img_corners.iter()) {
+                match model.camera.project(&(pose * point)) {
+                    Some(pixel) => energy += (pixel - corner).norm_squared(),
+                    None => return Float::INFINITY,
+                }
+            }
+        }
+        energy
+    }
+
+    /// Fully evaluate a model.
+    fn compute_eval_data(obs: &Obs, model: Model, energy: Float) -> EvalData {
+        let nb_params = NB_CAMERA_PARAMS + 6 * model.poses.len();
+        let mut hessian = DMatrix::zeros(nb_params, nb_params);
+        let mut gradient = DVector::zeros(nb_params);
+        for (i, (pose, img_corners)) in model.poses.iter().zip(obs.corners.iter()).enumerate() {
+            let pose_index = NB_CAMERA_PARAMS + 6 * i;
+            for (point, corner) in obs.board.iter().zip(img_corners.iter()) {
+                let x = pose * point;
+                if let Some(pixel) = model.camera.project(&x) {
+                    let residual = pixel - corner;
+                    let jac_camera = camera_jacobian(&model.camera, &x);
+                    let jac_pose = pose_jacobian(&model.camera, &x);
+
+                    // Accumulate the camera and pose blocks.
+                    let h_cc = jac_camera.transpose() * jac_camera;
+                    let h_cp = jac_camera.transpose() * jac_pose;
+                    let h_pp = jac_pose.transpose() * jac_pose;
+                    let mut slice = hessian.fixed_slice_mut::<U9, U9>(0, 0);
+                    slice += h_cc;
+                    let mut slice = hessian.fixed_slice_mut::<U9, U6>(0, pose_index);
+                    slice += h_cp;
+                    let mut slice = hessian.fixed_slice_mut::<U6, U9>(pose_index, 0);
+                    slice += h_cp.transpose();
+                    let mut slice = hessian.fixed_slice_mut::<U6, U6>(pose_index, pose_index);
+                    slice += h_pp;
+                    let mut slice = gradient.fixed_rows_mut::<U9>(0);
+                    slice += jac_camera.transpose() * residual;
+                    let mut slice = gradient.fixed_rows_mut::<U6>(pose_index);
+                    slice += jac_pose.transpose() * residual;
+                }
+            }
+        }
+
+        // A fixed k3 has a null step.
+        if obs.fix_k3 {
+            hessian.row_mut(K3_INDEX).fill(0.0);
+            hessian.column_mut(K3_INDEX).fill(0.0);
+            hessian[(K3_INDEX, K3_INDEX)] = 1.0;
+            gradient[K3_INDEX] = 0.0;
+        }
+        EvalData {
+            hessian,
+            gradient,
+            energy,
+            model,
+        }
+    }
+}
+
+/// `impl<'a> optimizer::State<Obs<'a>, EvalState, Model, String> for CalibrationOptimizerState`.
+impl<'a> optimizer::State<Obs<'a>, EvalState, Model, String> for CalibrationOptimizerState {
+    /// Initialize the optimizer state.
+    fn init(obs: &Obs, model: Model) -> Self {
+        let energy = Self::eval_energy(obs, &model);
+        Self {
+            config: obs.lm_config,
+            lm_coef: obs.lm_config.initial_coef,
+            eval_data: Self::compute_eval_data(obs, model, energy),
+        }
+    }
+
+    /// Compute the step using Levenberg-Marquardt.
+    /// Camera parameters are updated as `params - delta`
+    /// and poses as `exp(-delta) * pose`.
+    /// May return an error at the Cholesky decomposition of the hessian.
+    fn step(&self) -> Result<Model, String> {
+        let mut hessian = self.eval_data.hessian.clone();
+        for k in 0..hessian.nrows() {
+            hessian[(k, k)] *= 1.0 + self.lm_coef;
+        }
+        let cholesky = hessian
+            .cholesky()
+            .ok_or("Error at Cholesky decomposition of hessian")?;
+        let delta = cholesky.solve(&self.eval_data.gradient);
+        let model = &self.eval_data.model;
+        let params = to_params(&model.camera) - delta.fixed_rows::<U9>(0);
+        let poses = model
+            .poses
+            .iter()
+            .enumerate()
+            .map(|(i, pose)| {
+                let delta_pose: Vec6 = delta
+                    .fixed_rows::<U6>(NB_CAMERA_PARAMS + 6 * i)
+                    .into_owned();
+                lm_optimizer::renormalize(se3::exp(-delta_pose) * pose)
+            })
+            .collect();
+        Ok(Model {
+            camera: from_params(&params),
+            poses,
+        })
+    }
+
+    /// Compute the energy of the new model.
+    /// Then, evaluate the new hessian and gradient if the energy has decreased.
+    fn eval(&self, obs: &Obs, model: Model) -> EvalState {
+        let energy = Self::eval_energy(obs, &model);
+        let old_energy = self.eval_data.energy;
+        if energy > old_energy || !energy.is_finite() {
+            Err(energy)
+        } else {
+            Ok(Self::compute_eval_data(obs, model, energy))
+        }
+    }
+
+    /// Stop after too many iterations,
+    /// or if the energy variation or the step is too low.
+    ///
+    /// Also update the Levenberg-Marquardt coefficient
+    /// depending on if the energy increased or decreased.
+    fn stop_criterion(self, nb_iter: usize, eval_state: EvalState) -> (Self, Continue) {
+        match eval_state {
+            Err(_) => {
+                let (lm_coef, continuation) = self.config.stop(nb_iter, self.lm_coef, None);
+                (Self { lm_coef, ..self }, continuation)
+            }
+            Ok(eval_data) => {
+                let old_energy = self.eval_data.energy;
+                let step_norm = step_norm(&self.eval_data.model, &eval_data.model);
+                let accepted = Some((old_energy, eval_data.energy, step_norm));
+                let (lm_coef, continuation) = self.config.stop(nb_iter, self.lm_coef, accepted);
+                let kept_state = Self {
+                    lm_coef,
+                    eval_data,
+                    ..self
+                };
+                (kept_state, continuation)
+            }
+        }
+    } // fn stop_criterion
+} // impl optimizer::State<...> for CalibrationOptimizerState
+
+/// Norm of a step, the max of the poses twists norms
+/// and of the camera parameters change, with pixel parameters relative to the focal.
+fn step_norm(old: &Model, new: &Model) -> Float {
+    let mut d_params = to_params(&new.camera) - to_params(&old.camera);
+    let focal = old.camera.pinhole.focal.0;
+    for k in 0..4 {
+        d_params[k] /= focal;
+    }
+    old.poses
+        .iter()
+        .zip(new.poses.iter())
+        .map(|(old, new)| se3::log(old.inverse() * new).norm())
+        .fold(d_params.norm(), Float::max)
+}
+
+/// Jacobian of the projection of a point (in the camera frame)
+/// with respect to the camera parameters.
+#[allow(clippy::many_single_char_names)]
+fn camera_jacobian(camera: &RadialTangential, point: &Point3) -> CameraJacobian {
+    let (x, y) = (point.x / point.z, point.y / point.z);
+    let (distorted, _) = camera.distort(&Vec2::new(x, y));
+    let (fu, fv) = camera.pinhole.focal;
+    let r2 = x * x + y * y;
+    let (r4, r6) = (r2 * r2, r2 * r2 * r2);
+    #[rustfmt::skip]
+    let jacobian = CameraJacobian::from_row_slice(&[
+        distorted.x, 0.0, 1.0, 0.0, fu * x * r2, fu * x * r4, fu * 2.0 * x * y, fu * (r2 + 2.0 * x * x), fu * x * r6,
+        0.0, distorted.y, 0.0, 1.0, fv * y * r2, fv * y * r4, fv * (r2 + 2.0 * y * y), fv * 2.0 * x * y, fv * y * r6,
+    ]);
+    jacobian
+}
+
+/// Jacobian of the projection of a point (in the camera frame)
+/// with respect to a left perturbation `exp(delta) * pose` of its board pose.
+fn pose_jacobian(camera: &RadialTangential, point: &Point3) -> Matrix2x6<Float> {
+    let jac_point = camera.project_jacobian(point);
+    let mut jac_pose = Matrix2x6::zeros();
+    jac_pose.fixed_columns_mut::<U3>(0).copy_from(&jac_point);
+    jac_pose
+        .fixed_columns_mut::<U3>(3)
+        .copy_from(&(-jac_point * so3::hat(point.coords)));
+    jac_pose
+}
+
+/// Parameters vector of a camera.
+fn to_params(camera: &RadialTangential) -> CameraParams {
+    let (k1, k2, k3) = camera.radial;
+    let (p1, p2) = camera.tangential;
+    let (fu, fv) = camera.pinhole.focal;
+    let (cu, cv) = camera.pinhole.principal_point;
+    CameraParams::from_column_slice(&[fu, fv, cu, cv, k1, k2, p1, p2, k3])
+}
+
+/// Camera of a parameters vector.
+fn from_params(params: &CameraParams) -> RadialTangential {
+    RadialTangential {
+        pinhole: Intrinsics {
+            principal_point: (params[2], params[3]),
+            focal: (params[0], params[1]),
+            skew: 0.0,
+        },
+        radial: (params[4], params[5], params[8]),
+        tangential: (params[6], params[7]),
+    }
+}
+
+/// Reprojection errors (in pixels) of the board points in one image.
+/// Points that cannot be projected have an infinite error.
+pub fn reprojection_errors(
+    camera: &RadialTangential,
+    pose: &Iso3,
+    board: &[Point3],
+    corners: &[Point2],
+) -> Vec<Float> {
+    board
+        .iter()
+        .zip(corners.iter())
+        .map(|(point, corner)| {
+            camera
+                .project(&(pose * point))
+                .map_or(Float::INFINITY, |pixel| (pixel - corner).norm())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::misc::helper::bounded;
+    use approx;
+    use quickcheck_macros;
+
+    // Precision of centered finite differences with f32 computations.
+    // Projections are hundreds of pixels, so the absolute precision is lower.
+    const EPSILON_FINITE_DIFF: Float = 1e-1;
+    const EPSILON_FINITE_DIFF_RELATIVE: Float = 1e-2;
+
+    // PROPERTY TESTS ################################################
+
+    #[quickcheck_macros::quickcheck]
+    fn camera_jacobian_finite_differences(x: Float, y: Float) -> bool {
+        let point = gen_point(x, y);
+        let camera = gen_camera();
+        let params = to_params(&camera);
+        // Projections are linear in each parameter, so large steps are exact
+        // and limit the f32 cancellation errors.
+        let steps = [1.0, 1.0, 1.0, 1.0, 1e-1, 1e-1, 1e-1, 1e-1, 1e-1];
+        let mut numeric = CameraJacobian::zeros();
+        for (k, &h) in steps.iter().enumerate() {
+            let mut delta = CameraParams::zeros();
+            delta[k] = h;
+            let plus = from_params(&(params + delta)).project(&point);
+            let minus = from_params(&(params - delta)).project(&point);
+            match (plus, minus) {
+                (Some(plus), Some(minus)) => numeric.set_column(k, &((plus - minus) / (2.0 * h))),
+                _ => return false,
+            }
+        }
+        approx::relative_eq!(
+            camera_jacobian(&camera, &point),
+            numeric,
+            epsilon = EPSILON_FINITE_DIFF,
+            max_relative = EPSILON_FINITE_DIFF_RELATIVE
+        )
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn pose_jacobian_finite_differences(x: Float, y: Float) -> bool {
+        let point = gen_point(x, y);
+        let camera = gen_camera();
+        let h = 1e-3;
+        let mut numeric = Matrix2x6::zeros();
+        for k in 0..6 {
+            let mut delta = Vec6::zeros();
+            delta[k] = h;
+            let plus = camera.project(&(se3::exp(delta) * point));
+            let minus = camera.project(&(se3::exp(-delta) * point));
+            match (plus, minus) {
+                (Some(plus), Some(minus)) => numeric.set_column(k, &((plus - minus) / (2.0 * h))),
+                _ => return false,
+            }
+        }
+        approx::relative_eq!(
+            pose_jacobian(&camera, &point),
+            numeric,
+            epsilon = EPSILON_FINITE_DIFF,
+            max_relative = EPSILON_FINITE_DIFF_RELATIVE
+        )
+    }
+
+    // GENERATORS ####################################################
+
+    fn gen_point(x: Float, y: Float) -> Point3 {
+        Point3::new(0.2 * bounded(x), 0.15 * bounded(y), 0.5)
+    }
+
+    fn gen_camera() -> RadialTangential {
+        RadialTangential {
+            pinhole: Intrinsics {
+                principal_point: (322.0, 236.0),
+                focal: (505.0, 500.0),
+                skew: 0.0,
+            },
+            radial: (-0.25, 0.08, 0.01),
+            tangential: (0.001, -0.0007),
+        }
+    }
+}
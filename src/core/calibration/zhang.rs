@@ -0,0 +1,260 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Closed-form initialization of the intrinsics and board poses with Zhang's method.
+//!
+//! Each image gives a homography from the board plane to the image,
+//! estimated with a normalized direct linear transformation.
+//! Since the first two columns of `K^-1 H` are orthonormal up to scale,
+//! each homography gives two linear constraints on the symmetric matrix `B = K^-T K^-1`,
+//! from which `K` is recovered, assuming a null skew.
+//! Pixel coordinates are first normalized with the mean and spread of all corners,
+//! for a better conditioning of the problem in single precision.
+//!
+//! Interesting read: Zhang, A flexible new technique for camera calibration, PAMI 2000.
+
+use nalgebra::{DMatrix, DVector, Rotation3, Translation3, UnitQuaternion};
+
+use crate::core::camera::Intrinsics;
+use crate::misc::type_aliases::{Float, Iso3, Mat3, Mat6, Mat9, Point2, Point3, Vec2, Vec3, Vec6};
+
+/// Initial intrinsics and board poses (in the camera frame) of each image,
+/// from the board points (on the plane `z = 0`) and the detected corners in each image.
+/// Return `None` if there are less than two images or if the estimation is degenerate.
+#[allow(clippy::cast_precision_loss)]
+#[allow(clippy::many_single_char_names)]
+pub fn init(board: &[Point3], corners: &[Vec<Point2>]) -> Option<(Intrinsics, Vec<Iso3>)> {
+    if corners.len() < 2 {
+        return None;
+    }
+    let all_corners: Vec<Point2> = corners.iter().flatten().cloned().collect();
+    let normalization = similarity(&all_corners)?;
+    let board_normalization = similarity(
+        &board
+            .iter()
+            .map(|p| Point2::new(p.x, p.y))
+            .collect::<Vec<_>>(),
+    )?;
+    let homographies: Vec<Mat3> = corners
+        .iter()
+        .map(|img_corners| homography(board, img_corners, &board_normalization, &normalization))
+        .collect::<Option<_>>()?;
+
+    // Two constraints per homography, plus a null skew.
+    let mut vtv = Mat6::zeros();
+    for h in homographies.iter() {
+        let v12 = constraint(h, 0, 1);
+        let v11_22 = constraint(h, 0, 0) - constraint(h, 1, 1);
+        vtv += v12 * v12.transpose() + v11_22 * v11_22.transpose();
+    }
+    vtv[(1, 1)] += 1.0;
+    let b = smallest_eigenvector(DMatrix::from_iterator(6, 6, vtv.iter().cloned()));
+    let b = if b[0] < 0.0 { -b } else { b };
+    let (b11, b12, b22, b13, b23, b33) = (b[0], b[1], b[2], b[3], b[4], b[5]);
+
+    // Closed-form intrinsics, in normalized pixel coordinates.
+    let det = b11 * b22 - b12 * b12;
+    if det <= 0.0 {
+        return None;
+    }
+    let v0 = (b12 * b13 - b11 * b23) / det;
+    let lambda = b33 - (b13 * b13 + v0 * (b12 * b13 - b11 * b23)) / b11;
+    if lambda / b11 <= 0.0 {
+        return None;
+    }
+    let alpha = (lambda / b11).sqrt();
+    let beta = (lambda * b11 / det).sqrt();
+    let u0 = -b13 * alpha * alpha / lambda;
+    #[rustfmt::skip]
+    let k_normalized = Mat3::new(
+        alpha, 0.0, u0,
+        0.0, beta, v0,
+        0.0, 0.0, 1.0,
+    );
+    let k = normalization.try_inverse()? * k_normalized;
+    let intrinsics = Intrinsics {
+        principal_point: (k[(0, 2)], k[(1, 2)]),
+        focal: (k[(0, 0)], k[(1, 1)]),
+        skew: 0.0,
+    };
+
+    // Pose of the board in each image.
+    let k_normalized_inverse = k_normalized.try_inverse()?;
+    let poses = homographies
+        .iter()
+        .map(|h| pose(&(k_normalized_inverse * h)))
+        .collect::<Option<_>>()?;
+    Some((intrinsics, poses))
+}
+
+/// Similarity normalizing points to a zero mean and a mean distance of `sqrt(2)` to the origin.
+#[allow(clippy::cast_precision_loss)]
+fn similarity(points: &[Point2]) -> Option<Mat3> {
+    let nb_points = points.len() as Float;
+    let mean = points.iter().map(|p| p.coords).sum::<Vec2>() / nb_points;
+    let spread = points
+        .iter()
+        .map(|p| (p.coords - mean).norm())
+        .sum::<Float>()
+        / nb_points;
+    if spread <= Float::EPSILON {
+        return None;
+    }
+    let s = std::f32::consts::SQRT_2 / spread;
+    #[rustfmt::skip]
+    let matrix = Mat3::new(
+        s, 0.0, -s * mean.x,
+        0.0, s, -s * mean.y,
+        0.0, 0.0, 1.0,
+    );
+    Some(matrix)
+}
+
+/// Homography from the board plane to normalized pixel coordinates,
+/// with the direct linear transformation of normalized points.
+fn homography(
+    board: &[Point3],
+    corners: &[Point2],
+    board_normalization: &Mat3,
+    normalization: &Mat3,
+) -> Option<Mat3> {
+    let mut ata = Mat9::zeros();
+    for (p, c) in board.iter().zip(corners.iter()) {
+        let src = board_normalization * Vec3::new(p.x, p.y, 1.0);
+        let dst = normalization * Vec3::new(c.x, c.y, 1.0);
+        let (x, y, u, v) = (src.x, src.y, dst.x, dst.y);
+        let row_u = [x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y, -u];
+        let row_v = [0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y, -v];
+        for i in 0..9 {
+            for j in 0..9 {
+                ata[(i, j)] += row_u[i] * row_u[j] + row_v[i] * row_v[j];
+            }
+        }
+    }
+    let h = smallest_eigenvector(DMatrix::from_iterator(9, 9, ata.iter().cloned()));
+    let h_normalized = Mat3::from_row_slice(h.as_slice());
+    Some(h_normalized * board_normalization)
+}
+
+/// Smallest eigenvector of a symmetric matrix.
+fn smallest_eigenvector(matrix: DMatrix<Float>) -> DVector<Float> {
+    let eigen = matrix.symmetric_eigen();
+    let (min_index, _) = eigen
+        .eigenvalues
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+        .unwrap();
+    eigen.eigenvectors.column(min_index).into_owned()
+}
+
+/// Constraint vector `v_ij` of Zhang's method, such that `h_i^T B h_j = v_ij^T b`.
+fn constraint(h: &Mat3, i: usize, j: usize) -> Vec6 {
+    let (hi, hj) = (h.column(i), h.column(j));
+    Vec6::from_column_slice(&[
+        hi[0] * hj[0],
+        hi[0] * hj[1] + hi[1] * hj[0],
+        hi[1] * hj[1],
+        hi[2] * hj[0] + hi[0] * hj[2],
+        hi[2] * hj[1] + hi[1] * hj[2],
+        hi[2] * hj[2],
+    ])
+}
+
+/// Pose of the board from `K^-1 H`, the closest rotation to `[r1, r2, r1 x r2]`.
+/// The board is in front of the camera.
+fn pose(m: &Mat3) -> Option<Iso3> {
+    let scale = 1.0 / m.column(0).norm();
+    let sign = if m[(2, 2)] < 0.0 { -scale } else { scale };
+    let r1: Vec3 = m.column(0) * sign;
+    let r2: Vec3 = m.column(1) * sign;
+    let t: Vec3 = m.column(2) * sign;
+    let approx = Mat3::from_columns(&[r1, r2, r1.cross(&r2)]);
+    let svd = approx.svd(true, true);
+    let rotation = svd.u? * svd.v_t?;
+    if rotation.determinant() <= 0.0 {
+        return None;
+    }
+    let rotation =
+        UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(rotation));
+    Some(Iso3::from_parts(Translation3::from(t), rotation))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::core::camera::CameraModel;
+    use crate::math::so3;
+    use approx;
+
+    #[test]
+    fn init_with_exact_homographies() {
+        let camera = Intrinsics {
+            principal_point: (322.0, 236.0),
+            focal: (505.0, 500.0),
+            skew: 0.0,
+        };
+        let (board, poses) = gen_board_and_poses();
+        let corners: Vec<Vec<Point2>> = poses
+            .iter()
+            .map(|pose| {
+                board
+                    .iter()
+                    .map(|p| CameraModel::project(&camera, &(pose * p)).unwrap())
+                    .collect()
+            })
+            .collect();
+        let (intrinsics, init_poses) = init(&board, &corners).unwrap();
+        assert!(approx::relative_eq!(
+            intrinsics.focal.0,
+            camera.focal.0,
+            max_relative = 1e-2
+        ));
+        assert!(approx::relative_eq!(
+            intrinsics.focal.1,
+            camera.focal.1,
+            max_relative = 1e-2
+        ));
+        assert!((intrinsics.principal_point.0 - camera.principal_point.0).abs() < 2.0);
+        assert!((intrinsics.principal_point.1 - camera.principal_point.1).abs() < 2.0);
+        for (pose, init_pose) in poses.iter().zip(init_poses.iter()) {
+            assert!(pose.rotation.angle_to(&init_pose.rotation) < 1e-2);
+            assert!((pose.translation.vector - init_pose.translation.vector).norm() < 5e-3);
+        }
+    }
+
+    // GENERATORS ####################################################
+
+    /// Board of 6 x 9 inner corners, and its poses in front of the camera.
+    #[allow(clippy::cast_precision_loss)]
+    fn gen_board_and_poses() -> (Vec<Point3>, Vec<Iso3>) {
+        let (nb_rows, nb_cols, square_size) = (6, 9, 0.03);
+        let mut board = Vec::new();
+        for row in 0..nb_rows {
+            for col in 0..nb_cols {
+                board.push(Point3::new(
+                    col as Float * square_size,
+                    row as Float * square_size,
+                    0.0,
+                ));
+            }
+        }
+        let center = Vec3::new(0.12, 0.075, 0.0);
+        let poses = [
+            (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.45)),
+            (Vec3::new(0.4, 0.0, 0.1), Vec3::new(0.08, 0.05, 0.4)),
+            (Vec3::new(0.0, 0.4, -0.1), Vec3::new(-0.1, 0.06, 0.5)),
+            (Vec3::new(-0.3, 0.3, 0.3), Vec3::new(0.1, -0.08, 0.45)),
+            (Vec3::new(0.3, -0.3, 1.4), Vec3::new(-0.05, -0.05, 0.35)),
+        ]
+        .iter()
+        .map(|(w, t)| {
+            let rotation = so3::exp(*w);
+            Iso3::from_parts(Translation3::from(t - rotation * center), rotation)
+        })
+        .collect();
+        (board, poses)
+    }
+}
@@ -5,9 +5,10 @@
 //! Helper types and functions to manipulate camera poses and projections.
 
 use nalgebra::Affine2;
+use std::f32::consts::PI;
 
 use crate::core::multires;
-use crate::misc::type_aliases::{Float, Iso3, Mat3, Point2, Point3, Vec3};
+use crate::misc::type_aliases::{Float, Iso3, Mat2, Mat23, Mat3, Point2, Point3, Vec2, Vec3};
 
 /// A camera has intrinsic and extrinsic parameters.
 /// Warning: extrinsics here is the pose of the camera,
@@ -57,6 +58,48 @@ impl Camera {
     }
 }
 
+// CAMERA MODELS ###########################################
+
+/// Projection model of a camera, mapping 3D points in camera coordinates to pixels.
+///
+/// Unprojected rays have a depth of 1,
+/// such that multiplying them by the depth of a point gives the 3D point.
+/// The depth is the `z` coordinate by default, as in the depth maps of RGB-D cameras,
+/// and the distance to the camera center for fisheye models,
+/// which can see points behind the image plane.
+/// Depth maps given to the trackers are expected with the same convention.
+pub trait CameraModel: Clone {
+    /// Project a 3D point in camera coordinates into pixel coordinates.
+    /// Return `None` if the point cannot be projected, e.g. if it is behind the camera.
+    fn project(&self, point: &Point3) -> Option<Point2>;
+
+    /// Unproject a pixel into its ray in camera coordinates, with a depth of 1.
+    /// Return `None` if the pixel is outside of the valid domain of the model.
+    fn unproject(&self, pixel: &Point2) -> Option<Vec3>;
+
+    /// Jacobian of the projection with respect to the coordinates of the 3D point.
+    fn project_jacobian(&self, point: &Point3) -> Mat23;
+
+    /// Depth of a 3D point in camera coordinates, its `z` coordinate by default.
+    fn depth(&self, point: &Point3) -> Float {
+        point.z
+    }
+
+    /// Gradient of the depth with respect to the coordinates of the 3D point.
+    fn depth_gradient(&self, _point: &Point3) -> Vec3 {
+        Vec3::z()
+    }
+
+    /// Model of the same camera for an image with half resolution.
+    fn half_res(&self) -> Self;
+
+    /// Generate a multi-resolution vector of camera models.
+    /// Each level corresponds to a camera with half resolution.
+    fn multi_res(self, n: usize) -> Vec<Self> {
+        multires::limited_sequence(n, self, |camera| Some(camera.half_res()))
+    }
+}
+
 // EXTRINSICS ##############################################
 
 /// Extrinsic parameters are represented by a rigid body motion (or direct isometry).
@@ -139,3 +182,721 @@ impl Intrinsics {
         Point3::new(x, y, z)
     }
 }
+
+/// The pinhole model, without distortion.
+impl CameraModel for Intrinsics {
+    fn project(&self, point: &Point3) -> Option<Point2> {
+        if point.z <= 0.0 {
+            return None;
+        }
+        let uvz = Intrinsics::project(self, *point);
+        Some(Point2::new(uvz.x / uvz.z, uvz.y / uvz.z))
+    }
+
+    fn unproject(&self, pixel: &Point2) -> Option<Vec3> {
+        Some(self.back_project(*pixel, 1.0).coords)
+    }
+
+    fn project_jacobian(&self, point: &Point3) -> Mat23 {
+        let _z = 1.0 / point.z;
+        let (x, y) = (point.x * _z, point.y * _z);
+        self.focal_matrix() * normalization_jacobian(x, y, _z)
+    }
+
+    fn half_res(&self) -> Self {
+        Intrinsics::half_res(self)
+    }
+}
+
+impl Intrinsics {
+    /// Linear part of the projection of normalized coordinates.
+    fn focal_matrix(&self) -> Mat2 {
+        Mat2::new(self.focal.0, self.skew, 0.0, self.focal.1)
+    }
+
+    /// Project normalized coordinates `(x/z, y/z)` into pixel coordinates.
+    fn project_normalized(&self, normalized: &Vec2) -> Point2 {
+        let (cu, cv) = self.principal_point;
+        Point2::from(self.focal_matrix() * normalized + Vec2::new(cu, cv))
+    }
+
+    /// Normalized coordinates `(x/z, y/z)` of a pixel.
+    fn unproject_normalized(&self, pixel: &Point2) -> Vec2 {
+        let ray = self.back_project(*pixel, 1.0);
+        Vec2::new(ray.x, ray.y)
+    }
+}
+
+/// Jacobian of the normalized coordinates `(x, y) = (X/Z, Y/Z)`
+/// with respect to the 3D point, given `_z = 1/Z`.
+fn normalization_jacobian(x: Float, y: Float, _z: Float) -> Mat23 {
+    Mat23::new(_z, 0.0, -x * _z, 0.0, _z, -y * _z)
+}
+
+// RADIAL-TANGENTIAL #######################################
+
+/// Max number of Gauss-Newton iterations to invert a distortion.
+const UNDISTORT_MAX_ITERATIONS: usize = 20;
+
+/// Squared error (in normalized coordinates) to consider a distortion inverted.
+const UNDISTORT_SQUARED_ERROR: Float = 1e-12;
+
+/// Brown-Conrady radial-tangential distortion model, as in OpenCV,
+/// applied to the normalized coordinates before the pinhole projection.
+#[derive(PartialEq, Debug, Clone)]
+pub struct RadialTangential {
+    /// Pinhole intrinsic parameters applied after the distortion.
+    pub pinhole: Intrinsics,
+    /// Radial distortion coefficients `(k1, k2, k3)`.
+    pub radial: (Float, Float, Float),
+    /// Tangential distortion coefficients `(p1, p2)`.
+    pub tangential: (Float, Float),
+}
+
+impl RadialTangential {
+    /// Distort normalized coordinates, and return the jacobian of the distortion.
+    #[allow(clippy::many_single_char_names)]
+    pub fn distort(&self, normalized: &Vec2) -> (Vec2, Mat2) {
+        let (k1, k2, k3) = self.radial;
+        let (p1, p2) = self.tangential;
+        let (x, y) = (normalized.x, normalized.y);
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+        let d_radial = k1 + r2 * (2.0 * k2 + 3.0 * r2 * k3);
+        let distorted = Vec2::new(
+            x * radial + 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x),
+            y * radial + p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y,
+        );
+        let cross = 2.0 * x * y * d_radial + 2.0 * p1 * x + 2.0 * p2 * y;
+        let jacobian = Mat2::new(
+            radial + 2.0 * x * x * d_radial + 2.0 * p1 * y + 6.0 * p2 * x,
+            cross,
+            cross,
+            radial + 2.0 * y * y * d_radial + 6.0 * p1 * y + 2.0 * p2 * x,
+        );
+        (distorted, jacobian)
+    }
+
+    /// Undistort normalized coordinates with Gauss-Newton iterations.
+    /// Return `None` if it does not converge.
+    pub fn undistort(&self, distorted: &Vec2) -> Option<Vec2> {
+        let mut normalized = *distorted;
+        for _ in 0..UNDISTORT_MAX_ITERATIONS {
+            let (current, jacobian) = self.distort(&normalized);
+            let error = current - distorted;
+            if error.norm_squared() < UNDISTORT_SQUARED_ERROR {
+                return Some(normalized);
+            }
+            normalized -= jacobian.try_inverse()? * error;
+        }
+        None
+    }
+}
+
+impl CameraModel for RadialTangential {
+    fn project(&self, point: &Point3) -> Option<Point2> {
+        if point.z <= 0.0 {
+            return None;
+        }
+        let (distorted, _) = self.distort(&Vec2::new(point.x / point.z, point.y / point.z));
+        Some(self.pinhole.project_normalized(&distorted))
+    }
+
+    fn unproject(&self, pixel: &Point2) -> Option<Vec3> {
+        let distorted = self.pinhole.unproject_normalized(pixel);
+        self.undistort(&distorted)
+            .map(|normalized| Vec3::new(normalized.x, normalized.y, 1.0))
+    }
+
+    fn project_jacobian(&self, point: &Point3) -> Mat23 {
+        let _z = 1.0 / point.z;
+        let (x, y) = (point.x * _z, point.y * _z);
+        let (_, distortion_jacobian) = self.distort(&Vec2::new(x, y));
+        self.pinhole.focal_matrix() * distortion_jacobian * normalization_jacobian(x, y, _z)
+    }
+
+    fn half_res(&self) -> Self {
+        Self {
+            pinhole: self.pinhole.half_res(),
+            radial: self.radial,
+            tangential: self.tangential,
+        }
+    }
+}
+
+// FISHEYE MODELS ##########################################
+
+/// Max number of Newton iterations to invert the angle distortion.
+const ANGLE_MAX_ITERATIONS: usize = 20;
+
+/// Error (in radians) to consider the angle distortion inverted.
+const ANGLE_ERROR: Float = 1e-6;
+
+/// Kannala-Brandt equidistant model, as the fisheye model of OpenCV and Kalibr.
+///
+/// A point at an angle `theta` of the optical axis is projected at a distance
+/// `theta * (1 + k1 * theta^2 + k2 * theta^4 + k3 * theta^6 + k4 * theta^8)`
+/// of the principal point, in normalized coordinates.
+///
+/// Interesting read: Kannala and Brandt, A generic camera model and calibration method
+/// for conventional, wide-angle, and fish-eye lenses, PAMI 2006.
+#[derive(PartialEq, Debug, Clone)]
+pub struct KannalaBrandt {
+    /// Focal lengths and principal point, applied to the normalized coordinates.
+    pub pinhole: Intrinsics,
+    /// Coefficients `(k1, k2, k3, k4)` of the polynomial.
+    pub coefficients: (Float, Float, Float, Float),
+}
+
+impl KannalaBrandt {
+    /// Distorted angle, and its derivative with respect to the angle.
+    pub fn distort_angle(&self, theta: Float) -> (Float, Float) {
+        let (k1, k2, k3, k4) = self.coefficients;
+        let t2 = theta * theta;
+        let poly = 1.0 + t2 * (k1 + t2 * (k2 + t2 * (k3 + t2 * k4)));
+        let d_poly = 1.0 + t2 * (3.0 * k1 + t2 * (5.0 * k2 + t2 * (7.0 * k3 + t2 * 9.0 * k4)));
+        (theta * poly, d_poly)
+    }
+
+    /// Angle of a distorted angle, with Newton iterations.
+    /// Return `None` if it does not converge to an angle in `[0, pi]`.
+    pub fn undistort_angle(&self, theta_d: Float) -> Option<Float> {
+        let mut theta = theta_d;
+        for _ in 0..ANGLE_MAX_ITERATIONS {
+            let (current, derivative) = self.distort_angle(theta);
+            if derivative <= 0.0 {
+                return None;
+            }
+            let step = (current - theta_d) / derivative;
+            theta -= step;
+            if step.abs() < ANGLE_ERROR {
+                return if (0.0..=PI).contains(&theta) {
+                    Some(theta)
+                } else {
+                    None
+                };
+            }
+        }
+        None
+    }
+}
+
+impl CameraModel for KannalaBrandt {
+    fn project(&self, point: &Point3) -> Option<Point2> {
+        let r = point.coords.xy().norm();
+        if r <= Float::EPSILON * point.z.abs() {
+            return if point.z > 0.0 {
+                Some(self.pinhole.project_normalized(&Vec2::zeros()))
+            } else {
+                None
+            };
+        }
+        let (theta_d, _) = self.distort_angle(r.atan2(point.z));
+        Some(
+            self.pinhole
+                .project_normalized(&(point.coords.xy() * (theta_d / r))),
+        )
+    }
+
+    fn unproject(&self, pixel: &Point2) -> Option<Vec3> {
+        let distorted = self.pinhole.unproject_normalized(pixel);
+        let theta_d = distorted.norm();
+        if theta_d <= Float::EPSILON {
+            return Some(Vec3::z());
+        }
+        let theta = self.undistort_angle(theta_d)?;
+        let direction = distorted * (theta.sin() / theta_d);
+        Some(Vec3::new(direction.x, direction.y, theta.cos()))
+    }
+
+    /// With `r = sqrt(x^2 + y^2)`, `theta = atan2(r, z)` and `s = d(theta) / r`,
+    /// the normalized coordinates are `s * (x, y)`.
+    fn project_jacobian(&self, point: &Point3) -> Mat23 {
+        let (x, y, z) = (point.x, point.y, point.z);
+        let r2 = x * x + y * y;
+        let r = r2.sqrt();
+        let rho2 = r2 + z * z;
+        let (theta_d, d_theta_d) = self.distort_angle(r.atan2(z));
+        let jacobian = if r <= Float::EPSILON * z.abs() {
+            normalization_jacobian(0.0, 0.0, 1.0 / z)
+        } else {
+            let s = theta_d / r;
+            let d_s_d_r = (d_theta_d * z / rho2 - s) / r;
+            let d_s_d_z = -d_theta_d / rho2;
+            radial_jacobian(point, s, d_s_d_r / r, d_s_d_z)
+        };
+        self.pinhole.focal_matrix() * jacobian
+    }
+
+    fn depth(&self, point: &Point3) -> Float {
+        point.coords.norm()
+    }
+
+    fn depth_gradient(&self, point: &Point3) -> Vec3 {
+        point.coords.normalize()
+    }
+
+    fn half_res(&self) -> Self {
+        Self {
+            pinhole: self.pinhole.half_res(),
+            coefficients: self.coefficients,
+        }
+    }
+}
+
+/// Unified camera model, projecting points on the unit sphere,
+/// then with a pinhole camera shifted by `xi` along the optical axis.
+/// Normalized coordinates are `(x, y) / (z + xi * d)` with `d` the distance of the point.
+///
+/// Interesting read: Mei and Rives, Single view point omnidirectional camera calibration
+/// from planar grids, ICRA 2007.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Unified {
+    /// Focal lengths and principal point, applied to the normalized coordinates.
+    pub pinhole: Intrinsics,
+    /// Shift of the projection center from the center of the sphere.
+    pub xi: Float,
+}
+
+impl Unified {
+    /// Min ratio `z / d` of a point to be projected (it is negative).
+    fn min_z_ratio(&self) -> Float {
+        if self.xi <= 1.0 {
+            -self.xi
+        } else {
+            -1.0 / self.xi
+        }
+    }
+}
+
+impl CameraModel for Unified {
+    fn project(&self, point: &Point3) -> Option<Point2> {
+        let d = point.coords.norm();
+        if point.z <= self.min_z_ratio() * d || d <= Float::EPSILON {
+            return None;
+        }
+        let normalized = point.coords.xy() / (point.z + self.xi * d);
+        Some(self.pinhole.project_normalized(&normalized))
+    }
+
+    fn unproject(&self, pixel: &Point2) -> Option<Vec3> {
+        let m = self.pinhole.unproject_normalized(pixel);
+        let r2 = m.norm_squared();
+        let discriminant = 1.0 + (1.0 - self.xi * self.xi) * r2;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let factor = (self.xi + discriminant.sqrt()) / (1.0 + r2);
+        let ray = Vec3::new(factor * m.x, factor * m.y, factor - self.xi);
+        if ray.z <= self.min_z_ratio() {
+            return None;
+        }
+        Some(ray)
+    }
+
+    fn project_jacobian(&self, point: &Point3) -> Mat23 {
+        let d = point.coords.norm();
+        let denominator = point.z + self.xi * d;
+        let d_denominator = Vec3::z() + point.coords * (self.xi / d);
+        self.pinhole.focal_matrix() * quotient_jacobian(point, denominator, &d_denominator)
+    }
+
+    fn depth(&self, point: &Point3) -> Float {
+        point.coords.norm()
+    }
+
+    fn depth_gradient(&self, point: &Point3) -> Vec3 {
+        point.coords.normalize()
+    }
+
+    fn half_res(&self) -> Self {
+        Self {
+            pinhole: self.pinhole.half_res(),
+            xi: self.xi,
+        }
+    }
+}
+
+/// Double sphere model, projecting points on two unit spheres shifted by `xi`,
+/// then with a pinhole camera shifted by `alpha / (1 - alpha)`.
+///
+/// Interesting read: Usenko et al., The double sphere camera model, 3DV 2018.
+#[derive(PartialEq, Debug, Clone)]
+pub struct DoubleSphere {
+    /// Focal lengths and principal point, applied to the normalized coordinates.
+    pub pinhole: Intrinsics,
+    /// Shift between the centers of the two spheres.
+    pub xi: Float,
+    /// Shift of the projection center, as a ratio in `[0, 1]`.
+    pub alpha: Float,
+}
+
+impl DoubleSphere {
+    /// Min ratio `z / d` of a point to be projected.
+    fn min_z_ratio(&self) -> Float {
+        let w1 = if self.alpha <= 0.5 {
+            self.alpha / (1.0 - self.alpha)
+        } else {
+            (1.0 - self.alpha) / self.alpha
+        };
+        let w2 = (w1 + self.xi) / (2.0 * w1 * self.xi + self.xi * self.xi + 1.0).sqrt();
+        -w2
+    }
+}
+
+impl CameraModel for DoubleSphere {
+    fn project(&self, point: &Point3) -> Option<Point2> {
+        let d1 = point.coords.norm();
+        if point.z <= self.min_z_ratio() * d1 || d1 <= Float::EPSILON {
+            return None;
+        }
+        let k = self.xi * d1 + point.z;
+        let d2 = (point.coords.xy().norm_squared() + k * k).sqrt();
+        let normalized = point.coords.xy() / (self.alpha * d2 + (1.0 - self.alpha) * k);
+        Some(self.pinhole.project_normalized(&normalized))
+    }
+
+    fn unproject(&self, pixel: &Point2) -> Option<Vec3> {
+        let (xi, alpha) = (self.xi, self.alpha);
+        let m = self.pinhole.unproject_normalized(pixel);
+        let r2 = m.norm_squared();
+        let discriminant = 1.0 - (2.0 * alpha - 1.0) * r2;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let mz = (1.0 - alpha * alpha * r2) / (alpha * discriminant.sqrt() + 1.0 - alpha);
+        let discriminant = mz * mz + (1.0 - xi * xi) * r2;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let factor = (mz * xi + discriminant.sqrt()) / (mz * mz + r2);
+        let ray = Vec3::new(factor * m.x, factor * m.y, factor * mz - xi);
+        if ray.z <= self.min_z_ratio() {
+            return None;
+        }
+        Some(ray)
+    }
+
+    fn project_jacobian(&self, point: &Point3) -> Mat23 {
+        let d1 = point.coords.norm();
+        let k = self.xi * d1 + point.z;
+        let d2 = (point.coords.xy().norm_squared() + k * k).sqrt();
+        let denominator = self.alpha * d2 + (1.0 - self.alpha) * k;
+        let d_k = Vec3::z() + point.coords * (self.xi / d1);
+        let d_d2 = (Vec3::new(point.x, point.y, 0.0) + k * d_k) / d2;
+        let d_denominator = self.alpha * d_d2 + (1.0 - self.alpha) * d_k;
+        self.pinhole.focal_matrix() * quotient_jacobian(point, denominator, &d_denominator)
+    }
+
+    fn depth(&self, point: &Point3) -> Float {
+        point.coords.norm()
+    }
+
+    fn depth_gradient(&self, point: &Point3) -> Vec3 {
+        point.coords.normalize()
+    }
+
+    fn half_res(&self) -> Self {
+        Self {
+            pinhole: self.pinhole.half_res(),
+            xi: self.xi,
+            alpha: self.alpha,
+        }
+    }
+}
+
+/// Field of view model, where the distance to the principal point
+/// in normalized coordinates is `atan2(2 * tan(w / 2) * r, z) / w`,
+/// with `r = sqrt(x^2 + y^2)`.
+///
+/// Interesting read: Devernay and Faugeras, Straight lines have to be straight, MVA 2001.
+#[derive(PartialEq, Debug, Clone)]
+pub struct FieldOfView {
+    /// Focal lengths and principal point, applied to the normalized coordinates.
+    pub pinhole: Intrinsics,
+    /// Field of view parameter `w` (in radians).
+    pub w: Float,
+}
+
+impl CameraModel for FieldOfView {
+    fn project(&self, point: &Point3) -> Option<Point2> {
+        let r = point.coords.xy().norm();
+        if r <= Float::EPSILON * point.z.abs() {
+            return if point.z > 0.0 {
+                Some(self.pinhole.project_normalized(&Vec2::zeros()))
+            } else {
+                None
+            };
+        }
+        let two_tan = 2.0 * (0.5 * self.w).tan();
+        let r_d = (two_tan * r).atan2(point.z) / self.w;
+        Some(
+            self.pinhole
+                .project_normalized(&(point.coords.xy() * (r_d / r))),
+        )
+    }
+
+    fn unproject(&self, pixel: &Point2) -> Option<Vec3> {
+        let distorted = self.pinhole.unproject_normalized(pixel);
+        let r_d = distorted.norm();
+        if r_d <= Float::EPSILON {
+            return Some(Vec3::z());
+        }
+        let angle = r_d * self.w;
+        if angle >= PI {
+            return None;
+        }
+        let two_tan = 2.0 * (0.5 * self.w).tan();
+        let direction = distorted * (angle.sin() / (two_tan * r_d));
+        Some(Vec3::new(direction.x, direction.y, angle.cos()).normalize())
+    }
+
+    /// With `r = sqrt(x^2 + y^2)` and `s = atan2(2 * tan(w / 2) * r, z) / (w * r)`,
+    /// the normalized coordinates are `s * (x, y)`.
+    fn project_jacobian(&self, point: &Point3) -> Mat23 {
+        let (x, y, z) = (point.x, point.y, point.z);
+        let r = (x * x + y * y).sqrt();
+        let two_tan = 2.0 * (0.5 * self.w).tan();
+        let jacobian = if r <= Float::EPSILON * z.abs() {
+            normalization_jacobian(0.0, 0.0, 1.0 / z) * (two_tan / self.w)
+        } else {
+            let a = two_tan * r;
+            let denominator = self.w * (a * a + z * z);
+            let s = a.atan2(z) / (self.w * r);
+            let d_s_d_r = (two_tan * z / denominator - s) / r;
+            let d_s_d_z = -two_tan / denominator;
+            radial_jacobian(point, s, d_s_d_r / r, d_s_d_z)
+        };
+        self.pinhole.focal_matrix() * jacobian
+    }
+
+    fn depth(&self, point: &Point3) -> Float {
+        point.coords.norm()
+    }
+
+    fn depth_gradient(&self, point: &Point3) -> Vec3 {
+        point.coords.normalize()
+    }
+
+    fn half_res(&self) -> Self {
+        Self {
+            pinhole: self.pinhole.half_res(),
+            w: self.w,
+        }
+    }
+}
+
+/// Jacobian of normalized coordinates `s * (x, y)` with respect to the 3D point,
+/// where `s` depends on `r = sqrt(x^2 + y^2)` and `z`,
+/// given `ds/dr / r` and `ds/dz`.
+fn radial_jacobian(point: &Point3, s: Float, d_s_d_r_over_r: Float, d_s_d_z: Float) -> Mat23 {
+    let (x, y) = (point.x, point.y);
+    let xy = x * y * d_s_d_r_over_r;
+    Mat23::new(
+        s + x * x * d_s_d_r_over_r,
+        xy,
+        x * d_s_d_z,
+        xy,
+        s + y * y * d_s_d_r_over_r,
+        y * d_s_d_z,
+    )
+}
+
+/// Jacobian of normalized coordinates `(x, y) / q` with respect to the 3D point,
+/// given the denominator `q` and its gradient.
+fn quotient_jacobian(point: &Point3, q: Float, d_q: &Vec3) -> Mat23 {
+    let _q = 1.0 / q;
+    let diagonal = Mat23::new(_q, 0.0, 0.0, 0.0, _q, 0.0);
+    diagonal - (Vec2::new(point.x, point.y) * (_q * _q)) * d_q.transpose()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::misc::helper::bounded;
+    use approx;
+    use quickcheck_macros;
+
+    // Precision of centered finite differences with f32 computations.
+    // Projections are hundreds of pixels, so the absolute precision is lower.
+    const EPSILON_FINITE_DIFF: Float = 1e-1;
+    const EPSILON_FINITE_DIFF_RELATIVE: Float = 1e-2;
+
+    // Precision of round trips, in the unit of the 3D points.
+    const EPSILON_ROUNDTRIP: Float = 1e-3;
+
+    // PROPERTY TESTS ################################################
+
+    #[quickcheck_macros::quickcheck]
+    fn pinhole_jacobian(x: Float, y: Float, z: Float) -> bool {
+        jacobian_ok(&gen_pinhole(), &gen_point(x, y, z))
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn pinhole_round_trip(x: Float, y: Float, z: Float) -> bool {
+        round_trip_ok(&gen_pinhole(), &gen_point(x, y, z))
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn radial_tangential_jacobian(x: Float, y: Float, z: Float) -> bool {
+        jacobian_ok(&gen_radial_tangential(), &gen_point(x, y, z))
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn radial_tangential_round_trip(x: Float, y: Float, z: Float) -> bool {
+        round_trip_ok(&gen_radial_tangential(), &gen_point(x, y, z))
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn kannala_brandt_jacobian(t: Float, p: Float, d: Float) -> bool {
+        jacobian_ok(&gen_kannala_brandt(), &gen_wide_point(t, p, d))
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn kannala_brandt_round_trip(t: Float, p: Float, d: Float) -> bool {
+        round_trip_ok(&gen_kannala_brandt(), &gen_wide_point(t, p, d))
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn unified_jacobian(t: Float, p: Float, d: Float) -> bool {
+        jacobian_ok(&gen_unified(), &gen_wide_point(t, p, d))
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn unified_round_trip(t: Float, p: Float, d: Float) -> bool {
+        round_trip_ok(&gen_unified(), &gen_wide_point(t, p, d))
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn double_sphere_jacobian(t: Float, p: Float, d: Float) -> bool {
+        jacobian_ok(&gen_double_sphere(), &gen_wide_point(t, p, d))
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn double_sphere_round_trip(t: Float, p: Float, d: Float) -> bool {
+        round_trip_ok(&gen_double_sphere(), &gen_wide_point(t, p, d))
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn field_of_view_jacobian(t: Float, p: Float, d: Float) -> bool {
+        jacobian_ok(&gen_field_of_view(), &gen_wide_point(t, p, d))
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn field_of_view_round_trip(t: Float, p: Float, d: Float) -> bool {
+        round_trip_ok(&gen_field_of_view(), &gen_wide_point(t, p, d))
+    }
+
+    // HELPERS #######################################################
+
+    /// Compare the jacobian of the projection with centered finite differences.
+    fn jacobian_ok<C: CameraModel>(camera: &C, point: &Point3) -> bool {
+        let h = 1e-2;
+        let mut numeric = Mat23::zeros();
+        for k in 0..3 {
+            let mut delta = Vec3::zeros();
+            delta[k] = h;
+            match (
+                camera.project(&(point + delta)),
+                camera.project(&(point - delta)),
+            ) {
+                (Some(plus), Some(minus)) => numeric.set_column(k, &((plus - minus) / (2.0 * h))),
+                _ => return false,
+            }
+        }
+        approx::relative_eq!(
+            camera.project_jacobian(point),
+            numeric,
+            epsilon = EPSILON_FINITE_DIFF,
+            max_relative = EPSILON_FINITE_DIFF_RELATIVE
+        )
+    }
+
+    /// Project a point, unproject its pixel and scale the ray by the depth of the point.
+    fn round_trip_ok<C: CameraModel>(camera: &C, point: &Point3) -> bool {
+        camera
+            .project(point)
+            .and_then(|pixel| camera.unproject(&pixel))
+            .is_some_and(|ray| {
+                approx::relative_eq!(
+                    Point3::from(ray * camera.depth(point)),
+                    *point,
+                    epsilon = EPSILON_ROUNDTRIP
+                )
+            })
+    }
+
+    // GENERATORS ####################################################
+
+    /// Point in front of the camera, in a cone of half angle about 30 degrees.
+    fn gen_point(x: Float, y: Float, z: Float) -> Point3 {
+        let z = 1.5 + bounded(z);
+        Point3::new(0.5 * z * bounded(x), 0.5 * z * bounded(y), z)
+    }
+
+    /// Point at up to 100 degrees of the optical axis, i.e. possibly behind the image plane.
+    fn gen_wide_point(theta: Float, phi: Float, distance: Float) -> Point3 {
+        let theta = 0.87 * (1.0 + bounded(theta));
+        let phi = PI * bounded(phi);
+        let distance = 1.5 + bounded(distance);
+        Point3::new(
+            distance * theta.sin() * phi.cos(),
+            distance * theta.sin() * phi.sin(),
+            distance * theta.cos(),
+        )
+    }
+
+    fn gen_pinhole() -> Intrinsics {
+        Intrinsics {
+            principal_point: (319.5, 239.5),
+            focal: (525.0, 520.0),
+            skew: 0.5,
+        }
+    }
+
+    fn gen_radial_tangential() -> RadialTangential {
+        RadialTangential {
+            pinhole: gen_pinhole(),
+            radial: (-0.28, 0.07, 0.01),
+            tangential: (1e-4, -2e-4),
+        }
+    }
+
+    fn gen_fisheye_pinhole() -> Intrinsics {
+        Intrinsics {
+            principal_point: (255.5, 255.5),
+            focal: (190.0, 191.0),
+            skew: 0.0,
+        }
+    }
+
+    fn gen_kannala_brandt() -> KannalaBrandt {
+        KannalaBrandt {
+            pinhole: gen_fisheye_pinhole(),
+            coefficients: (0.0034, 0.0007, -0.002, 0.0002),
+        }
+    }
+
+    fn gen_unified() -> Unified {
+        Unified {
+            pinhole: gen_fisheye_pinhole(),
+            xi: 0.9,
+        }
+    }
+
+    fn gen_double_sphere() -> DoubleSphere {
+        DoubleSphere {
+            pinhole: gen_fisheye_pinhole(),
+            xi: -0.2,
+            alpha: 0.59,
+        }
+    }
+
+    fn gen_field_of_view() -> FieldOfView {
+        FieldOfView {
+            pinhole: gen_fisheye_pinhole(),
+            w: 0.9,
+        }
+    }
+}
@@ -0,0 +1,289 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Noise models of depth sensors, giving the variance of each inverse depth of a depth map.
+//!
+//! The noise of consumer depth sensors grows with the distance and with the angle
+//! between the viewing ray and the surface normal (the incidence angle).
+//! Incidence angles are estimated from the normals of the back-projected depth map,
+//! so that pixels on a depth discontinuity, where neighbors are on different surfaces,
+//! get a grazing angle and thus a high variance.
+//!
+//! Interesting reads:
+//!
+//! - Nguyen et al., Modeling Kinect sensor noise for improved 3D reconstruction
+//!   and tracking, 3DIMPVT 2012.
+//! - Fankhauser et al., Kinect v2 for mobile robot navigation:
+//!   evaluation and modeling, ICAR 2015.
+
+use nalgebra::DMatrix;
+
+use crate::core::camera::CameraModel;
+use crate::core::inverse_depth::InverseDepth;
+use crate::misc::type_aliases::{Float, Point2, Vec3};
+
+/// Max incidence angle (in radians) used by the models,
+/// since their angular term diverges for grazing angles.
+pub const MAX_INCIDENCE_ANGLE: Float = 1.4;
+
+/// Structured light model of the Kinect v1, from Nguyen et al.
+pub const KINECT_V1: StructuredLight = StructuredLight {
+    base: 0.0012,
+    quadratic: 0.0019,
+    optimal_depth: 0.4,
+    angular: 0.0001,
+};
+
+/// Time of flight model of the Kinect v2, from Fankhauser et al.
+pub const KINECT_V2: TimeOfFlight = TimeOfFlight {
+    base: 0.0015,
+    linear: -0.0005,
+    quadratic: 0.0003,
+    angular: 0.0001,
+};
+
+/// Noise model of a depth sensor.
+pub trait DepthNoiseModel {
+    /// Variance (in squared meters) of a depth (in meters)
+    /// measured with a given incidence angle (in radians).
+    fn depth_variance(&self, depth: Float, angle: Float) -> Float;
+
+    /// Variance of the inverse of a depth (in meters)
+    /// measured with a given incidence angle (in radians).
+    /// By first order propagation, it is `depth_variance / depth^4`.
+    fn idepth_variance(&self, depth: Float, angle: Float) -> Float {
+        self.depth_variance(depth, angle) / depth.powi(4)
+    }
+}
+
+/// Same variance for all inverse depths, regardless of the depth and angle.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ConstantIdepth(pub Float);
+
+impl DepthNoiseModel for ConstantIdepth {
+    fn depth_variance(&self, depth: Float, _angle: Float) -> Float {
+        self.0 * depth.powi(4)
+    }
+
+    fn idepth_variance(&self, _depth: Float, _angle: Float) -> Float {
+        self.0
+    }
+}
+
+/// Noise of structured light sensors, such as the Kinect v1.
+/// The standard deviation of a depth `z` with an incidence angle `a` is
+/// `base + quadratic * (z - optimal_depth)^2 + angular / sqrt(z) * a^2 / (pi/2 - a)^2`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct StructuredLight {
+    /// Standard deviation (in meters) at the optimal depth.
+    pub base: Float,
+    /// Coefficient of the quadratic growth with the distance to the optimal depth.
+    pub quadratic: Float,
+    /// Depth (in meters) with the lowest noise.
+    pub optimal_depth: Float,
+    /// Coefficient of the angular term.
+    pub angular: Float,
+}
+
+impl DepthNoiseModel for StructuredLight {
+    fn depth_variance(&self, depth: Float, angle: Float) -> Float {
+        let d = depth - self.optimal_depth;
+        let std = self.base + self.quadratic * d * d + self.angular / depth.sqrt() * angular(angle);
+        std * std
+    }
+}
+
+/// Noise of time of flight sensors, such as the Kinect v2.
+/// The standard deviation of a depth `z` with an incidence angle `a` is
+/// `base + linear * z + quadratic * z^2 + angular * z^1.5 * a^2 / (pi/2 - a)^2`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TimeOfFlight {
+    /// Constant term of the standard deviation (in meters).
+    pub base: Float,
+    /// Coefficient of the linear term.
+    pub linear: Float,
+    /// Coefficient of the quadratic term.
+    pub quadratic: Float,
+    /// Coefficient of the angular term.
+    pub angular: Float,
+}
+
+impl DepthNoiseModel for TimeOfFlight {
+    fn depth_variance(&self, depth: Float, angle: Float) -> Float {
+        let std = self.base
+            + depth * (self.linear + self.quadratic * depth)
+            + self.angular * depth * depth.sqrt() * angular(angle);
+        std * std
+    }
+}
+
+/// Angular factor `a^2 / (pi/2 - a)^2` of the models, with the angle clamped.
+fn angular(angle: Float) -> Float {
+    let a = angle.clamp(0.0, MAX_INCIDENCE_ANGLE);
+    let b = std::f32::consts::FRAC_PI_2 as Float - a;
+    a * a / (b * b)
+}
+
+/// Inverse depth map of a depth image, with variances given by a noise model.
+///
+/// Depth values are scaled by `depth_scale` (5000.0 for 1 meter in TUM RGB-D),
+/// and 0 means unknown.
+/// The incidence angle is computed with the normal estimated by centered differences
+/// of the neighbor points, and is `MAX_INCIDENCE_ANGLE` for pixels with unknown neighbors,
+/// which are often at depth discontinuities, to keep their variance conservative.
+#[allow(clippy::cast_precision_loss)]
+pub fn inverse_depth_map<M: DepthNoiseModel + ?Sized, C: CameraModel>(
+    model: &M,
+    camera: &C,
+    depth_scale: Float,
+    depth_map: &DMatrix<u16>,
+) -> DMatrix<InverseDepth> {
+    let (nb_rows, nb_cols) = depth_map.shape();
+    let points: DMatrix<Option<Vec3>> = DMatrix::from_fn(nb_rows, nb_cols, |r, c| {
+        let depth = depth_map[(r, c)];
+        if depth == 0 {
+            return None;
+        }
+        let ray = camera.unproject(&Point2::new(c as Float, r as Float))?;
+        Some(ray * (Float::from(depth) / depth_scale))
+    });
+    let point = |r: usize, c: usize| points.get((r, c)).cloned().flatten();
+    DMatrix::from_fn(nb_rows, nb_cols, |r, c| {
+        let depth = depth_map[(r, c)];
+        if depth == 0 {
+            return InverseDepth::Unknown;
+        }
+        let depth_m = Float::from(depth) / depth_scale;
+        let angle = point(r, c)
+            .and_then(|p| {
+                let d_x = point(r, c + 1)? - point(r, c.checked_sub(1)?)?;
+                let d_y = point(r + 1, c)? - point(r.checked_sub(1)?, c)?;
+                let n = d_x.cross(&d_y).try_normalize(Float::EPSILON)?;
+                let cos = n.dot(&p).abs() / p.norm();
+                Some(cos.min(1.0).acos())
+            })
+            .unwrap_or(MAX_INCIDENCE_ANGLE);
+        InverseDepth::WithVariance(1.0 / depth_m, model.idepth_variance(depth_m, angle))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::core::camera::Intrinsics;
+    use crate::misc::helper::bounded;
+    use approx;
+    use quickcheck_macros;
+
+    const EPSILON: Float = 1e-6;
+
+    #[test]
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn tilted_plane_is_noisier() {
+        let camera = Intrinsics {
+            principal_point: (15.5, 15.5),
+            focal: (30.0, 30.0),
+            skew: 0.0,
+        };
+        let depth_scale = 1000.0;
+        let fronto = DMatrix::repeat(32, 32, 2000);
+        // Plane tilted by 45 degrees around the y axis, at 2 meters at the center.
+        let tilted = DMatrix::from_fn(32, 32, |_, x| {
+            let u = (x as Float - 15.5) / 30.0;
+            (depth_scale * 2.0 / (1.0 + u)).round() as u16
+        });
+        let variance = |depth_map: &DMatrix<u16>, r: usize, c: usize| match inverse_depth_map(
+            &KINECT_V1,
+            &camera,
+            depth_scale,
+            depth_map,
+        )[(r, c)]
+        {
+            InverseDepth::WithVariance(_, variance) => variance,
+            _ => panic!("Expected a known inverse depth"),
+        };
+        assert!(variance(&tilted, 16, 16) > variance(&fronto, 16, 16));
+        // Pixels on the border have unknown neighbors, so the max angle.
+        assert!(approx::relative_eq!(
+            variance(&tilted, 0, 16),
+            KINECT_V1.idepth_variance(
+                Float::from(tilted[(0, 16)]) / depth_scale,
+                MAX_INCIDENCE_ANGLE
+            ),
+            epsilon = EPSILON
+        ));
+    }
+
+    #[test]
+    fn missing_neighbors_are_grazing() {
+        let camera = Intrinsics {
+            principal_point: (7.5, 7.5),
+            focal: (15.0, 15.0),
+            skew: 0.0,
+        };
+        let mut depth_map = DMatrix::repeat(16, 16, 2000);
+        depth_map[(8, 8)] = 0;
+        let idepth_map = inverse_depth_map(&KINECT_V2, &camera, 1000.0, &depth_map);
+        assert_eq!(idepth_map[(8, 8)], InverseDepth::Unknown);
+        let variance = |r: usize, c: usize| match idepth_map[(r, c)] {
+            InverseDepth::WithVariance(_, variance) => variance,
+            _ => panic!("Expected a known inverse depth"),
+        };
+        // Neighbors of the hole and pixels on the border have the max angle.
+        let grazing = KINECT_V2.idepth_variance(2.0, MAX_INCIDENCE_ANGLE);
+        let hole_neighbors = [(7, 8), (9, 8), (8, 7), (8, 9)];
+        let borders = [(0, 4), (15, 4), (4, 0), (4, 15)];
+        for &(r, c) in hole_neighbors.iter().chain(borders.iter()) {
+            assert_eq!(variance(r, c), grazing);
+        }
+        // Other pixels see the fronto-parallel plane with an angle of 0.
+        let fronto = KINECT_V2.idepth_variance(2.0, 0.0);
+        for &(r, c) in [(7, 7), (4, 4), (1, 14)].iter() {
+            assert!(approx::relative_eq!(
+                variance(r, c),
+                fronto,
+                max_relative = 1e-3
+            ));
+        }
+    }
+
+    #[test]
+    fn unknown_depth() {
+        let camera = Intrinsics {
+            principal_point: (1.5, 1.5),
+            focal: (3.0, 3.0),
+            skew: 0.0,
+        };
+        let depth_map = DMatrix::zeros(4, 4);
+        let idepth_map = inverse_depth_map(&KINECT_V2, &camera, 1000.0, &depth_map);
+        assert!(idepth_map.iter().all(|&d| d == InverseDepth::Unknown));
+    }
+
+    // PROPERTY TESTS ################################################
+
+    #[quickcheck_macros::quickcheck]
+    fn constant_idepth(depth: Float, angle: Float) -> bool {
+        let depth = 0.5 + 4.0 * bounded(depth).abs();
+        let angle = bounded(angle).abs();
+        let model = ConstantIdepth(1e-3);
+        model.idepth_variance(depth, angle) == 1e-3
+            && approx::relative_eq!(
+                model.depth_variance(depth, angle) / depth.powi(4),
+                1e-3,
+                epsilon = EPSILON
+            )
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn grows_with_angle(depth: Float, angle: Float) -> bool {
+        let depth = 0.5 + 4.0 * bounded(depth).abs();
+        let angle = MAX_INCIDENCE_ANGLE * bounded(angle).abs();
+        let grazing = MAX_INCIDENCE_ANGLE;
+        KINECT_V1.depth_variance(depth, angle) <= KINECT_V1.depth_variance(depth, grazing)
+            && KINECT_V2.depth_variance(depth, angle) <= KINECT_V2.depth_variance(depth, grazing)
+            && KINECT_V1.depth_variance(depth, 10.0) == KINECT_V1.depth_variance(depth, grazing)
+    }
+}
@@ -0,0 +1,325 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Epipolar line search, estimating the inverse depth of a pixel
+//! by matching it in another image taken with a known relative motion.
+//!
+//! Interesting reads:
+//! - Engel et al., Semi-dense visual odometry for a monocular camera, ICCV 2013.
+//! - Engel et al., Direct sparse odometry, PAMI 2018.
+
+use nalgebra::DMatrix;
+
+use crate::core::camera::Intrinsics;
+use crate::core::inverse_depth::InverseDepth;
+use crate::misc::helper::interpolate;
+use crate::misc::type_aliases::{Float, Iso3, Point2, Point3, Vec2, Vec3};
+
+/// Offsets of the pattern of pixels compared during the search,
+/// the "8 pattern" of DSO.
+const PATTERN: [(Float, Float); 8] = [
+    (0.0, -2.0),
+    (-1.0, -1.0),
+    (1.0, -1.0),
+    (-2.0, 0.0),
+    (0.0, 0.0),
+    (2.0, 0.0),
+    (-1.0, 1.0),
+    (0.0, 2.0),
+];
+
+/// Parameters of the epipolar line search.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SearchConfig {
+    /// Range of inverse depths searched when the inverse depth is unknown.
+    pub idepth_range: (Float, Float),
+    /// Number of standard deviations around a known inverse depth
+    /// defining the searched range.
+    pub nb_std_dev: Float,
+    /// Max number of samples along the epipolar line.
+    /// Samples are one pixel apart, unless the line is longer than this.
+    pub max_nb_samples: usize,
+    /// Max mean squared intensity difference of the best match.
+    pub max_error: Float,
+    /// Min ratio between the errors of the second best and of the best matches.
+    pub min_uniqueness: Float,
+    /// Min mean squared gradient along the epipolar line at the match.
+    pub min_gradient: Float,
+    /// Variance of the image noise, to estimate the photometric disparity error.
+    pub image_noise_variance: Float,
+    /// Variance (in squared pixels) of the match position, for geometric errors.
+    pub pixel_variance: Float,
+}
+
+/// Default parameters of the search, for 8 bits images.
+pub const DEFAULT_SEARCH_CONFIG: SearchConfig = SearchConfig {
+    idepth_range: (0.05, 2.0),
+    nb_std_dev: 2.0,
+    max_nb_samples: 100,
+    max_error: 400.0,
+    min_uniqueness: 1.5,
+    min_gradient: 4.0,
+    image_noise_variance: 16.0,
+    pixel_variance: 0.25,
+};
+
+/// Search a pixel of the host image along its epipolar line in the target image,
+/// where `motion` brings points from the host frame to the target frame.
+///
+/// The searched inverse depth range is deduced from the prior if it is known.
+/// Return `WithVariance` if the match is successful,
+/// `Discarded` if the match is unreliable (ambiguous or too different),
+/// and `Unknown` if nothing could be learned (epipolar line out of the image,
+/// too short or without texture).
+#[allow(clippy::cast_precision_loss)]
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+pub fn search(
+    host: &DMatrix<u8>,
+    target: &DMatrix<u8>,
+    intrinsics: &Intrinsics,
+    motion: &Iso3,
+    (x, y): (usize, usize),
+    prior: InverseDepth,
+    config: &SearchConfig,
+) -> InverseDepth {
+    // Searched inverse depth range.
+    let (range_min, range_max) = config.idepth_range;
+    let (idepth_min, idepth_max) = match prior {
+        InverseDepth::WithVariance(idepth, variance) => {
+            let delta = config.nb_std_dev * variance.sqrt();
+            (
+                (idepth - delta).max(range_min),
+                (idepth + delta).min(range_max),
+            )
+        }
+        _ => (range_min, range_max),
+    };
+    if idepth_min >= idepth_max {
+        return InverseDepth::Unknown;
+    }
+
+    // Epipolar segment in the target image.
+    let (x, y) = (x as Float, y as Float);
+    let ray = motion.rotation * intrinsics.back_project(Point2::new(x, y), 1.0).coords;
+    let t = motion.translation.vector;
+    let (p_min, p_max) = match (
+        project(intrinsics, &(ray + idepth_min * t)),
+        project(intrinsics, &(ray + idepth_max * t)),
+    ) {
+        (Some(p_min), Some(p_max)) => (p_min, p_max),
+        _ => return InverseDepth::Unknown,
+    };
+    let length = (p_max - p_min).norm();
+    if length < Float::EPSILON {
+        return InverseDepth::Unknown;
+    }
+    let dir = (p_max - p_min) / length;
+    let nb_samples = (length.ceil() as usize + 1)
+        .min(config.max_nb_samples)
+        .max(2);
+    let step = length / (nb_samples - 1) as Float;
+
+    // Reference intensities of the pattern in the host image.
+    let mut reference = [0.0; 8];
+    for (r, (dx, dy)) in reference.iter_mut().zip(PATTERN.iter()) {
+        match interpolate(x + dx, y + dy, host) {
+            Some(intensity) => *r = intensity,
+            None => return InverseDepth::Unknown,
+        }
+    }
+
+    // Errors along the epipolar segment.
+    let position = |s: Float| p_min + s * step * dir;
+    let errors: Vec<Float> = (0..nb_samples)
+        .map(|s| pattern_error(target, &position(s as Float), &reference))
+        .collect();
+    let (best, best_error) =
+        errors
+            .iter()
+            .enumerate()
+            .fold((0, Float::INFINITY), |(i_min, e_min), (i, &e)| {
+                if e < e_min {
+                    (i, e)
+                } else {
+                    (i_min, e_min)
+                }
+            });
+    if !best_error.is_finite() {
+        return InverseDepth::Unknown;
+    }
+    if best_error > config.max_error {
+        return InverseDepth::Discarded;
+    }
+    let second_error = errors
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| (*i as Float - best as Float).abs() * step > 2.0)
+        .map(|(_, &e)| e)
+        .fold(Float::INFINITY, Float::min);
+    if second_error < config.min_uniqueness * best_error {
+        return InverseDepth::Discarded;
+    }
+
+    // Sub-sample refinement by fitting a parabola to the errors.
+    let mut s_best = best as Float;
+    if best > 0 && best + 1 < nb_samples {
+        let (e_prev, e_next) = (errors[best - 1], errors[best + 1]);
+        let curvature = e_prev - 2.0 * best_error + e_next;
+        if curvature > 0.0 && e_prev.is_finite() && e_next.is_finite() {
+            s_best += (0.5 * (e_prev - e_next) / curvature).clamp(-0.5, 0.5);
+        }
+    }
+    let p_best = position(s_best);
+
+    // Photometric disparity error, from the gradient along the epipolar line.
+    let gradient_sq = match gradient_along(target, &p_best, &dir) {
+        Some(g) if g >= config.min_gradient => g,
+        _ => return InverseDepth::Unknown,
+    };
+    let pixel_variance = config.pixel_variance + 2.0 * config.image_noise_variance / gradient_sq;
+
+    // Triangulation, and variance propagation with a numerical derivative.
+    let idepth_at = |p: &Point2| triangulate(intrinsics, &ray, &t, p);
+    match (
+        idepth_at(&p_best),
+        idepth_at(&(p_best - 0.5 * dir)),
+        idepth_at(&(p_best + 0.5 * dir)),
+    ) {
+        (Some(idepth), Some(d_prev), Some(d_next)) if idepth > 0.0 => {
+            let d_idepth = d_next - d_prev;
+            InverseDepth::WithVariance(idepth, d_idepth * d_idepth * pixel_variance)
+        }
+        _ => InverseDepth::Discarded,
+    }
+}
+
+// Helper ######################################################################
+
+/// Project a point (possibly scaled by its inverse depth) into pixel coordinates.
+fn project(intrinsics: &Intrinsics, point: &Vec3) -> Option<Point2> {
+    if point.z <= Float::EPSILON {
+        return None;
+    }
+    let uvz = intrinsics.project(Point3::from(*point));
+    Some(Point2::new(uvz.x / uvz.z, uvz.y / uvz.z))
+}
+
+/// Mean squared difference between the pattern around a target position
+/// and the reference intensities.
+fn pattern_error(target: &DMatrix<u8>, p: &Point2, reference: &[Float; 8]) -> Float {
+    let mut error = 0.0;
+    for (r, (dx, dy)) in reference.iter().zip(PATTERN.iter()) {
+        match interpolate(p.x + dx, p.y + dy, target) {
+            Some(intensity) => error += (intensity - r) * (intensity - r),
+            None => return Float::INFINITY,
+        }
+    }
+    error / PATTERN.len() as Float
+}
+
+/// Mean squared gradient of the pattern along a direction.
+fn gradient_along(target: &DMatrix<u8>, p: &Point2, dir: &Vec2) -> Option<Float> {
+    let mut sum = 0.0;
+    for (dx, dy) in PATTERN.iter() {
+        let next = interpolate(p.x + dx + dir.x, p.y + dy + dir.y, target)?;
+        let prev = interpolate(p.x + dx - dir.x, p.y + dy - dir.y, target)?;
+        let g = 0.5 * (next - prev);
+        sum += g * g;
+    }
+    Some(sum / PATTERN.len() as Float)
+}
+
+/// Inverse depth such that `ray + idepth * t` projects at the given pixel.
+/// Uses the coordinate with the most precision, depending on the line direction.
+fn triangulate(intrinsics: &Intrinsics, ray: &Vec3, t: &Vec3, p: &Point2) -> Option<Float> {
+    let n = intrinsics.back_project(*p, 1.0);
+    let denom_x = n.x * t.z - t.x;
+    let denom_y = n.y * t.z - t.y;
+    if denom_x.abs() >= denom_y.abs() && denom_x.abs() > Float::EPSILON {
+        Some((ray.x - n.x * ray.z) / denom_x)
+    } else if denom_y.abs() > Float::EPSILON {
+        Some((ray.y - n.y * ray.z) / denom_y)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::math::so3;
+    use crate::misc::helper::{bounded, gen_texture};
+    use approx;
+    use quickcheck_macros;
+
+    const EPSILON: Float = 1e-3;
+
+    #[test]
+    fn search_shifted_plane() {
+        // Fronto-parallel plane at 2 meters, moving 5 pixels to the left in the target.
+        let intrinsics = gen_intrinsics();
+        let host = gen_texture(40, 80);
+        let target = DMatrix::from_fn(40, 80, |y, x| host[(y, (x + 5).min(79))]);
+        let motion = Iso3::translation(-5.0 * 2.0 / 50.0, 0.0, 0.0);
+        let config = DEFAULT_SEARCH_CONFIG;
+        let (mut nb_matches, mut nb_accurate) = (0, 0);
+        for y in 5..35 {
+            for x in 30..70 {
+                let result = search(
+                    &host,
+                    &target,
+                    &intrinsics,
+                    &motion,
+                    (x, y),
+                    InverseDepth::Unknown,
+                    &config,
+                );
+                if let InverseDepth::WithVariance(idepth, variance) = result {
+                    nb_matches += 1;
+                    // One pixel of disparity is 0.1 of inverse depth.
+                    let error = (idepth - 0.5).abs();
+                    if error < 0.05 && error < 3.0 * variance.sqrt() {
+                        nb_accurate += 1;
+                    }
+                }
+            }
+        }
+        assert!(nb_matches > 30 * 40 * 9 / 10);
+        assert!(nb_accurate > nb_matches * 95 / 100);
+    }
+
+    // PROPERTY TESTS ################################################
+
+    #[quickcheck_macros::quickcheck]
+    fn triangulate_projection(x: Float, y: Float, d: Float, t1: Float, t2: Float) -> bool {
+        let intrinsics = gen_intrinsics();
+        let rotation = so3::exp(Vec3::new(0.05, -0.1, 0.02));
+        let ray = rotation
+            * intrinsics
+                .back_project(
+                    Point2::new(40.0 + 40.0 * bounded(x), 20.0 + 20.0 * bounded(y)),
+                    1.0,
+                )
+                .coords;
+        let t = Vec3::new(0.2 * bounded(t1) + 0.1, 0.2 * bounded(t2), 0.05);
+        let idepth = 0.2 + bounded(d).abs();
+        project(&intrinsics, &(ray + idepth * t))
+            .and_then(|p| triangulate(&intrinsics, &ray, &t, &p))
+            .is_some_and(|triangulated| {
+                approx::relative_eq!(triangulated, idepth, epsilon = EPSILON)
+            })
+    }
+
+    // GENERATORS ####################################################
+
+    fn gen_intrinsics() -> Intrinsics {
+        Intrinsics {
+            principal_point: (39.5, 19.5),
+            focal: (50.0, 50.0),
+            skew: 0.0,
+        }
+    }
+}
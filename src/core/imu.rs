@@ -0,0 +1,329 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Inertial measurements, and their on-manifold preintegration between frames.
+//!
+//! The preintegrated rotation, velocity and position deltas summarize
+//! all measurements between two frames in the body frame of the first one,
+//! independently of its pose, velocity and of gravity.
+//! Their covariance is propagated from the sensor noise.
+//! Biases are considered fixed during the integration,
+//! so no jacobian with respect to the biases is maintained.
+//!
+//! Interesting reads:
+//! - Forster et al., On-manifold preintegration for real-time
+//!   visual-inertial odometry, TRO 2017.
+
+use nalgebra::{UnitQuaternion, U3};
+
+use crate::math::so3;
+use crate::misc::type_aliases::{Float, Iso3, Mat3, Mat9, Vec3};
+
+/// A measurement of the inertial measurement unit (IMU), in the body (IMU) frame.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ImuMeasurement {
+    /// Timestamp of the measurement (in seconds).
+    pub timestamp: f64,
+    /// Angular velocity (in rad/s) measured by the gyroscope.
+    pub gyro: Vec3,
+    /// Specific force (in m/s^2) measured by the accelerometer.
+    /// It is `-gravity` for a body at rest.
+    pub accel: Vec3,
+}
+
+/// Biases of the gyroscope and accelerometer.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Bias {
+    /// Bias of the gyroscope (in rad/s).
+    pub gyro: Vec3,
+    /// Bias of the accelerometer (in m/s^2).
+    pub accel: Vec3,
+}
+
+impl Bias {
+    /// Null biases.
+    pub fn zeros() -> Self {
+        Self {
+            gyro: Vec3::zeros(),
+            accel: Vec3::zeros(),
+        }
+    }
+}
+
+/// Noise densities of the sensors, as given in Kalibr calibration files.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Noise {
+    /// Gyroscope white noise density (in rad/s/sqrt(Hz)).
+    pub gyro: Float,
+    /// Accelerometer white noise density (in m/s^2/sqrt(Hz)).
+    pub accel: Float,
+}
+
+/// Pose (of the body in the world frame) and velocity (in the world frame).
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct NavState {
+    /// Pose of the body frame in the world frame.
+    pub pose: Iso3,
+    /// Velocity of the body, in the world frame.
+    pub velocity: Vec3,
+}
+
+/// Preintegrated measurements between two frames `i` and `j`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Preintegration {
+    /// Biases used for the integration.
+    pub bias: Bias,
+    /// Integrated duration (in seconds).
+    pub delta_t: Float,
+    /// Rotation from the body frame at `j` to the body frame at `i`.
+    pub delta_rotation: UnitQuaternion<Float>,
+    /// Velocity change, without gravity, in the body frame at `i`.
+    pub delta_velocity: Vec3,
+    /// Position change, without gravity and initial velocity, in the body frame at `i`.
+    pub delta_position: Vec3,
+    /// Covariance of the rotation (right perturbation), velocity and position deltas.
+    pub covariance: Mat9,
+}
+
+impl Preintegration {
+    /// Empty preintegration, with the given biases.
+    pub fn new(bias: Bias) -> Self {
+        Self {
+            bias,
+            delta_t: 0.0,
+            delta_rotation: UnitQuaternion::identity(),
+            delta_velocity: Vec3::zeros(),
+            delta_position: Vec3::zeros(),
+            covariance: Mat9::zeros(),
+        }
+    }
+
+    /// Preintegrate the measurements between two timestamps.
+    /// Measurements are considered constant until the next one,
+    /// and the last one is held until `time_end`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn between(
+        measurements: &[ImuMeasurement],
+        time_start: f64,
+        time_end: f64,
+        bias: Bias,
+        noise: &Noise,
+    ) -> Self {
+        let mut preintegration = Self::new(bias);
+        for (k, m) in measurements.iter().enumerate() {
+            let next_time = measurements
+                .get(k + 1)
+                .map_or(time_end, |next| next.timestamp);
+            let start = m.timestamp.max(time_start);
+            let end = next_time.min(time_end);
+            if end > start {
+                preintegration.integrate(m.gyro, m.accel, (end - start) as Float, noise);
+            }
+        }
+        preintegration
+    }
+
+    /// Integrate one measurement, constant during `dt` seconds.
+    #[allow(clippy::similar_names)]
+    pub fn integrate(&mut self, gyro: Vec3, accel: Vec3, dt: Float, noise: &Noise) {
+        let omega = gyro - self.bias.gyro;
+        let acc = accel - self.bias.accel;
+        let d_rot = so3::exp(omega * dt);
+        let d_rot_mat = d_rot.to_rotation_matrix().into_inner();
+        let right_jac = so3::right_jacobian(omega * dt);
+        let rot = self.delta_rotation.to_rotation_matrix().into_inner();
+        let rot_acc_hat = rot * so3::hat(acc);
+
+        // Covariance propagation of [rotation, velocity, position] errors.
+        let mut a = Mat9::identity();
+        a.fixed_slice_mut::<U3, U3>(0, 0)
+            .copy_from(&d_rot_mat.transpose());
+        a.fixed_slice_mut::<U3, U3>(3, 0)
+            .copy_from(&(-rot_acc_hat * dt));
+        a.fixed_slice_mut::<U3, U3>(6, 0)
+            .copy_from(&(-0.5 * rot_acc_hat * dt * dt));
+        a.fixed_slice_mut::<U3, U3>(6, 3)
+            .copy_from(&(Mat3::identity() * dt));
+        let gyro_var = noise.gyro * noise.gyro / dt;
+        let accel_var = noise.accel * noise.accel / dt;
+        let mut noise_cov = Mat9::zeros();
+        noise_cov
+            .fixed_slice_mut::<U3, U3>(0, 0)
+            .copy_from(&(right_jac * right_jac.transpose() * (gyro_var * dt * dt)));
+        // The accelerometer noise is rotated by the (orthonormal) delta rotation.
+        let dt2 = dt * dt;
+        noise_cov
+            .fixed_slice_mut::<U3, U3>(3, 3)
+            .copy_from(&(Mat3::identity() * (accel_var * dt2)));
+        noise_cov
+            .fixed_slice_mut::<U3, U3>(3, 6)
+            .copy_from(&(Mat3::identity() * (0.5 * accel_var * dt2 * dt)));
+        noise_cov
+            .fixed_slice_mut::<U3, U3>(6, 3)
+            .copy_from(&(Mat3::identity() * (0.5 * accel_var * dt2 * dt)));
+        noise_cov
+            .fixed_slice_mut::<U3, U3>(6, 6)
+            .copy_from(&(Mat3::identity() * (0.25 * accel_var * dt2 * dt2)));
+        self.covariance = a * self.covariance * a.transpose() + noise_cov;
+
+        // Deltas, using the previous rotation and velocity.
+        self.delta_position += self.delta_velocity * dt + 0.5 * (rot * acc) * dt * dt;
+        self.delta_velocity += rot * acc * dt;
+        self.delta_rotation = renormalize(self.delta_rotation * d_rot);
+        self.delta_t += dt;
+    }
+
+    /// Predict the state at `j` from the state at `i`, with gravity in the world frame.
+    pub fn predict(&self, state: &NavState, gravity: &Vec3) -> NavState {
+        let dt = self.delta_t;
+        let rotation = state.pose.rotation;
+        let position = state.pose.translation.vector
+            + state.velocity * dt
+            + 0.5 * gravity * dt * dt
+            + rotation * self.delta_position;
+        NavState {
+            pose: Iso3::from_parts(position.into(), renormalize(rotation * self.delta_rotation)),
+            velocity: state.velocity + gravity * dt + rotation * self.delta_velocity,
+        }
+    }
+}
+
+/// Initialization of gravity and biases from measurements of a body at rest.
+///
+/// The gyroscope bias is the mean angular velocity.
+/// The mean specific force gives the direction of gravity (in the body frame),
+/// and its difference with the gravity magnitude is attributed to the accelerometer bias.
+/// Other components of the accelerometer bias are not observable at rest.
+/// Return the gravity vector in the body frame and the biases,
+/// or `None` without measurements.
+#[allow(clippy::cast_precision_loss)]
+pub fn static_init(
+    measurements: &[ImuMeasurement],
+    gravity_magnitude: Float,
+) -> Option<(Vec3, Bias)> {
+    if measurements.is_empty() {
+        return None;
+    }
+    let nb = measurements.len() as Float;
+    let mean_gyro = measurements.iter().fold(Vec3::zeros(), |s, m| s + m.gyro) / nb;
+    let mean_accel = measurements.iter().fold(Vec3::zeros(), |s, m| s + m.accel) / nb;
+    let norm = mean_accel.norm();
+    if norm < Float::EPSILON {
+        return None;
+    }
+    let up = mean_accel / norm;
+    let bias = Bias {
+        gyro: mean_gyro,
+        accel: mean_accel - gravity_magnitude * up,
+    };
+    Some((-gravity_magnitude * up, bias))
+}
+
+/// Renormalize a unit quaternion to limit numerical drift.
+fn renormalize(rotation: UnitQuaternion<Float>) -> UnitQuaternion<Float> {
+    UnitQuaternion::from_quaternion(rotation.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::misc::helper::bounded;
+    use approx;
+    use quickcheck_macros;
+
+    const EPSILON: Float = 1e-3;
+    const RATE: f64 = 200.0;
+    const DURATION: f64 = 0.5;
+
+    #[test]
+    fn static_init_at_rest() {
+        let gravity_body = Vec3::new(0.0, -9.81, 0.0);
+        let bias = Bias {
+            gyro: Vec3::new(0.01, -0.02, 0.005),
+            accel: Vec3::zeros(),
+        };
+        let measurements = gen_measurements(bias.gyro, -gravity_body);
+        let (gravity, init_bias) = static_init(&measurements, 9.81).unwrap();
+        assert!(approx::relative_eq!(
+            gravity,
+            gravity_body,
+            epsilon = EPSILON
+        ));
+        assert!(approx::relative_eq!(
+            init_bias.gyro,
+            bias.gyro,
+            epsilon = EPSILON
+        ));
+    }
+
+    // PROPERTY TESTS ################################################
+
+    #[quickcheck_macros::quickcheck]
+    fn constant_rate_preintegration(x: Float, y: Float, z: Float, acc: Float) -> bool {
+        // With an acceleration along the rotation axis, the acceleration
+        // in the body frame at i stays constant during the integration.
+        let rate = Vec3::new(bounded(x), bounded(y), bounded(z)) * 3.0;
+        let axis = rate.try_normalize(Float::EPSILON).unwrap_or_else(Vec3::x);
+        let accel = axis * bounded(acc) * 10.0;
+        let measurements = gen_measurements(rate, accel);
+        let noise = Noise {
+            gyro: 1e-3,
+            accel: 1e-2,
+        };
+        let preintegration =
+            Preintegration::between(&measurements, 0.0, DURATION, Bias::zeros(), &noise);
+        let dt = DURATION as Float;
+        let rotation = so3::exp(rate * dt);
+        preintegration.delta_rotation.angle_to(&rotation) < EPSILON
+            && approx::relative_eq!(preintegration.delta_t, dt, epsilon = EPSILON)
+            && approx::relative_eq!(preintegration.delta_velocity, accel * dt, epsilon = EPSILON)
+            && approx::relative_eq!(
+                preintegration.delta_position,
+                0.5 * accel * dt * dt,
+                epsilon = EPSILON
+            )
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn predict_at_rest(x: Float, y: Float, z: Float) -> bool {
+        // A body at rest measures the opposite of gravity, in its own frame.
+        let rotation = so3::exp(Vec3::new(bounded(x), bounded(y), bounded(z)) * 3.0);
+        let gravity = Vec3::new(0.0, 0.0, -9.81);
+        let measurements = gen_measurements(Vec3::zeros(), -(rotation.inverse() * gravity));
+        let noise = Noise {
+            gyro: 1e-3,
+            accel: 1e-2,
+        };
+        let preintegration =
+            Preintegration::between(&measurements, 0.0, DURATION, Bias::zeros(), &noise);
+        let state = NavState {
+            pose: Iso3::from_parts(Vec3::new(1.0, 2.0, 3.0).into(), rotation),
+            velocity: Vec3::zeros(),
+        };
+        let predicted = preintegration.predict(&state, &gravity);
+        approx::relative_eq!(predicted.velocity, Vec3::zeros(), epsilon = EPSILON)
+            && approx::relative_eq!(
+                predicted.pose.translation.vector,
+                state.pose.translation.vector,
+                epsilon = EPSILON
+            )
+            && predicted.pose.rotation.angle_to(&rotation) < EPSILON
+    }
+
+    // GENERATORS ####################################################
+
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn gen_measurements(gyro: Vec3, accel: Vec3) -> Vec<ImuMeasurement> {
+        let nb = (DURATION * RATE) as usize;
+        (0..nb)
+            .map(|k| ImuMeasurement {
+                timestamp: k as f64 / RATE,
+                gyro,
+                accel,
+            })
+            .collect()
+    }
+}
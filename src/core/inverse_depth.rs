@@ -41,8 +41,26 @@ pub fn to_depth(scale: Float, idepth: InverseDepth) -> u16 {
     }
 }
 
+/// Fuse a new measurement `(inverse_depth, variance)` into an inverse depth,
+/// as the update step of a one dimensional Kalman filter.
+///
+/// Unknown or discarded values are simply replaced by the measurement.
+pub fn kalman_update(idepth: InverseDepth, (d2, v2): (Float, Float)) -> InverseDepth {
+    match idepth {
+        InverseDepth::WithVariance(d1, v1) => {
+            let sum = v1 + v2;
+            InverseDepth::WithVariance((d1 * v2 + d2 * v1) / sum, v1 * v2 / sum)
+        }
+        _ => InverseDepth::WithVariance(d2, v2),
+    }
+}
+
 // Merging strategies ######################################
 
+/// Strategy merging the known `(inverse_depth, variance)` values of a bloc,
+/// such as `strategy_dso_mean`.
+pub type Strategy = fn(&[(Float, Float)]) -> InverseDepth;
+
 /// Fuse 4 inverse depth pixels of a bloc with a given merging strategy.
 ///
 /// It only keeps the known values and passes them as a `Vec` into the given strategy.
@@ -97,6 +115,31 @@ pub fn strategy_dso_mean(valid_values: &[(Float, Float)]) -> InverseDepth {
     }
 }
 
+/// Merge idepth pixels of a bloc into their mean weighted by the inverse variances.
+///
+/// The variance of the weighted mean is increased by the weighted dispersion
+/// of the inverse depths, such that a bloc spanning a depth discontinuity
+/// is less trusted than a bloc on a single surface.
+/// Variances are clamped to `Float::EPSILON` to avoid infinite weights.
+pub fn strategy_inverse_variance(valid_values: &[(Float, Float)]) -> InverseDepth {
+    if valid_values.is_empty() {
+        return InverseDepth::Unknown;
+    }
+    let clamped: Vec<(Float, Float)> = valid_values
+        .iter()
+        .map(|&(d, v)| (d, v.max(Float::EPSILON)))
+        .collect();
+    let valid_values = clamped.as_slice();
+    let sum_w: Float = valid_values.iter().map(|(_, v)| 1.0 / v).sum();
+    let mean = valid_values.iter().map(|(d, v)| d / v).sum::<Float>() / sum_w;
+    let dispersion = valid_values
+        .iter()
+        .map(|(d, v)| (d - mean).powi(2) / v)
+        .sum::<Float>()
+        / sum_w;
+    InverseDepth::WithVariance(mean, 1.0 / sum_w + dispersion)
+}
+
 /// Only merge inverse depths that are statistically similar.
 /// Others are discarded.
 ///
@@ -150,3 +193,27 @@ pub fn strategy_statistically_similar(valid_values: &[(Float, Float)]) -> Invers
         _ => InverseDepth::Unknown,
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn inverse_variance_null_variance() {
+        let fused = strategy_inverse_variance(&[(0.5, 0.0), (0.6, 1e-2)]);
+        match fused {
+            InverseDepth::WithVariance(idepth, variance) => {
+                assert!(idepth.is_finite() && variance.is_finite());
+                assert!((idepth - 0.5).abs() < 1e-3);
+            }
+            _ => panic!("Expected a known inverse depth"),
+        }
+    }
+
+    #[test]
+    fn inverse_variance_same_values() {
+        let fused = strategy_inverse_variance(&[(0.5, 2e-2), (0.5, 2e-2)]);
+        assert_eq!(fused, InverseDepth::WithVariance(0.5, 1e-2));
+    }
+}
@@ -0,0 +1,443 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Loop closure detection with a database of keyframes appearances.
+//!
+//! Each keyframe is described by a small thumbnail of its image,
+//! normalized to zero mean and unit norm, and two keyframes are compared
+//! by the normalized cross correlation of their thumbnails.
+//! Candidates for a new keyframe are the most similar older keyframes,
+//! which are then geometrically verified by a direct alignment
+//! of the old keyframe points into the new keyframe image.
+//! The resulting relative poses can be added as loop closure edges of a pose graph.
+//!
+//! Interesting reads:
+//! - Klein and Murray, Improving the agility of keyframe-based SLAM, ECCV 2008.
+//! - Gao et al., LDSO: Direct sparse odometry with loop closure, IROS 2018.
+
+use nalgebra::{DMatrix, U6};
+
+use crate::core::camera::{CameraModel, Intrinsics};
+use crate::core::track::inverse_compositional::Levels;
+use crate::core::track::lm_optimizer::{self, AffineBrightness, LMConfig, LMOptimizerState};
+use crate::math::optimizer::State as _;
+use crate::math::robust::RobustLoss;
+use crate::math::se3;
+use crate::misc::type_aliases::{Float, Iso3, Mat6, Mat8, Vec6};
+
+/// Configuration of the loop closure detection.
+pub struct Config {
+    /// Number of rows and columns of the thumbnails describing keyframes.
+    pub thumbnail_size: (usize, usize),
+    /// Min normalized cross correlation of thumbnails for a loop closure candidate.
+    pub min_similarity: Float,
+    /// Number of most recent keyframes excluded from the candidates,
+    /// since they trivially look like the new keyframe.
+    pub min_keyframe_gap: usize,
+    /// Max number of candidates geometrically verified for each new keyframe.
+    pub max_candidates: usize,
+    /// Finest level of the multi-resolution pyramids kept for the verification.
+    /// Finer levels are dropped to save memory.
+    pub min_level: usize,
+    /// Robust loss weighting the photometric residuals of the verification.
+    pub robust_loss: Box<dyn RobustLoss>,
+    /// Parameters of the Levenberg-Marquardt optimizer of the verification.
+    pub lm_config: LMConfig,
+    /// Max final energy of the verification on the finest kept level.
+    pub max_energy: Float,
+    /// Min ratio of old keyframe points warped inside the new keyframe image
+    /// on the finest kept level.
+    pub min_visible_ratio: Float,
+}
+
+/// Data of one level of a keyframe, needed for the direct alignment.
+pub struct KeyframeLevel<C: CameraModel = Intrinsics> {
+    /// Camera model at this level.
+    pub intrinsics: C,
+    /// Image at this level.
+    pub image: DMatrix<u8>,
+    /// Coordinates of the candidate points with known inverse depths.
+    pub coordinates: Vec<(usize, usize)>,
+    /// Inverse depths of the candidate points.
+    pub idepths: Vec<Float>,
+    /// Precomputed jacobians of the candidate points.
+    pub jacobians: Vec<Vec6>,
+    /// Precomputed hessians of the candidate points.
+    pub hessians: Vec<Mat6>,
+}
+
+/// A keyframe of the database.
+pub struct Keyframe<C: CameraModel = Intrinsics> {
+    /// Timestamp of the keyframe (of depth image).
+    pub timestamp: f64,
+    /// Pose of the keyframe when it was added.
+    pub pose: Iso3,
+    /// Normalized thumbnail describing the keyframe appearance.
+    pub descriptor: DMatrix<Float>,
+    /// Multi-resolution data, from the configured `min_level`.
+    pub levels: Levels<KeyframeLevel<C>>,
+}
+
+/// A verified loop closure between an old keyframe and a new one.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LoopClosure {
+    /// Index of the old keyframe in the database.
+    pub from: usize,
+    /// Index of the new keyframe in the database.
+    pub to: usize,
+    /// Relative pose `pose_from^-1 * pose_to`.
+    pub measurement: Iso3,
+    /// Information matrix of the relative pose, as for pose graph edges.
+    pub information: Mat6,
+    /// Similarity of the keyframes thumbnails.
+    pub similarity: Float,
+    /// Final energy of the verification.
+    pub energy: Float,
+}
+
+/// Database of keyframes, indexed in insertion order.
+/// If keyframes are also added to a pose graph in the same order,
+/// database indices are the pose graph node indices.
+pub struct Database<C: CameraModel = Intrinsics> {
+    config: Config,
+    keyframes: Vec<Keyframe<C>>,
+}
+
+impl<C: CameraModel> Database<C> {
+    /// Create an empty database.
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            keyframes: Vec::new(),
+        }
+    }
+
+    /// Configuration of the detection.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Keyframes of the database, oldest first.
+    pub fn keyframes(&self) -> &[Keyframe<C>] {
+        &self.keyframes
+    }
+
+    /// Detect loop closures of a new keyframe with the keyframes of the database,
+    /// then add it to the database.
+    pub fn add(&mut self, keyframe: Keyframe<C>) -> Vec<LoopClosure> {
+        let to = self.keyframes.len();
+        let closures = self
+            .query(&keyframe.descriptor)
+            .into_iter()
+            .take(self.config.max_candidates)
+            .filter_map(|(from, similarity)| {
+                self.verify(from, &keyframe)
+                    .map(|(measurement, information, energy)| LoopClosure {
+                        from,
+                        to,
+                        measurement,
+                        information,
+                        similarity,
+                        energy,
+                    })
+            })
+            .collect();
+        self.keyframes.push(keyframe);
+        closures
+    }
+
+    /// Keyframes similar to a descriptor, most similar first,
+    /// excluding the most recent keyframes.
+    pub fn query(&self, descriptor: &DMatrix<Float>) -> Vec<(usize, Float)> {
+        let nb_candidates = self
+            .keyframes
+            .len()
+            .saturating_sub(self.config.min_keyframe_gap);
+        let mut candidates: Vec<(usize, Float)> = self.keyframes[..nb_candidates]
+            .iter()
+            .enumerate()
+            .map(|(i, kf)| (i, similarity(&kf.descriptor, descriptor)))
+            .filter(|(_, s)| *s >= self.config.min_similarity)
+            .collect();
+        candidates.sort_by(|(_, s1), (_, s2)| s2.partial_cmp(s1).unwrap());
+        candidates
+    }
+
+    /// Geometric verification of a loop closure candidate,
+    /// by a coarse to fine direct alignment of the points of the old keyframe
+    /// into the image of the new keyframe, starting from the identity.
+    ///
+    /// Return the relative pose, its information matrix and the final energy
+    /// if the alignment converged with a low energy and enough visible points.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn verify(&self, from: usize, keyframe: &Keyframe<C>) -> Option<(Iso3, Mat6, Float)> {
+        let old = &self.keyframes[from];
+        let mut model = lm_optimizer::Model {
+            motion: Iso3::identity(),
+            affine: AffineBrightness::identity(),
+        };
+        let mut energy = Float::INFINITY;
+        let mut nb_residuals = 0;
+        let mut hessian = Mat8::zeros();
+        for (level, new_level) in old.levels.iter().zip(keyframe.levels.iter()).rev() {
+            let obs = lm_optimizer::Obs {
+                intrinsics: &level.intrinsics,
+                template: &level.image,
+                image: &new_level.image,
+                coordinates: &level.coordinates,
+                _z_candidates: &level.idepths,
+                jacobians: &level.jacobians,
+                hessians: &level.hessians,
+                robust_loss: self.config.robust_loss.as_ref(),
+                fix_affine_brightness: false,
+                motion_prior: None,
+                lm_config: self.config.lm_config,
+                depth: None,
+                rolling_shutter: None,
+                idepth_uncertainty: None,
+            };
+            let eval_data = LMOptimizerState::iterative_solve(&obs, model)
+                .ok()?
+                .0
+                .eval_data;
+            if !eval_data.energy.is_finite() {
+                return None;
+            }
+            model = eval_data.model;
+            energy = eval_data.energy;
+            nb_residuals = eval_data.nb_residuals;
+            hessian = eval_data.hessian;
+        }
+        let nb_points = old.levels.first()?.coordinates.len();
+        let visible_ratio = nb_residuals as Float / nb_points as Float;
+        if energy > self.config.max_energy || visible_ratio < self.config.min_visible_ratio {
+            return None;
+        }
+
+        // The motion brings points from the old keyframe to the new one,
+        // and its covariance is expressed in the old keyframe frame,
+        // since the inverse compositional step is `motion * exp(-delta)`.
+        let measurement = model.motion.inverse();
+        let covariance = (hessian / energy).try_inverse()?;
+        let motion_covariance: Mat6 = covariance.fixed_slice::<U6, U6>(0, 0).into_owned();
+        let adj = se3::adjoint(model.motion);
+        let information = (adj * motion_covariance * adj.transpose()).try_inverse()?;
+        Some((measurement, information, energy))
+    }
+}
+
+/// Normalized thumbnail of an image, by averaging blocks of pixels.
+/// The thumbnail has zero mean and unit norm, for robustness to brightness changes.
+#[allow(clippy::cast_precision_loss)]
+pub fn thumbnail(img: &DMatrix<u8>, (nb_rows, nb_cols): (usize, usize)) -> DMatrix<Float> {
+    let (img_rows, img_cols) = img.shape();
+    let mut thumbnail = DMatrix::from_fn(nb_rows, nb_cols, |r, c| {
+        let (r_start, r_end) = (r * img_rows / nb_rows, (r + 1) * img_rows / nb_rows);
+        let (c_start, c_end) = (c * img_cols / nb_cols, (c + 1) * img_cols / nb_cols);
+        let block = img.slice((r_start, c_start), (r_end - r_start, c_end - c_start));
+        let sum: Float = block.iter().map(|&x| Float::from(x)).sum();
+        sum / block.len().max(1) as Float
+    });
+    let mean = thumbnail.mean();
+    thumbnail.apply(|x| x - mean);
+    let norm = thumbnail.norm();
+    if norm > Float::EPSILON {
+        thumbnail /= norm;
+    }
+    thumbnail
+}
+
+/// Normalized cross correlation of two normalized thumbnails, in [-1, 1].
+pub fn similarity(a: &DMatrix<Float>, b: &DMatrix<Float>) -> Float {
+    a.dot(b)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::core::depth_noise;
+    use crate::core::inverse_depth;
+    use crate::core::track::inverse_compositional::{self, Tracker};
+    use crate::core::track::keyframe::OpticalFlow;
+    use crate::core::track::motion_model::MotionModel;
+    use crate::math::robust::Squared;
+    use crate::misc::helper::gen_texture;
+    use crate::misc::type_aliases::Vec3;
+    use approx;
+
+    #[test]
+    fn similarity_of_thumbnails() {
+        let img = gen_texture(60, 80);
+        let descriptor = thumbnail(&img, (6, 8));
+        assert!((similarity(&descriptor, &descriptor) - 1.0).abs() < 1e-5);
+        // Thumbnails are robust to affine brightness changes.
+        let darker = img.map(|x| x / 2 + 10);
+        assert!(similarity(&descriptor, &thumbnail(&darker, (6, 8))) > 0.99);
+        // Similarity drops for a different image, or the mirrored one.
+        assert!(similarity(&descriptor, &thumbnail(&gen_other(), (6, 8))) < 0.5);
+        let mirrored = DMatrix::from_fn(60, 80, |y, x| img[(y, 79 - x)]);
+        assert!(similarity(&descriptor, &thumbnail(&mirrored, (6, 8))) < 0.5);
+    }
+
+    #[test]
+    fn query_ranks_older_keyframes() {
+        let mut config = gen_config();
+        config.min_keyframe_gap = 2;
+        let mut database = Database::new(config);
+        let query = DMatrix::from_row_slice(1, 4, &[0.5, 0.5, -0.5, -0.5]);
+        let descriptors = [
+            [0.5, 0.5, -0.5, -0.5],
+            [-0.5, -0.5, 0.5, 0.5],
+            [0.5, 0.6, -0.5, -0.6],
+            [Float::NAN; 4],
+            [0.8, 0.5, -0.8, -0.5],
+            // Recent keyframes, too close to the query to be candidates.
+            [0.5, 0.5, -0.5, -0.5],
+            [0.5, 0.5, -0.5, -0.5],
+        ];
+        for descriptor in descriptors.iter() {
+            let descriptor = DMatrix::from_row_slice(1, 4, descriptor).normalize();
+            database.keyframes.push(gen_descriptor_keyframe(descriptor));
+        }
+        let candidates = database.query(&query);
+        let indices: Vec<usize> = candidates.iter().map(|(i, _)| *i).collect();
+        assert_eq!(indices, vec![0, 2, 4]);
+        assert!((candidates[0].1 - 1.0).abs() < 1e-5);
+        assert!(candidates.windows(2).all(|c| c[0].1 > c[1].1));
+
+        // Without any old enough keyframe, there is no candidate.
+        database.keyframes.truncate(2);
+        assert!(database.query(&query).is_empty());
+    }
+
+    #[test]
+    fn verify_translated_keyframe() {
+        let mut database = Database::new(gen_config());
+        let texture = gen_texture(60, 80);
+        database
+            .keyframes
+            .push(gen_keyframe(&database, texture.clone()));
+        // Camera translated along x by 2 pixels at the depth of the plane.
+        let keyframe = gen_keyframe(&database, shift_columns(&texture, 2));
+        let (measurement, information, energy) = database.verify(0, &keyframe).unwrap();
+        let expected = Vec3::new(2.0 * PLANE_DEPTH / FOCAL, 0.0, 0.0);
+        assert!(approx::relative_eq!(
+            measurement.translation.vector,
+            expected,
+            epsilon = 5e-3
+        ));
+        assert!(measurement.rotation.angle() < 5e-3);
+        assert!(energy <= database.config().max_energy);
+        assert!(approx::relative_eq!(
+            information,
+            information.transpose(),
+            max_relative = 1e-3
+        ));
+        assert!(information.cholesky().is_some());
+
+        // A different image is rejected.
+        let keyframe = gen_keyframe(&database, gen_other());
+        assert!(database.verify(0, &keyframe).is_none());
+    }
+
+    #[test]
+    fn add_emits_loop_closures() {
+        let mut database = Database::new(gen_config());
+        let texture = gen_texture(60, 80);
+        assert!(database
+            .add(gen_keyframe(&database, texture.clone()))
+            .is_empty());
+        assert!(database
+            .add(gen_keyframe(&database, gen_other()))
+            .is_empty());
+        // Back at the first keyframe, the second one being too recent to be a candidate.
+        let keyframe = gen_keyframe(&database, shift_columns(&texture, 1));
+        let closures = database.add(keyframe);
+        assert_eq!(closures.len(), 1);
+        assert_eq!((closures[0].from, closures[0].to), (0, 2));
+        assert!(closures[0].similarity >= database.config().min_similarity);
+        assert_eq!(database.keyframes().len(), 3);
+        // Similar to no keyframe of the database.
+        let mirrored = DMatrix::from_fn(60, 80, |y, x| texture[(y, 79 - x)]);
+        assert!(database.add(gen_keyframe(&database, mirrored)).is_empty());
+        assert_eq!(database.keyframes().len(), 4);
+    }
+
+    // GENERATORS ####################################################
+
+    const FOCAL: Float = 60.0;
+    const PLANE_DEPTH: Float = 2.0;
+    const DEPTH_SCALE: Float = 1000.0;
+
+    fn gen_config() -> Config {
+        Config {
+            thumbnail_size: (6, 8),
+            min_similarity: 0.5,
+            min_keyframe_gap: 1,
+            max_candidates: 3,
+            min_level: 0,
+            robust_loss: Box::new(Squared),
+            lm_config: LMConfig::default(),
+            max_energy: 10.0,
+            min_visible_ratio: 0.5,
+        }
+    }
+
+    /// Keyframe of a textured fronto-parallel plane, at the identity pose.
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn gen_keyframe(database: &Database, img: DMatrix<u8>) -> Keyframe {
+        let config = inverse_compositional::Config {
+            nb_levels: 3,
+            candidates_diff_threshold: 7,
+            depth_scale: DEPTH_SCALE,
+            intrinsics: Intrinsics {
+                principal_point: (39.5, 29.5),
+                focal: (FOCAL, FOCAL),
+                skew: 0.0,
+            },
+            depth_noise: Box::new(depth_noise::ConstantIdepth(1e-4)),
+            idepth_fusion: inverse_depth::strategy_dso_mean,
+            robust_loss: Box::new(Squared),
+            fix_affine_brightness: false,
+            keyframe_policy: Box::new(OpticalFlow { threshold: 2.0 }),
+            motion_model: MotionModel::Static,
+            lm_configs: vec![LMConfig::default(); 3],
+            depth_residual_weight: None,
+            refine_keyframe_idepth: false,
+            sliding_window: None,
+            inertial: None,
+            readout_time: None,
+            photometric: None,
+            intensity_variance: None,
+        };
+        let depth = DMatrix::repeat(60, 80, (PLANE_DEPTH * DEPTH_SCALE) as u16);
+        let tracker: Tracker = config.init(0.0, &depth, 0.0, img);
+        tracker.loop_closure_keyframe(database.config())
+    }
+
+    fn gen_descriptor_keyframe(descriptor: DMatrix<Float>) -> Keyframe {
+        Keyframe {
+            timestamp: 0.0,
+            pose: Iso3::identity(),
+            descriptor,
+            levels: Vec::new(),
+        }
+    }
+
+    /// Texture unrelated to the one of `gen_texture(60, 80)`.
+    fn gen_other() -> DMatrix<u8> {
+        let texture = gen_texture(120, 80);
+        DMatrix::from_fn(60, 80, |y, x| texture[(60 + y, x)])
+    }
+
+    /// Image shifted to the left by `shift` columns, repeating the last column.
+    fn shift_columns(img: &DMatrix<u8>, shift: usize) -> DMatrix<u8> {
+        let (nb_rows, nb_cols) = img.shape();
+        DMatrix::from_fn(nb_rows, nb_cols, |y, x| {
+            img[(y, (x + shift).min(nb_cols - 1))]
+        })
+    }
+}
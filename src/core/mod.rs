@@ -4,9 +4,19 @@
 
 //! Core functionalities of Visual Odometry Rust.
 
+pub mod calibration;
 pub mod camera;
 pub mod candidates;
+pub mod depth_noise;
+pub mod epipolar;
 pub mod gradient;
+pub mod imu;
 pub mod inverse_depth;
+pub mod loop_closure;
 pub mod multires;
+pub mod photometric;
+pub mod pose_graph;
+pub mod registration;
+pub mod stereo;
 pub mod track;
+pub mod undistort;
@@ -0,0 +1,406 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Offline estimation of the response and vignette from a tracked sequence.
+//!
+//! Scene points are observed in several frames of the sequence,
+//! at different positions in the image and with different known exposures,
+//! as given by the camera driver of sequences recorded with auto exposure.
+//! The inverse response `U = G^-1`, the vignette `V` and the radiances `B`
+//! of the points are estimated by minimizing the energy `sum (U(I) - e * V(x) * B)^2`
+//! over all observations, with alternating least squares on each set of variables.
+//!
+//! The inverse response is non-parametric, with one value per intensity,
+//! and the vignette is a radial polynomial `1 + v1 * r^2 + v2 * r^4 + v3 * r^6`,
+//! with `r` the distance to the image center, normalized by the half diagonal.
+//! Exposure changes are required, otherwise a constant response
+//! and vignette trivially explain all observations.
+
+use nalgebra::DMatrix;
+
+use crate::core::camera::CameraModel;
+use crate::core::photometric::{Calibration, NB_INTENSITIES};
+use crate::misc::type_aliases::{Float, Iso3, Mat3, Point3, Vec3};
+
+/// Observation of a scene point in a frame.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Observation {
+    /// Index of the frame.
+    pub frame: usize,
+    /// Index of the scene point.
+    pub point: usize,
+    /// Pixel coordinates `(x, y)` of the observation.
+    pub pixel: (usize, usize),
+    /// Measured intensity.
+    pub intensity: u8,
+}
+
+/// Configuration of the estimation.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Config {
+    /// Max number of alternating iterations.
+    pub max_iterations: usize,
+    /// Stop when the energy decreases less than this ratio of the energy.
+    pub min_relative_energy_decrease: Float,
+    /// Observations with an intensity above this one are considered saturated and ignored.
+    pub max_intensity: u8,
+}
+
+/// Result of the estimation.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Estimate {
+    /// Estimated photometric calibration.
+    pub calibration: Calibration,
+    /// Coefficients `(v1, v2, v3)` of the vignette polynomial.
+    pub vignette_coefficients: (Float, Float, Float),
+    /// Radiance of each scene point, in the unit of the calibrated irradiances
+    /// for an exposure of 1.
+    pub radiances: Vec<Float>,
+    /// Root mean square residual, in intensities.
+    pub rms_error: Float,
+    /// Number of alternating iterations.
+    pub nb_iterations: usize,
+}
+
+/// Observe points (in the world frame) in a tracked frame,
+/// given the pose of the camera in the world frame.
+/// Points projected outside of the image are not observed.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+#[allow(clippy::cast_precision_loss)]
+pub fn observe<C: CameraModel>(
+    camera: &C,
+    points: &[Point3],
+    pose: &Iso3,
+    frame: usize,
+    img: &DMatrix<u8>,
+    observations: &mut Vec<Observation>,
+) {
+    let (nb_rows, nb_cols) = img.shape();
+    let world_to_camera = pose.inverse();
+    for (point, p) in points.iter().enumerate() {
+        if let Some(pixel) = camera.project(&(world_to_camera * p)) {
+            let (x, y) = (pixel.x.round(), pixel.y.round());
+            if x >= 0.0 && x < nb_cols as Float && y >= 0.0 && y < nb_rows as Float {
+                let (x, y) = (x as usize, y as usize);
+                observations.push(Observation {
+                    frame,
+                    point,
+                    pixel: (x, y),
+                    intensity: img[(y, x)],
+                });
+            }
+        }
+    }
+}
+
+/// Estimate the photometric calibration of images of shape `(nb_rows, nb_cols)`
+/// from observations of scene points, given the exposure of each frame.
+#[allow(clippy::cast_precision_loss)]
+#[allow(clippy::many_single_char_names)]
+pub fn estimate(
+    observations: &[Observation],
+    exposures: &[Float],
+    shape: (usize, usize),
+    config: &Config,
+) -> Result<Estimate, String> {
+    let observations: Vec<&Observation> = observations
+        .iter()
+        .filter(|o| o.intensity <= config.max_intensity)
+        .collect();
+    let nb_frames = observations.iter().map(|o| o.frame + 1).max().unwrap_or(0);
+    let nb_points = observations.iter().map(|o| o.point + 1).max().unwrap_or(0);
+    if nb_frames == 0 {
+        return Err("No unsaturated observation".to_string());
+    }
+    if exposures.len() < nb_frames {
+        return Err("Some observed frames have no exposure".to_string());
+    }
+    let e = exposures;
+    let r2: Vec<Float> = observations
+        .iter()
+        .map(|o| squared_radius(shape, o.pixel))
+        .collect();
+    let mut u: Vec<Float> = (0..NB_INTENSITIES).map(|i| i as Float).collect();
+    let mut v = Vec3::zeros();
+    let mut b = vec![0.0; nb_points];
+
+    let mut energy = Float::INFINITY;
+    let mut nb_iterations = 0;
+    while nb_iterations < config.max_iterations {
+        nb_iterations += 1;
+        let vignette: Vec<Float> = r2.iter().map(|&r2| vignette_at(&v, r2)).collect();
+
+        // Radiances of the points.
+        let mut num = vec![0.0; nb_points];
+        let mut den = vec![0.0; nb_points];
+        for (o, &vig) in observations.iter().zip(vignette.iter()) {
+            let ev = e[o.frame] * vig;
+            num[o.point] += ev * u[o.intensity as usize];
+            den[o.point] += ev * ev;
+        }
+        for (b_p, (n, d)) in b.iter_mut().zip(num.iter().zip(den.iter())) {
+            *b_p = if *d > 0.0 { n / d } else { 0.0 };
+        }
+
+        // Inverse response, scaled to keep the irradiance of the max intensity.
+        let mut num = vec![0.0; NB_INTENSITIES];
+        let mut count = vec![0_usize; NB_INTENSITIES];
+        for (o, &vig) in observations.iter().zip(vignette.iter()) {
+            num[o.intensity as usize] += e[o.frame] * vig * b[o.point];
+            count[o.intensity as usize] += 1;
+        }
+        let observed: Vec<Option<Float>> = num
+            .iter()
+            .zip(count.iter())
+            .map(|(n, &c)| if c > 0 { Some(n / c as Float) } else { None })
+            .collect();
+        u = increasing(&fill_unobserved(&observed));
+        let scale = Float::from(config.max_intensity) / u[config.max_intensity as usize];
+        if scale.is_finite() && scale > 0.0 {
+            u.iter_mut().for_each(|u_k| *u_k *= scale);
+            b.iter_mut().for_each(|b_p| *b_p *= scale);
+        }
+
+        // Vignette coefficients, by linear least squares.
+        let mut hessian = Mat3::zeros();
+        let mut gradient = Vec3::zeros();
+        for (o, &r2) in observations.iter().zip(r2.iter()) {
+            let eb = e[o.frame] * b[o.point];
+            let jacobian = eb * Vec3::new(r2, r2 * r2, r2 * r2 * r2);
+            hessian += jacobian * jacobian.transpose();
+            gradient += jacobian * (u[o.intensity as usize] - eb);
+        }
+        if let Some(cholesky) = hessian.cholesky() {
+            v = cholesky.solve(&gradient);
+        }
+
+        // Energy of the new estimates.
+        let new_energy: Float = observations
+            .iter()
+            .zip(r2.iter())
+            .map(|(o, &r2)| {
+                let residual =
+                    u[o.intensity as usize] - e[o.frame] * vignette_at(&v, r2) * b[o.point];
+                residual * residual
+            })
+            .sum();
+        let d_energy = energy - new_energy;
+        energy = new_energy;
+        if d_energy <= config.min_relative_energy_decrease * energy {
+            break;
+        }
+    }
+
+    let (nb_rows, nb_cols) = shape;
+    let vignette = DMatrix::from_fn(nb_rows, nb_cols, |y, x| {
+        vignette_at(&v, squared_radius(shape, (x, y))).max(0.0)
+    });
+
+    // Radiances in the unit of the normalized response and vignette.
+    let radiance_scale = 255.0 / u[NB_INTENSITIES - 1] * vignette.max();
+    Ok(Estimate {
+        calibration: Calibration::new(u, vignette)?,
+        vignette_coefficients: (v.x, v.y, v.z),
+        radiances: b.iter().map(|b_p| b_p * radiance_scale).collect(),
+        rms_error: (energy / observations.len() as Float).sqrt(),
+        nb_iterations,
+    })
+}
+
+/// Squared distance of a pixel to the image center, normalized by the half diagonal.
+#[allow(clippy::cast_precision_loss)]
+fn squared_radius((nb_rows, nb_cols): (usize, usize), (x, y): (usize, usize)) -> Float {
+    let (cx, cy) = (
+        0.5 * (nb_cols as Float - 1.0),
+        0.5 * (nb_rows as Float - 1.0),
+    );
+    let (dx, dy) = (x as Float - cx, y as Float - cy);
+    (dx * dx + dy * dy) / (cx * cx + cy * cy)
+}
+
+/// Vignette polynomial at a squared radius.
+fn vignette_at(v: &Vec3, r2: Float) -> Float {
+    1.0 + r2 * (v.x + r2 * (v.y + r2 * v.z))
+}
+
+/// Linear interpolation of the values of unobserved intensities,
+/// and linear extrapolation below and above the observed ones.
+/// A single observed value is extrapolated through 0,
+/// or kept constant if it is the value of intensity 0.
+#[allow(clippy::cast_precision_loss)]
+fn fill_unobserved(observed: &[Option<Float>]) -> Vec<Float> {
+    let known: Vec<(usize, Float)> = observed
+        .iter()
+        .enumerate()
+        .filter_map(|(k, value)| value.map(|v| (k, v)))
+        .collect();
+    let line = |(k0, v0): (usize, Float), (k1, v1): (usize, Float), k: usize| {
+        v0 + (v1 - v0) * (k as Float - k0 as Float) / (k1 as Float - k0 as Float)
+    };
+    (0..observed.len())
+        .map(|k| match (observed[k], known.len()) {
+            (Some(value), _) => value,
+            (None, 0) => k as Float,
+            (None, 1) if known[0].0 == 0 => known[0].1,
+            (None, 1) => known[0].1 * k as Float / known[0].0 as Float,
+            (None, n) => {
+                let next = known.iter().position(|&(i, _)| i >= k).unwrap_or(n - 1);
+                let next = next.max(1);
+                line(known[next - 1], known[next], k)
+            }
+        })
+        .collect()
+}
+
+/// Make values increasing, with a running max starting at 0.
+fn increasing(values: &[Float]) -> Vec<Float> {
+    let mut max = 0.0;
+    values
+        .iter()
+        .map(|&value| {
+            max = value.max(max);
+            max
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use quickcheck_macros;
+
+    #[test]
+    #[allow(clippy::cast_precision_loss)]
+    fn estimate_recovers_synthetic_calibration() {
+        let shape = (60, 80);
+        let (observations, exposures) = gen_observations(shape);
+        let config = Config {
+            max_iterations: 100,
+            min_relative_energy_decrease: 1e-6,
+            max_intensity: 250,
+        };
+        let estimate = estimate(&observations, &exposures, shape, &config).unwrap();
+        assert!(estimate.rms_error < 1.0);
+        let (v1, v2, v3) = estimate.vignette_coefficients;
+        let (true_v1, true_v2, true_v3) = TRUE_VIGNETTE;
+        assert!((v1 - true_v1).abs() < 0.02);
+        assert!((v2 - true_v2).abs() < 0.02);
+        assert!((v3 - true_v3).abs() < 0.02);
+        let calibration = estimate.calibration;
+        for (intensity, u) in calibration.inverse_response.iter().enumerate() {
+            let expected = true_inverse_response(intensity as Float);
+            assert!((u - expected).abs() < 1.0 + 0.01 * expected);
+        }
+        let true_v = Vec3::new(true_v1, true_v2, true_v3);
+        for y in 0..shape.0 {
+            for x in 0..shape.1 {
+                let expected = vignette_at(&true_v, squared_radius(shape, (x, y)));
+                assert!((calibration.vignette[(y, x)] - expected).abs() < 0.02);
+            }
+        }
+    }
+
+    #[test]
+    fn fill_unobserved_interpolates() {
+        let observed = [None, Some(2.0), None, None, Some(8.0), None];
+        assert_eq!(
+            fill_unobserved(&observed),
+            vec![0.0, 2.0, 4.0, 6.0, 8.0, 10.0]
+        );
+    }
+
+    #[test]
+    fn fill_unobserved_single_value() {
+        let observed = [None, None, Some(4.0), None];
+        assert_eq!(fill_unobserved(&observed), vec![0.0, 2.0, 4.0, 6.0]);
+    }
+
+    #[test]
+    fn fill_unobserved_single_value_at_zero() {
+        assert_eq!(fill_unobserved(&[Some(1.0), None]), vec![1.0, 1.0]);
+    }
+
+    #[test]
+    fn fill_unobserved_nothing() {
+        assert_eq!(fill_unobserved(&[None, None, None]), vec![0.0, 1.0, 2.0]);
+    }
+
+    // PROPERTY TESTS ################################################
+
+    #[quickcheck_macros::quickcheck]
+    fn increasing_is_running_max(values: Vec<Float>) -> bool {
+        let values: Vec<Float> = values.into_iter().filter(|v| v.is_finite()).collect();
+        let result = increasing(&values);
+        result.len() == values.len()
+            && result.windows(2).all(|w| w[0] <= w[1])
+            && result
+                .iter()
+                .zip(values.iter())
+                .all(|(&r, &v)| r >= v && r >= 0.0)
+            && result.iter().all(|&r| r == 0.0 || values.contains(&r))
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn fill_unobserved_keeps_observed(values: Vec<Option<Float>>) -> bool {
+        let values: Vec<Option<Float>> = values
+            .into_iter()
+            .map(|v| v.filter(|x| x.is_finite()).map(|x| x % 1000.0))
+            .collect();
+        let filled = fill_unobserved(&values);
+        filled.len() == values.len()
+            && filled
+                .iter()
+                .zip(values.iter())
+                .all(|(f, v)| v.is_none_or(|x| x == *f))
+    }
+
+    // GENERATORS ####################################################
+
+    /// Vignette coefficients of the synthetic camera.
+    const TRUE_VIGNETTE: (Float, Float, Float) = (-0.3, 0.1, -0.05);
+
+    /// Gamma inverse response of the synthetic camera.
+    fn true_inverse_response(intensity: Float) -> Float {
+        255.0 * (intensity / 255.0).powf(2.2)
+    }
+
+    /// Observations of scene points with random radiances, at random pixels
+    /// of frames with exposures varying from 1 to 4.
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::cast_sign_loss)]
+    fn gen_observations(shape: (usize, usize)) -> (Vec<Observation>, Vec<Float>) {
+        let mut seed: u32 = 42;
+        let mut random = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            Float::from((seed >> 8) as u16) / 65536.0
+        };
+        let nb_frames = 10;
+        let exposures: Vec<Float> = (0..nb_frames)
+            .map(|f| 2.0_f32.powf(2.0 * f as Float / (nb_frames - 1) as Float))
+            .collect();
+        let (true_v1, true_v2, true_v3) = TRUE_VIGNETTE;
+        let true_v = Vec3::new(true_v1, true_v2, true_v3);
+        let mut observations = Vec::new();
+        for point in 0..400 {
+            let radiance = 1.0 + 249.0 * random();
+            for (frame, exposure) in exposures.iter().enumerate() {
+                let x = (random() * shape.1 as Float) as usize;
+                let y = (random() * shape.0 as Float) as usize;
+                let irradiance =
+                    exposure * vignette_at(&true_v, squared_radius(shape, (x, y))) * radiance;
+                let intensity = 255.0 * (irradiance.min(255.0) / 255.0).powf(1.0 / 2.2);
+                observations.push(Observation {
+                    frame,
+                    point,
+                    pixel: (x, y),
+                    intensity: intensity.round() as u8,
+                });
+            }
+        }
+        (observations, exposures)
+    }
+}
@@ -0,0 +1,137 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Photometric calibration of a camera: response function and vignetting.
+//!
+//! The intensity `I` measured at pixel `x` for a scene point of radiance `B`
+//! is modeled as `I = G(e * V(x) * B)`, where `G` is the nonlinear response function,
+//! `e` the exposure time and `V` the vignette, i.e. the attenuation of the lens.
+//! Correcting an image with the inverse response and the vignette
+//! makes intensities proportional to the scene radiance, up to the exposure,
+//! which is compensated by the affine brightness of the tracker.
+//! Corrected 8 bits images are scaled by the min vignette of unmasked pixels,
+//! such that irradiances fit in the range of intensities without saturation.
+//!
+//! Interesting read: Engel et al., A photometrically calibrated benchmark
+//! for monocular visual odometry, arXiv 2016.
+
+pub mod estimator;
+
+use nalgebra::DMatrix;
+
+use crate::misc::type_aliases::Float;
+
+/// Number of intensity levels of 8 bits images.
+pub const NB_INTENSITIES: usize = 256;
+
+/// Min vignette value of a pixel, under which the pixel is considered masked.
+const MIN_VIGNETTE: Float = 1e-3;
+
+/// Inverse response and vignette of a camera.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Calibration {
+    /// Irradiance of each of the 256 intensities, increasing,
+    /// scaled such that the irradiance of 255 is 255.
+    pub inverse_response: Vec<Float>,
+    /// Attenuation of each pixel, with a max of 1.
+    pub vignette: DMatrix<Float>,
+    /// Scale of the corrected images, the min vignette of unmasked pixels.
+    pub gain: Float,
+}
+
+impl Calibration {
+    /// Calibration from an inverse response of 256 values and a vignette image,
+    /// both normalized by their max value.
+    pub fn new(inverse_response: Vec<Float>, vignette: DMatrix<Float>) -> Result<Self, String> {
+        if inverse_response.len() != NB_INTENSITIES {
+            return Err(format!(
+                "The inverse response has {} values instead of {}",
+                inverse_response.len(),
+                NB_INTENSITIES
+            ));
+        }
+        if inverse_response.windows(2).any(|w| w[1] < w[0]) {
+            return Err("The inverse response is not increasing".to_string());
+        }
+        let max_irradiance = inverse_response[NB_INTENSITIES - 1];
+        let max_vignette = vignette.max();
+        if max_irradiance <= 0.0 || max_vignette <= 0.0 {
+            return Err("The inverse response and vignette must be positive".to_string());
+        }
+        let vignette = vignette / max_vignette;
+        let gain = vignette
+            .iter()
+            .filter(|&&v| v >= MIN_VIGNETTE)
+            .fold(1.0, |min, &v| v.min(min));
+        Ok(Self {
+            inverse_response: inverse_response
+                .iter()
+                .map(|g| 255.0 * g / max_irradiance)
+                .collect(),
+            vignette,
+            gain,
+        })
+    }
+
+    /// Calibration with a linear response and without vignetting,
+    /// for images of shape `(nb_rows, nb_cols)`.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn identity((nb_rows, nb_cols): (usize, usize)) -> Self {
+        Self {
+            inverse_response: (0..NB_INTENSITIES).map(|i| i as Float).collect(),
+            vignette: DMatrix::repeat(nb_rows, nb_cols, 1.0),
+            gain: 1.0,
+        }
+    }
+
+    /// Irradiance of each pixel of an image, `G^-1(I) / V`, in the range of intensities
+    /// except at the image borders where it can be higher.
+    /// Masked pixels, with a null vignette, have a null irradiance.
+    pub fn irradiance(&self, img: &DMatrix<u8>) -> DMatrix<Float> {
+        assert_eq!(
+            img.shape(),
+            self.vignette.shape(),
+            "The image and vignette must have the same shape"
+        );
+        img.zip_map(&self.vignette, |intensity, v| {
+            if v < MIN_VIGNETTE {
+                0.0
+            } else {
+                self.inverse_response[intensity as usize] / v
+            }
+        })
+    }
+
+    /// Photometrically corrected image, with irradiances scaled by the gain
+    /// and rounded to 8 bits.
+    /// Since the gain is the min vignette, the brightest intensity of any unmasked pixel
+    /// is mapped to at most 255.
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    pub fn correct(&self, img: &DMatrix<u8>) -> DMatrix<u8> {
+        self.irradiance(img)
+            .map(|irradiance| (self.gain * irradiance).round().min(255.0) as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::cast_precision_loss)]
+    fn correct_without_saturation() {
+        let vignette = DMatrix::from_row_slice(2, 2, &[1.0, 0.8, 0.5, 0.0]);
+        let response: Vec<Float> = (0..NB_INTENSITIES).map(|i| (i * i) as Float).collect();
+        let calibration = Calibration::new(response, vignette).unwrap();
+        assert_eq!(calibration.gain, 0.5);
+        let img = DMatrix::repeat(2, 2, 255);
+        let corrected = calibration.correct(&img);
+        assert_eq!(
+            corrected,
+            DMatrix::from_row_slice(2, 2, &[128, 159, 255, 0])
+        );
+    }
+}
@@ -0,0 +1,238 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Import and export of pose graphs in the g2o text format,
+//! with `VERTEX_SE3:QUAT`, `EDGE_SE3:QUAT` and `FIX` lines.
+//!
+//! g2o expresses the rotation error of an edge with the vector part of a quaternion,
+//! which is half the rotation vector for small errors,
+//! so the rotation blocks of information matrices are scaled accordingly.
+//! Node ids are renumbered in order of appearance at import,
+//! and exported as node indices.
+
+use nalgebra::{Quaternion, Translation3, UnitQuaternion};
+use std::collections::HashMap;
+
+use crate::core::pose_graph::{Edge, EdgeKind, PoseGraph};
+use crate::misc::type_aliases::{Float, Iso3, Mat6};
+
+/// Write a pose graph in the g2o format.
+pub fn to_string(graph: &PoseGraph) -> String {
+    let mut lines = Vec::new();
+    for (id, node) in graph.nodes().iter().enumerate() {
+        lines.push(format!(
+            "VERTEX_SE3:QUAT {} {}",
+            id,
+            pose_to_string(&node.pose)
+        ));
+    }
+    for (id, node) in graph.nodes().iter().enumerate() {
+        if node.fixed {
+            lines.push(format!("FIX {}", id));
+        }
+    }
+    for edge in graph.edges() {
+        let information = rescale_rotation(&edge.information, 2.0);
+        let mut upper_triangle = Vec::with_capacity(21);
+        for i in 0..6 {
+            for j in i..6 {
+                upper_triangle.push(information[(i, j)].to_string());
+            }
+        }
+        lines.push(format!(
+            "EDGE_SE3:QUAT {} {} {} {}",
+            edge.from,
+            edge.to,
+            pose_to_string(&edge.measurement),
+            upper_triangle.join(" ")
+        ));
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// Parse a pose graph in the g2o format.
+///
+/// Edges between consecutive ids are considered odometry edges,
+/// the others are considered loop closures.
+/// If no node is fixed in the file, the first one is fixed.
+/// Other types of lines are ignored.
+pub fn parse(file_content: &str) -> Result<PoseGraph, String> {
+    let lines: Vec<Vec<&str>> = file_content
+        .lines()
+        .map(|line| line.split_whitespace().collect())
+        .filter(|tokens: &Vec<&str>| !tokens.is_empty())
+        .collect();
+
+    // Nodes.
+    let mut graph = PoseGraph::new();
+    let mut indices = HashMap::new();
+    for tokens in lines.iter().filter(|t| t[0] == "VERTEX_SE3:QUAT") {
+        let values = floats(tokens, 2, 7)?;
+        let id = id(tokens[1])?;
+        if indices.insert(id, graph.nodes().len()).is_some() {
+            return Err(format!("Duplicated vertex {}", id));
+        }
+        graph.add_node(pose(&values));
+    }
+    let index = |token: &str| -> Result<usize, String> {
+        let id = id(token)?;
+        indices
+            .get(&id)
+            .cloned()
+            .ok_or(format!("Unknown vertex {}", id))
+    };
+
+    // Fixed nodes.
+    let fixed: Vec<usize> = lines
+        .iter()
+        .filter(|t| t[0] == "FIX")
+        .flat_map(|t| t[1..].iter().map(|token| index(token)))
+        .collect::<Result<_, _>>()?;
+    if !fixed.is_empty() {
+        for node in 0..graph.nodes().len() {
+            graph.set_fixed(node, fixed.contains(&node));
+        }
+    }
+
+    // Edges.
+    for tokens in lines.iter().filter(|t| t[0] == "EDGE_SE3:QUAT") {
+        let values = floats(tokens, 3, 28)?;
+        let mut information = Mat6::zeros();
+        let mut k = 7;
+        for i in 0..6 {
+            for j in i..6 {
+                information[(i, j)] = values[k];
+                information[(j, i)] = values[k];
+                k += 1;
+            }
+        }
+        let kind = if id(tokens[2])? == id(tokens[1])? + 1 {
+            EdgeKind::Odometry
+        } else {
+            EdgeKind::LoopClosure
+        };
+        graph.add_edge(Edge {
+            from: index(tokens[1])?,
+            to: index(tokens[2])?,
+            measurement: pose(&values[..7]),
+            information: rescale_rotation(&information, 0.5),
+            kind,
+        });
+    }
+    Ok(graph)
+}
+
+// Helper ######################################################################
+
+/// `x y z qx qy qz qw`
+fn pose_to_string(pose: &Iso3) -> String {
+    let t = pose.translation.vector;
+    let q = pose.rotation.into_inner().coords;
+    format!("{} {} {} {} {} {} {}", t.x, t.y, t.z, q.x, q.y, q.z, q.w)
+}
+
+/// Pose from `x y z qx qy qz qw` values.
+fn pose(values: &[Float]) -> Iso3 {
+    let translation = Translation3::new(values[0], values[1], values[2]);
+    let q = Quaternion::new(values[6], values[3], values[4], values[5]);
+    Iso3::from_parts(translation, UnitQuaternion::from_quaternion(q))
+}
+
+/// Parse a vertex id.
+fn id(token: &str) -> Result<i64, String> {
+    token
+        .parse()
+        .map_err(|_| format!("Invalid vertex id {}", token))
+}
+
+/// Parse `nb` floating point values of a line, starting at the token `start`.
+fn floats(tokens: &[&str], start: usize, nb: usize) -> Result<Vec<Float>, String> {
+    if tokens.len() < start + nb {
+        return Err(format!("Expected {} values in {} line", nb, tokens[0]));
+    }
+    tokens[start..start + nb]
+        .iter()
+        .map(|token| {
+            token
+                .parse()
+                .map_err(|_| format!("Invalid value {}", token))
+        })
+        .collect()
+}
+
+/// Scale the rotation rows and columns of an information matrix.
+fn rescale_rotation(information: &Mat6, factor: Float) -> Mat6 {
+    let mut rescaled = *information;
+    for i in 0..6 {
+        for j in 0..6 {
+            if i >= 3 {
+                rescaled[(i, j)] *= factor;
+            }
+            if j >= 3 {
+                rescaled[(i, j)] *= factor;
+            }
+        }
+    }
+    rescaled
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::misc::helper::bounded;
+    use crate::misc::type_aliases::Vec3;
+    use approx;
+    use quickcheck_macros;
+
+    const EPSILON: Float = 1e-6;
+
+    #[test]
+    fn parse_unknown_vertex() {
+        let content = "VERTEX_SE3:QUAT 0 0 0 0 0 0 0 1\nFIX 1\n";
+        assert!(parse(content).is_err());
+    }
+
+    // PROPERTY TESTS ################################################
+
+    #[quickcheck_macros::quickcheck]
+    fn to_string_parse_round_trip(a1: Float, a2: Float, a3: Float, info: Float) -> bool {
+        let mut graph = PoseGraph::new();
+        let rotation = UnitQuaternion::from_euler_angles(bounded(a1), bounded(a2), bounded(a3));
+        let step = Iso3::from_parts(Translation3::from(Vec3::new(0.5, 0.1, -0.2)), rotation);
+        let mut pose = Iso3::identity();
+        for _ in 0..4 {
+            graph.add_node(pose);
+            pose *= step;
+        }
+        graph.set_fixed(0, true);
+        graph.set_fixed(2, true);
+        let mut information = Mat6::identity() * (1.0 + bounded(info).abs());
+        information[(0, 4)] = bounded(info);
+        information[(4, 0)] = bounded(info);
+        for k in 0..3 {
+            graph.add_odometry(k, k + 1, step, information);
+        }
+        graph.add_loop_closure(3, 0, step * step * step, information);
+
+        let parsed = match parse(&to_string(&graph)) {
+            Ok(parsed) => parsed,
+            Err(_) => return false,
+        };
+        let same_nodes = graph.nodes().len() == parsed.nodes().len()
+            && graph.nodes().iter().zip(parsed.nodes()).all(|(a, b)| {
+                a.fixed == b.fixed && approx::relative_eq!(a.pose, b.pose, epsilon = EPSILON)
+            });
+        let same_edges = graph.edges().len() == parsed.edges().len()
+            && graph.edges().iter().zip(parsed.edges()).all(|(a, b)| {
+                a.from == b.from
+                    && a.to == b.to
+                    && a.kind == b.kind
+                    && approx::relative_eq!(a.measurement, b.measurement, epsilon = EPSILON)
+                    && approx::relative_eq!(a.information, b.information, epsilon = EPSILON)
+            });
+        same_nodes && same_edges
+    }
+}
@@ -0,0 +1,259 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Pose graph of keyframes, with relative pose constraints
+//! from the tracking (odometry) and from loop closures.
+//!
+//! Nodes are keyframe poses in the world frame, and an edge from node `i`
+//! to node `j` measures the relative pose `pose_i^-1 * pose_j`,
+//! with an information matrix expressed in the twist coordinates of its error.
+
+pub mod g2o;
+pub mod optimizer;
+
+use crate::core::pose_graph::optimizer::{Obs, PoseGraphOptimizerState};
+use crate::core::track::lm_optimizer::LMConfig;
+use crate::math::optimizer::State as _;
+use crate::misc::type_aliases::{Float, Iso3, Mat6};
+
+/// Default parameters of the Levenberg-Marquardt optimizer of the pose graph.
+pub const DEFAULT_LM_CONFIG: LMConfig = LMConfig {
+    initial_coef: 1e-4,
+    coef_increase_factor: 10.0,
+    coef_decrease_factor: 0.1,
+    max_iterations: 50,
+    min_energy_decrease: 0.0,
+    min_relative_energy_decrease: 1e-6,
+    min_step_norm: 1e-6,
+};
+
+/// A node of the graph, usually a keyframe.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Node {
+    /// Pose of the node in the world frame.
+    pub pose: Iso3,
+    /// Fixed nodes are not modified by the optimization.
+    pub fixed: bool,
+}
+
+/// Origin of the constraint of an edge.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EdgeKind {
+    /// Relative pose between consecutive keyframes, estimated by the tracking.
+    Odometry,
+    /// Relative pose between a keyframe and an older one, found by loop closure detection.
+    LoopClosure,
+}
+
+/// A relative pose constraint between two nodes.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Edge {
+    /// Index of the node the relative pose is expressed in.
+    pub from: usize,
+    /// Index of the other node.
+    pub to: usize,
+    /// Measured relative pose `pose_from^-1 * pose_to`.
+    pub measurement: Iso3,
+    /// Information matrix (inverse covariance) of the measurement,
+    /// for the twist `log(measurement^-1 * pose_from^-1 * pose_to)`.
+    pub information: Mat6,
+    /// Origin of the constraint.
+    pub kind: EdgeKind,
+}
+
+/// Result of the optimization of the graph.
+#[derive(Debug, Clone, Copy)]
+pub struct GraphResult {
+    /// Energy before the optimization.
+    pub initial_energy: Float,
+    /// Final energy of the optimization.
+    pub energy: Float,
+    /// Number of Levenberg-Marquardt iterations.
+    pub nb_iterations: usize,
+}
+
+/// Graph of poses and relative pose constraints.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct PoseGraph {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+impl PoseGraph {
+    /// Create an empty graph.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Nodes of the graph, in insertion order.
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// Edges of the graph, in insertion order.
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// Add a node and return its index.
+    /// The first node is fixed, to remove the gauge freedom of the problem.
+    pub fn add_node(&mut self, pose: Iso3) -> usize {
+        let fixed = self.nodes.is_empty();
+        self.nodes.push(Node { pose, fixed });
+        self.nodes.len() - 1
+    }
+
+    /// Fix or free a node.
+    pub fn set_fixed(&mut self, node: usize, fixed: bool) {
+        self.nodes[node].fixed = fixed;
+    }
+
+    /// Add an odometry edge.
+    pub fn add_odometry(&mut self, from: usize, to: usize, measurement: Iso3, information: Mat6) {
+        self.add_edge(Edge {
+            from,
+            to,
+            measurement,
+            information,
+            kind: EdgeKind::Odometry,
+        });
+    }
+
+    /// Add a loop closure edge.
+    pub fn add_loop_closure(
+        &mut self,
+        from: usize,
+        to: usize,
+        measurement: Iso3,
+        information: Mat6,
+    ) {
+        self.add_edge(Edge {
+            from,
+            to,
+            measurement,
+            information,
+            kind: EdgeKind::LoopClosure,
+        });
+    }
+
+    /// Add an edge between two existing nodes.
+    pub fn add_edge(&mut self, edge: Edge) {
+        assert!(
+            edge.from < self.nodes.len() && edge.to < self.nodes.len(),
+            "Edges must connect existing nodes"
+        );
+        self.edges.push(edge);
+    }
+
+    /// Energy of the graph, i.e. the sum of squared Mahalanobis norms of the edges errors.
+    pub fn energy(&self) -> Float {
+        let poses: Vec<Iso3> = self.nodes.iter().map(|n| n.pose).collect();
+        self.edges
+            .iter()
+            .map(|edge| {
+                let e = optimizer::edge_error(edge, &poses);
+                e.dot(&(edge.information * e))
+            })
+            .sum()
+    }
+
+    /// Optimize the poses of the free nodes to best satisfy all the edges constraints.
+    /// Free nodes without any edge are not constrained, and are left unchanged.
+    ///
+    /// Nodes are left unchanged if the optimization fails.
+    pub fn optimize(&mut self, lm_config: LMConfig) -> Result<GraphResult, String> {
+        let initial_energy = self.energy();
+        let fixed: Vec<bool> = self.nodes.iter().map(|n| n.fixed).collect();
+        let obs = Obs {
+            edges: &self.edges,
+            fixed: &fixed,
+            lm_config,
+        };
+        let poses = self.nodes.iter().map(|n| n.pose).collect();
+        let (state, nb_iterations) = PoseGraphOptimizerState::iterative_solve(&obs, poses)?;
+        let eval_data = state.eval_data;
+        for (node, pose) in self.nodes.iter_mut().zip(eval_data.model) {
+            node.pose = pose;
+        }
+        Ok(GraphResult {
+            initial_energy,
+            energy: eval_data.energy,
+            nb_iterations,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::misc::type_aliases::Vec3;
+
+    #[test]
+    fn perturbed_loop_converges() {
+        let truth = gen_loop();
+        let mut graph = PoseGraph::new();
+        for (k, pose) in truth.iter().enumerate() {
+            let noise = Iso3::new(
+                Vec3::new(0.3, -0.2, 0.25) * k as Float,
+                Vec3::new(0.05, 0.1, -0.08) * k as Float,
+            );
+            graph.add_node(noise * pose);
+        }
+        for k in 0..truth.len() {
+            let (from, to) = (k, (k + 1) % truth.len());
+            let measurement = truth[from].inverse() * truth[to];
+            if to == 0 {
+                graph.add_loop_closure(from, to, measurement, Mat6::identity());
+            } else {
+                graph.add_odometry(from, to, measurement, Mat6::identity());
+            }
+        }
+        // Energy decreases at each iteration, toward 0.
+        let single_iteration = LMConfig {
+            max_iterations: 1,
+            ..DEFAULT_LM_CONFIG
+        };
+        let mut energies = vec![graph.energy()];
+        for _ in 0..10 {
+            let result = graph.optimize(single_iteration).unwrap();
+            assert_eq!(result.initial_energy, *energies.last().unwrap());
+            energies.push(result.energy);
+        }
+        assert!(energies[0] > 1.0);
+        assert!(energies.windows(2).all(|e| e[1] <= e[0]));
+        assert!(*energies.last().unwrap() < 1e-6);
+        for (node, pose) in graph.nodes().iter().zip(truth.iter()) {
+            let error = crate::math::se3::log(pose.inverse() * node.pose);
+            assert!(error.norm() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn free_node_without_edges_is_unchanged() {
+        let truth = gen_loop();
+        let mut graph = PoseGraph::new();
+        graph.add_node(truth[0]);
+        graph.add_node(Iso3::translation(0.0, 0.1, 0.0));
+        let isolated = graph.add_node(truth[2]);
+        graph.add_odometry(0, 1, truth[0].inverse() * truth[1], Mat6::identity());
+        let result = graph.optimize(DEFAULT_LM_CONFIG).unwrap();
+        assert!(result.energy < 1e-6);
+        assert_eq!(graph.nodes()[isolated].pose, truth[2]);
+    }
+
+    // GENERATORS ####################################################
+
+    /// Poses of a camera going around a square of side 2 meters, turning at each corner.
+    fn gen_loop() -> Vec<Iso3> {
+        let quarter = std::f32::consts::FRAC_PI_2 as Float;
+        (0..4)
+            .map(|k| {
+                let angle = quarter * k as Float;
+                let corner = Vec3::new(angle.cos() - angle.sin(), 0.0, angle.sin() + angle.cos());
+                Iso3::new(corner, Vec3::new(0.0, angle, 0.0))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,294 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Levenberg-Marquardt implementation of the `optimizer::State` trait
+//! for the optimization of a pose graph on the SE(3) manifold.
+//!
+//! The error of an edge from node `i` to node `j` is the twist
+//! `e = log(measurement^-1 * pose_i^-1 * pose_j)`.
+//! Poses are updated as `exp(-delta) * pose`, and with this parameterization,
+//! the jacobians of `e` are `Ad(pose_j^-1)` for node `j` and its opposite for node `i`,
+//! where the inverse of the right jacobian of SE(3) at `e` is approximated by the identity.
+
+use nalgebra::{DMatrix, DVector, U6};
+
+use crate::core::pose_graph::Edge;
+use crate::core::track::lm_optimizer::{self, LMConfig};
+use crate::math::optimizer::{self, Continue};
+use crate::math::se3;
+use crate::misc::type_aliases::{Float, Iso3, Mat6, Vec6};
+
+/// State of the Levenberg-Marquardt optimizer.
+pub struct PoseGraphOptimizerState {
+    /// Parameters of the optimizer.
+    pub config: LMConfig,
+    /// Levenberg-Marquardt hessian diagonal coefficient.
+    pub lm_coef: Float,
+    /// Whether each node is left unchanged, being fixed or without any edge.
+    pub fixed: Vec<bool>,
+    /// Data resulting of a successful model evaluation.
+    pub eval_data: EvalData,
+}
+
+/// Either a successfully constructed `EvalData`
+/// or an error containing the energy of a given model.
+///
+/// The error is returned when the new computed energy
+/// is higher than the previous iteration energy.
+pub type EvalState = Result<EvalData, Float>;
+
+/// Data resulting of a successful model evaluation.
+pub struct EvalData {
+    /// The hessian matrix of the free poses.
+    pub hessian: DMatrix<Float>,
+    /// The gradient of the free poses.
+    pub gradient: DVector<Float>,
+    /// Energy associated with the current model.
+    pub energy: Float,
+    /// Poses of all nodes at the current state of iterations.
+    pub model: Vec<Iso3>,
+}
+
+/// Precomputed data available for the optimizer iterations:
+pub struct Obs<'a> {
+    /// Edges of the graph.
+    pub edges: &'a [Edge],
+    /// Whether each node is fixed.
+    pub fixed: &'a [bool],
+    /// Parameters of the optimizer.
+    pub lm_config: LMConfig,
+}
+
+impl<'a> Obs<'a> {
+    /// Index of the parameters block of each node, `None` for fixed nodes.
+    /// Nodes without any edge are not constrained, so they are also left out
+    /// of the optimization, as if they were fixed.
+    fn blocks(&self) -> Vec<Option<usize>> {
+        let mut constrained = vec![false; self.fixed.len()];
+        for edge in self.edges.iter() {
+            constrained[edge.from] = true;
+            constrained[edge.to] = true;
+        }
+        let mut nb_free = 0;
+        self.fixed
+            .iter()
+            .zip(constrained)
+            .map(|(&fixed, constrained)| {
+                if fixed || !constrained {
+                    None
+                } else {
+                    nb_free += 1;
+                    Some(nb_free - 1)
+                }
+            })
+            .collect()
+    }
+}
+
+impl PoseGraphOptimizerState {
+    /// Compute the energy of the poses.
+    fn eval_energy(obs: &Obs, poses: &[Iso3]) -> Float {
+        obs.edges
+            .iter()
+            .map(|edge| {
+                let e = edge_error(edge, poses);
+                e.dot(&(edge.information * e))
+            })
+            .sum()
+    }
+
+    /// Fully evaluate a model.
+    fn compute_eval_data(obs: &Obs, model: Vec<Iso3>, energy: Float) -> EvalData {
+        let blocks = obs.blocks();
+        let nb_params = 6 * blocks.iter().filter(|b| b.is_some()).count();
+        let mut hessian = DMatrix::zeros(nb_params, nb_params);
+        let mut gradient = DVector::zeros(nb_params);
+        for edge in obs.edges.iter() {
+            let e = edge_error(edge, &model);
+            let (jac_from, jac_to) = edge_jacobians(edge, &model);
+            let jacobians = [(edge.from, jac_from), (edge.to, jac_to)];
+            for &(node_a, jac_a) in jacobians.iter() {
+                if let Some(a) = blocks[node_a] {
+                    let jt_info: Mat6 = jac_a.transpose() * edge.information;
+                    let g: Vec6 = jt_info * e;
+                    let mut slice = gradient.fixed_rows_mut::<U6>(6 * a);
+                    slice += g;
+                    for &(node_b, jac_b) in jacobians.iter() {
+                        if let Some(b) = blocks[node_b] {
+                            let h: Mat6 = jt_info * jac_b;
+                            let mut slice = hessian.fixed_slice_mut::<U6, U6>(6 * a, 6 * b);
+                            slice += h;
+                        }
+                    }
+                }
+            }
+        }
+        EvalData {
+            hessian,
+            gradient,
+            energy,
+            model,
+        }
+    }
+}
+
+/// `impl<'a> optimizer::State<Obs<'a>, EvalState, Vec<Iso3>, String> for PoseGraphOptimizerState`.
+impl<'a> optimizer::State<Obs<'a>, EvalState, Vec<Iso3>, String> for PoseGraphOptimizerState {
+    /// Initialize the optimizer state.
+    fn init(obs: &Obs, model: Vec<Iso3>) -> Self {
+        let energy = Self::eval_energy(obs, &model);
+        Self {
+            config: obs.lm_config,
+            lm_coef: obs.lm_config.initial_coef,
+            fixed: obs.blocks().iter().map(Option::is_none).collect(),
+            eval_data: Self::compute_eval_data(obs, model, energy),
+        }
+    }
+
+    /// Compute the step using Levenberg-Marquardt.
+    /// Apply the step in a forward compositional approach: `exp(-delta) * pose`.
+    /// May return an error at the Cholesky decomposition of the hessian.
+    fn step(&self) -> Result<Vec<Iso3>, String> {
+        let mut hessian = self.eval_data.hessian.clone();
+        for k in 0..hessian.nrows() {
+            hessian[(k, k)] *= 1.0 + self.lm_coef;
+        }
+        let cholesky = hessian
+            .cholesky()
+            .ok_or("Error at Cholesky decomposition of hessian")?;
+        let delta = cholesky.solve(&self.eval_data.gradient);
+        let mut block = 0;
+        let mut poses = self.eval_data.model.clone();
+        for (pose, &fixed) in poses.iter_mut().zip(self.fixed.iter()) {
+            if !fixed {
+                let delta_pose: Vec6 = delta.fixed_rows::<U6>(6 * block).into_owned();
+                *pose = lm_optimizer::renormalize(se3::exp(-delta_pose) * *pose);
+                block += 1;
+            }
+        }
+        Ok(poses)
+    }
+
+    /// Compute the energy of the new model.
+    /// Then, evaluate the new hessian and gradient if the energy has decreased.
+    fn eval(&self, obs: &Obs, model: Vec<Iso3>) -> EvalState {
+        let energy = Self::eval_energy(obs, &model);
+        let old_energy = self.eval_data.energy;
+        if energy > old_energy {
+            Err(energy)
+        } else {
+            Ok(Self::compute_eval_data(obs, model, energy))
+        }
+    }
+
+    /// Stop after too many iterations,
+    /// or if the energy variation or the step is too low.
+    ///
+    /// Also update the Levenberg-Marquardt coefficient
+    /// depending on if the energy increased or decreased.
+    fn stop_criterion(self, nb_iter: usize, eval_state: EvalState) -> (Self, Continue) {
+        match eval_state {
+            Err(_) => {
+                let (lm_coef, continuation) = self.config.stop(nb_iter, self.lm_coef, None);
+                (Self { lm_coef, ..self }, continuation)
+            }
+            Ok(eval_data) => {
+                let old_energy = self.eval_data.energy;
+                let step_norm = self
+                    .eval_data
+                    .model
+                    .iter()
+                    .zip(eval_data.model.iter())
+                    .map(|(old, new)| se3::log(old.inverse() * new).norm())
+                    .fold(0.0, Float::max);
+                let accepted = Some((old_energy, eval_data.energy, step_norm));
+                let (lm_coef, continuation) = self.config.stop(nb_iter, self.lm_coef, accepted);
+                let kept_state = Self {
+                    lm_coef,
+                    eval_data,
+                    ..self
+                };
+                (kept_state, continuation)
+            }
+        }
+    } // fn stop_criterion
+} // impl optimizer::State<...> for PoseGraphOptimizerState
+
+/// Error twist of an edge for the given poses of the nodes.
+pub fn edge_error(edge: &Edge, poses: &[Iso3]) -> Vec6 {
+    let relative = poses[edge.from].inverse() * poses[edge.to];
+    se3::log(edge.measurement.inverse() * relative)
+}
+
+/// Jacobians of the error of an edge with respect to the poses of its nodes
+/// `(from, to)`, for left perturbations `exp(delta) * pose`.
+/// They are exact when the error is null.
+pub fn edge_jacobians(edge: &Edge, poses: &[Iso3]) -> (Mat6, Mat6) {
+    let jac_to = se3::adjoint(poses[edge.to].inverse());
+    (-jac_to, jac_to)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::core::pose_graph::EdgeKind;
+    use crate::misc::helper::bounded;
+    use crate::misc::type_aliases::Vec3;
+    use approx;
+    use nalgebra::{Translation3, UnitQuaternion};
+    use quickcheck_macros;
+
+    // Precision of centered finite differences with f32 computations.
+    const EPSILON_FINITE_DIFF: Float = 1e-2;
+
+    // PROPERTY TESTS ################################################
+
+    #[quickcheck_macros::quickcheck]
+    fn edge_jacobians_finite_differences(
+        t1: Float,
+        t2: Float,
+        t3: Float,
+        a1: Float,
+        a2: Float,
+        a3: Float,
+    ) -> bool {
+        let pose_from = gen_pose(0.1, -0.2, 0.3, 0.2, 0.1, -0.3);
+        let pose_to = gen_pose(t1, t2, t3, a1, a2, a3);
+        let edge = Edge {
+            from: 0,
+            to: 1,
+            measurement: pose_from.inverse() * pose_to,
+            information: Mat6::identity(),
+            kind: EdgeKind::Odometry,
+        };
+        let poses = [pose_from, pose_to];
+        let (jac_from, jac_to) = edge_jacobians(&edge, &poses);
+        let numeric = |node: usize| {
+            let h = 1e-3;
+            let mut jacobian = Mat6::zeros();
+            for k in 0..6 {
+                let mut delta = Vec6::zeros();
+                delta[k] = h;
+                let mut poses_plus = poses;
+                poses_plus[node] = se3::exp(delta) * poses[node];
+                let mut poses_minus = poses;
+                poses_minus[node] = se3::exp(-delta) * poses[node];
+                let diff = edge_error(&edge, &poses_plus) - edge_error(&edge, &poses_minus);
+                jacobian.set_column(k, &(diff / (2.0 * h)));
+            }
+            jacobian
+        };
+        approx::relative_eq!(jac_from, numeric(0), epsilon = EPSILON_FINITE_DIFF)
+            && approx::relative_eq!(jac_to, numeric(1), epsilon = EPSILON_FINITE_DIFF)
+    }
+
+    // GENERATORS ####################################################
+
+    fn gen_pose(t1: Float, t2: Float, t3: Float, a1: Float, a2: Float, a3: Float) -> Iso3 {
+        let translation = Translation3::from(Vec3::new(bounded(t1), bounded(t2), bounded(t3)));
+        let rotation = UnitQuaternion::from_euler_angles(bounded(a1), bounded(a2), bounded(a3));
+        Iso3::from_parts(translation, rotation)
+    }
+}
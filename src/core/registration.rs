@@ -0,0 +1,249 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Registration of depth maps into the color camera, for sensors with separate cameras.
+//!
+//! Each pixel of the depth map is back-projected with the depth camera model,
+//! moved into the color camera frame, and projected into the color image,
+//! where the closest depth is kept (z-buffering) to handle occlusions.
+//! To avoid cracks between projected pixels, through which background depths
+//! would appear, each depth pixel covers the color pixels up to halfway
+//! to the projections of its neighbors on the same surface.
+//! Holes, such as the background occluded from the depth camera, remain empty.
+
+use nalgebra::DMatrix;
+
+use crate::core::camera::{CameraModel, Intrinsics};
+use crate::misc::type_aliases::{Float, Iso3, Point2, Point3, Vec3};
+
+/// Default max relative difference of two depths considered on the same surface.
+pub const DEFAULT_MAX_RELATIVE_DIFFERENCE: Float = 0.05;
+
+/// Precomputed registration of depth maps into a color camera.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Registration<C: CameraModel = Intrinsics> {
+    /// Camera model of the color camera.
+    pub color_camera: C,
+    /// Shape `(nb_rows, nb_cols)` of the color images.
+    pub color_shape: (usize, usize),
+    /// Pose of the depth camera in the color camera frame.
+    pub depth_to_color: Iso3,
+    /// Scale of the 16 bits depth values, for example 5000.0 for 1 meter.
+    pub depth_scale: Float,
+    /// Max relative difference of two neighbor depths considered on the same surface,
+    /// `DEFAULT_MAX_RELATIVE_DIFFERENCE` by default.
+    pub max_relative_difference: Float,
+    /// Rays of the depth pixels, rotated in the color camera frame,
+    /// scaled for a unit depth in the convention of the depth camera model.
+    /// `None` for pixels that cannot be back-projected.
+    rays: DMatrix<Option<Vec3>>,
+}
+
+impl<C: CameraModel> Registration<C> {
+    /// Precompute the registration of depth maps of shape `(nb_rows, nb_cols)`.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn new<D: CameraModel>(
+        depth_camera: &D,
+        (nb_rows, nb_cols): (usize, usize),
+        color_camera: C,
+        color_shape: (usize, usize),
+        depth_to_color: Iso3,
+        depth_scale: Float,
+    ) -> Self {
+        let rotation = depth_to_color.rotation;
+        let rays = DMatrix::from_fn(nb_rows, nb_cols, |y, x| {
+            let ray = depth_camera.unproject(&Point2::new(x as Float, y as Float))?;
+            let depth = depth_camera.depth(&Point3::from(ray));
+            if depth > 0.0 {
+                Some(rotation * (ray / depth))
+            } else {
+                None
+            }
+        });
+        Self {
+            color_camera,
+            color_shape,
+            depth_to_color,
+            depth_scale,
+            max_relative_difference: DEFAULT_MAX_RELATIVE_DIFFERENCE,
+            rays,
+        }
+    }
+
+    /// Depth map registered in the color camera, with depths in the convention
+    /// of the color camera model and the same scale as the input depth map.
+    /// Pixels without depth are 0, as in the input depth map.
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn register(&self, depth_map: &DMatrix<u16>) -> DMatrix<u16> {
+        let projections = self.project(depth_map);
+        let (nb_rows, nb_cols) = self.color_shape;
+        let (depth_rows, depth_cols) = projections.shape();
+        let mut registered = DMatrix::zeros(nb_rows, nb_cols);
+        for x in 0..depth_cols {
+            for y in 0..depth_rows {
+                if let Some((pixel, depth)) = projections[(y, x)] {
+                    // Footprint extended halfway to the neighbors on the same surface.
+                    let (mut min, mut max) = (pixel, pixel);
+                    let neighbors = [
+                        (y, x.wrapping_sub(1)),
+                        (y, x + 1),
+                        (y.wrapping_sub(1), x),
+                        (y + 1, x),
+                    ];
+                    for &(ny, nx) in neighbors.iter() {
+                        if let Some(Some((n_pixel, n_depth))) = projections.get((ny, nx)) {
+                            if self.same_surface(depth, *n_depth) {
+                                let half = Point2::from(0.5 * (pixel.coords + n_pixel.coords));
+                                min = Point2::new(min.x.min(half.x), min.y.min(half.y));
+                                max = Point2::new(max.x.max(half.x), max.y.max(half.y));
+                            }
+                        }
+                    }
+
+                    // Z-buffering on the pixels of the footprint.
+                    let x_min = min.x.ceil().min(pixel.x.round()).max(0.0) as usize;
+                    let y_min = min.y.ceil().min(pixel.y.round()).max(0.0) as usize;
+                    let x_max = max
+                        .x
+                        .floor()
+                        .max(pixel.x.round())
+                        .min(nb_cols as Float - 1.0);
+                    let y_max = max
+                        .y
+                        .floor()
+                        .max(pixel.y.round())
+                        .min(nb_rows as Float - 1.0);
+                    for cx in x_min..=x_max as usize {
+                        for cy in y_min..=y_max as usize {
+                            let current = &mut registered[(cy, cx)];
+                            if *current == 0 || depth < *current {
+                                *current = depth;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        registered
+    }
+
+    /// Projection in the color image of each pixel of a depth map,
+    /// with its depth in the color camera.
+    /// `None` for pixels without depth or projected outside of the color image.
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    #[allow(clippy::cast_precision_loss)]
+    fn project(&self, depth_map: &DMatrix<u16>) -> DMatrix<Option<(Point2, u16)>> {
+        assert_eq!(
+            depth_map.shape(),
+            self.rays.shape(),
+            "The depth map must have the shape of the registration"
+        );
+        let (nb_rows, nb_cols) = self.color_shape;
+        let translation = self.depth_to_color.translation.vector * self.depth_scale;
+        let max_depth = Float::from(u16::MAX);
+        depth_map.zip_map(&self.rays, |depth, ray| {
+            let ray = ray.filter(|_| depth > 0)?;
+            // Point in the color camera frame, in the unit of depth values.
+            let point = Point3::from(ray * Float::from(depth) + translation);
+            let pixel = self.color_camera.project(&point)?;
+            let new_depth = self.color_camera.depth(&point).round();
+            let inside = (-0.5..nb_cols as Float - 0.5).contains(&pixel.x)
+                && (-0.5..nb_rows as Float - 0.5).contains(&pixel.y);
+            if inside && (1.0..=max_depth).contains(&new_depth) {
+                Some((pixel, new_depth as u16))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Whether two depths are on the same surface.
+    fn same_surface(&self, a: u16, b: u16) -> bool {
+        let (near, far) = (Float::from(a.min(b)), Float::from(a.max(b)));
+        far - near <= self.max_relative_difference * near
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const SHAPE: (usize, usize) = (40, 60);
+    const DEPTH_SCALE: Float = 5000.0;
+
+    #[test]
+    #[allow(clippy::cast_possible_truncation)]
+    fn identity_registration() {
+        let registration = gen_registration(Iso3::identity());
+        let depth_map = DMatrix::from_fn(SHAPE.0, SHAPE.1, |y, x| (5000 + 37 * x + 91 * y) as u16);
+        assert_eq!(registration.register(&depth_map), depth_map);
+    }
+
+    #[test]
+    #[should_panic(expected = "The depth map must have the shape of the registration")]
+    fn wrong_shape() {
+        let registration = gen_registration(Iso3::identity());
+        registration.register(&DMatrix::zeros(SHAPE.0 + 1, SHAPE.1));
+    }
+
+    #[test]
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn z_buffering() {
+        // Square at 1 meter in front of a plane at 2 meters,
+        // seen by a depth camera 10 cm to the left of the color camera.
+        let depth_to_color = Iso3::translation(-0.1, 0.0, 0.0);
+        let registration = gen_registration(depth_to_color);
+        let foreground = |y: usize, x: usize| (15..25).contains(&y) && (25..35).contains(&x);
+        let depth_map = DMatrix::from_fn(SHAPE.0, SHAPE.1, |y, x| {
+            if foreground(y, x) {
+                5000
+            } else {
+                10000
+            }
+        });
+        let registered = registration.register(&depth_map);
+
+        // The foreground hides the background projected at the same place.
+        let camera = &registration.color_camera;
+        for y in 15..25 {
+            for x in 25..35 {
+                let point = Point3::new(
+                    (x as Float - 29.5) / 50.0 - 0.1,
+                    (y as Float - 19.5) / 50.0,
+                    1.0,
+                );
+                let pixel = CameraModel::project(camera, &point).unwrap();
+                let (cx, cy) = (pixel.x.round() as usize, pixel.y.round() as usize);
+                assert_eq!(registered[(cy, cx)], 5000);
+            }
+        }
+
+        // No crack in the background, but a hole where it is occluded from the depth camera.
+        assert!((5..50).all(|x| registered[(5, x)] == 10000));
+        assert!((5..55).any(|x| registered[(20, x)] == 0));
+    }
+
+    // GENERATORS ####################################################
+
+    fn gen_registration(depth_to_color: Iso3) -> Registration {
+        let camera = Intrinsics {
+            principal_point: (29.5, 19.5),
+            focal: (50.0, 50.0),
+            skew: 0.0,
+        };
+        Registration::new(
+            &camera,
+            SHAPE,
+            camera.clone(),
+            SHAPE,
+            depth_to_color,
+            DEPTH_SCALE,
+        )
+    }
+}
@@ -0,0 +1,224 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Inverse depth of candidate points from a rectified stereo pair.
+//!
+//! In a rectified pair, the right camera is translated by the baseline
+//! along the x axis of the left camera, so epipolar lines are image rows.
+//! Matching is thus a special case of the epipolar line search,
+//! followed by a left-right consistency check.
+//! The resulting inverse depth maps can be used with
+//! `Config::init_with_idepth` and `Tracker::track_with_idepth` of the tracker.
+
+use nalgebra::DMatrix;
+
+use crate::core::camera::Intrinsics;
+use crate::core::epipolar::{self, SearchConfig};
+use crate::core::inverse_depth::InverseDepth;
+use crate::misc::type_aliases::{Float, Iso3};
+
+/// Configuration of a rectified stereo pair and of the matching.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Config {
+    /// Intrinsic parameters of both rectified cameras.
+    pub intrinsics: Intrinsics,
+    /// Distance between the two cameras (in the depth unit, usually meters).
+    pub baseline: Float,
+    /// Parameters of the search along image rows.
+    /// The inverse depth range defines the disparity range.
+    pub search: SearchConfig,
+    /// Max difference (in pixels) between the disparities found from left to right
+    /// and from right to left.
+    pub max_lr_difference: Float,
+}
+
+impl Config {
+    /// Disparity (in pixels) corresponding to an inverse depth.
+    pub fn disparity(&self, idepth: Float) -> Float {
+        self.intrinsics.focal.0 * self.baseline * idepth
+    }
+
+    /// Motion bringing points from the left camera frame to the right one.
+    fn left_to_right(&self) -> Iso3 {
+        Iso3::translation(-self.baseline, 0.0, 0.0)
+    }
+}
+
+/// Compute the inverse depths of candidate points of the left image.
+///
+/// The candidates mask is typically selected in the left image
+/// with `candidates::coarse_to_fine` or `candidates::dso`,
+/// and must have the shape of the images.
+/// Matches that are ambiguous or fail the left-right check are `Discarded`.
+/// Points without match (out of image or without texture)
+/// and points outside of the candidates mask are `Unknown`.
+pub fn idepth_map(
+    left: &DMatrix<u8>,
+    right: &DMatrix<u8>,
+    candidates: &DMatrix<bool>,
+    config: &Config,
+) -> DMatrix<InverseDepth> {
+    assert_eq!(
+        left.shape(),
+        right.shape(),
+        "The left and right images must have the same shape"
+    );
+    assert_eq!(
+        candidates.shape(),
+        left.shape(),
+        "The candidates mask must have the shape of the images"
+    );
+    DMatrix::from_fn(left.nrows(), left.ncols(), |y, x| {
+        if candidates[(y, x)] {
+            match_point(left, right, (x, y), config)
+        } else {
+            InverseDepth::Unknown
+        }
+    })
+}
+
+/// Compute the inverse depth of one pixel of the left image.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+#[allow(clippy::cast_precision_loss)]
+pub fn match_point(
+    left: &DMatrix<u8>,
+    right: &DMatrix<u8>,
+    (x, y): (usize, usize),
+    config: &Config,
+) -> InverseDepth {
+    let motion = config.left_to_right();
+    let idepth = epipolar::search(
+        left,
+        right,
+        &config.intrinsics,
+        &motion,
+        (x, y),
+        InverseDepth::Unknown,
+        &config.search,
+    );
+    if let InverseDepth::WithVariance(d, _) = idepth {
+        // Left-right check, from the closest pixel of the match in the right image.
+        let x_right = (x as Float - config.disparity(d)).round();
+        if x_right < 0.0 {
+            return InverseDepth::Discarded;
+        }
+        let idepth_back = epipolar::search(
+            right,
+            left,
+            &config.intrinsics,
+            &motion.inverse(),
+            (x_right as usize, y),
+            InverseDepth::Unknown,
+            &config.search,
+        );
+        match idepth_back {
+            InverseDepth::WithVariance(d_back, _) => {
+                let x_back = x_right + config.disparity(d_back);
+                if (x_back - x as Float).abs() > config.max_lr_difference {
+                    return InverseDepth::Discarded;
+                }
+            }
+            _ => return InverseDepth::Discarded,
+        }
+    }
+    idepth
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::core::epipolar::DEFAULT_SEARCH_CONFIG;
+    use crate::misc::helper::gen_texture;
+
+    #[test]
+    fn plane_known_disparity() {
+        let config = gen_config();
+        let texture = gen_texture(40, 100);
+        // Fronto-parallel plane at inverse depth 0.5, so a disparity of 5 pixels.
+        let left = texture.clone();
+        let right = DMatrix::from_fn(40, 100, |y, x| texture[(y, (x + 5).min(99))]);
+        let candidates = DMatrix::from_fn(40, 100, |y, x| (5..35).contains(&y) && x >= 25);
+        let idepths = idepth_map(&left, &right, &candidates, &config);
+        let (mut nb_matches, mut nb_accurate) = (0, 0);
+        for idepth in idepths.iter() {
+            if let InverseDepth::WithVariance(d, _) = *idepth {
+                nb_matches += 1;
+                if (d - 0.5).abs() < 0.05 {
+                    nb_accurate += 1;
+                }
+            }
+        }
+        let nb_candidates = candidates.iter().filter(|&&c| c).count();
+        assert!(nb_matches > nb_candidates * 9 / 10);
+        assert!(nb_accurate > nb_matches * 95 / 100);
+        assert!(idepths
+            .iter()
+            .zip(candidates.iter())
+            .all(|(d, &c)| c || *d == InverseDepth::Unknown));
+        assert_eq!(config.disparity(0.5), 5.0);
+    }
+
+    #[test]
+    fn occlusion_discarded() {
+        let config = gen_config();
+        let texture = gen_texture(40, 200);
+        let background = |y: usize, x: usize| texture[(y, x.min(99))];
+        let foreground = |y: usize, x: usize| texture[(y, 100 + x)];
+        // Background at disparity 5, occluded in the right image by a foreground
+        // rectangle at disparity 15 over the columns [60, 80) of the left image.
+        let in_front = |x: usize| (60..80).contains(&x);
+        let left = DMatrix::from_fn(40, 100, |y, x| {
+            if in_front(x) {
+                foreground(y, x)
+            } else {
+                background(y, x)
+            }
+        });
+        let right = DMatrix::from_fn(40, 100, |y, x| {
+            if in_front(x + 15) {
+                foreground(y, x + 15)
+            } else {
+                background(y, x + 5)
+            }
+        });
+        let candidates = DMatrix::from_fn(40, 100, |y, x| (5..35).contains(&y) && x >= 25);
+        let idepths = idepth_map(&left, &right, &candidates, &config);
+        let count = |xs: std::ops::Range<usize>, f: &dyn Fn(InverseDepth) -> bool| {
+            (5..35)
+                .flat_map(|y| xs.clone().map(move |x| (y, x)))
+                .filter(|&(y, x)| f(idepths[(y, x)]))
+                .count()
+        };
+        let close_to = |expected: Float| {
+            move |idepth| match idepth {
+                InverseDepth::WithVariance(d, _) => (d - expected).abs() < 0.05,
+                _ => false,
+            }
+        };
+        // Visible background and foreground points are matched.
+        assert!(count(30..50, &close_to(0.5)) > 30 * 20 * 9 / 10);
+        assert!(count(62..78, &close_to(1.5)) > 30 * 16 * 9 / 10);
+        // Background points seen by the left camera only, in the columns [50, 60),
+        // are discarded by the left-right check.
+        let discarded = |idepth| idepth == InverseDepth::Discarded;
+        assert!(count(50..60, &discarded) > 30 * 10 * 95 / 100);
+    }
+
+    // GENERATORS ####################################################
+
+    fn gen_config() -> Config {
+        Config {
+            intrinsics: Intrinsics {
+                principal_point: (49.5, 19.5),
+                focal: (50.0, 50.0),
+                skew: 0.0,
+            },
+            baseline: 0.2,
+            search: DEFAULT_SEARCH_CONFIG,
+            max_lr_difference: 1.0,
+        }
+    }
+}
@@ -0,0 +1,240 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Common interface of the trackers, to swap them at runtime.
+
+use nalgebra::{allocator::Allocator, DMatrix, DefaultAllocator, DimName, MatrixN, U6};
+
+use crate::core::track::inverse_compositional::Levels;
+use crate::core::track::keyframe::{FrameInfo, KeyframePolicy};
+use crate::core::track::motion_model::PosePrior;
+use crate::core::track::sliding_window::WindowResult;
+use crate::math::se3;
+use crate::misc::type_aliases::{Float, Iso3, Mat6};
+
+/// Number of previous poses kept for motion models.
+pub const MAX_POSE_HISTORY: usize = 3;
+
+/// Result of the tracking of a frame, to react programmatically to tracking failures.
+#[derive(Debug, Clone)]
+pub struct TrackingResult {
+    /// Final status of the tracking.
+    pub status: TrackingStatus,
+    /// Optimization results at each level.
+    /// Levels that were not optimized due to a failure at a coarser level are `None`.
+    pub levels: Levels<Option<LevelResult>>,
+    /// Mean L1 optical flow of the candidate points on the coarsest level.
+    pub optical_flow: Float,
+    /// Whether the tracked frame became the new keyframe.
+    pub keyframe_created: bool,
+    /// Result of the sliding window optimization, if it ran after a keyframe creation.
+    /// It is `None` if the optimization failed, in which case poses are left unchanged.
+    pub window: Option<WindowResult>,
+}
+
+/// Status of the tracking of a frame.
+/// The pose of the frame is only updated if the status is `Ok`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackingStatus {
+    /// Tracking went well.
+    Ok,
+    /// The energy could not be evaluated, typically because
+    /// no candidate point is warped inside the image anymore.
+    Diverged,
+    /// Cholesky decomposition of the hessian failed during the optimization.
+    CholeskyFailure,
+}
+
+/// Result of the optimization at one level of the multi-resolution pyramid.
+#[derive(Debug, Clone, Copy)]
+pub struct LevelResult {
+    /// Final energy of the optimization.
+    pub energy: Float,
+    /// Number of Levenberg-Marquardt iterations.
+    pub nb_iterations: usize,
+    /// Number of residuals for points warped inside the image.
+    pub nb_residuals: usize,
+}
+
+/// A tracker estimating the pose of each new RGB-D frame.
+pub trait FrameTracker {
+    /// Track a new frame.
+    /// Some trackers only use the depth map or only use the intensity image.
+    fn track(
+        &mut self,
+        depth_time: f64,
+        depth_map: &DMatrix<u16>,
+        img_time: f64,
+        img: DMatrix<u8>,
+    ) -> TrackingResult;
+
+    /// Provide an external prior (e.g. from wheel odometry) for the next frame to track.
+    fn set_pose_prior(&mut self, prior: PosePrior);
+
+    /// Retrieve the current frame timestamp (of depth image) and pose.
+    fn current_pose(&self) -> (f64, Iso3);
+
+    /// Retrieve the covariance of the current frame pose, expressed in the world frame.
+    fn current_frame_covariance(&self) -> Option<Mat6>;
+}
+
+// Helpers shared by the trackers ##############################################
+
+/// Add the pose of a successfully tracked frame to the history used by motion models,
+/// only keeping the last `MAX_POSE_HISTORY` poses.
+pub fn push_pose_history(history: &mut Vec<(f64, Iso3)>, timestamp: f64, pose: Iso3) {
+    history.push((timestamp, pose));
+    if history.len() > MAX_POSE_HISTORY {
+        history.remove(0);
+    }
+}
+
+/// Ask a keyframe policy if a tracked frame should become the new keyframe.
+///
+/// Only successfully tracked frames can become keyframes.
+/// The energy of the first one tracked with the current keyframe is recorded
+/// as the reference energy, which is given to `frame_info` to build the policy input.
+pub fn change_keyframe<F: FnOnce(Float) -> FrameInfo>(
+    policy: &dyn KeyframePolicy,
+    status: TrackingStatus,
+    keyframe_reference_energy: &mut Option<Float>,
+    energy: Float,
+    frame_info: F,
+) -> bool {
+    if status != TrackingStatus::Ok {
+        return false;
+    }
+    let reference_energy = *keyframe_reference_energy.get_or_insert(energy);
+    policy.change_keyframe(&frame_info(reference_energy))
+}
+
+/// Covariance of a pose in the world frame, from the final hessian of an optimizer
+/// whose first 6 parameters are a twist step expressed in the frame of `pose`.
+///
+/// The hessian is scaled by the final energy, as an estimation of the residuals variance.
+/// The pose covariance is the top left block of the inverse hessian,
+/// which marginalizes the other parameters, such as the affine brightness.
+pub fn pose_covariance<D: DimName>(
+    pose: &Iso3,
+    hessian: &MatrixN<Float, D>,
+    energy: Float,
+) -> Option<Mat6>
+where
+    DefaultAllocator: Allocator<Float, D, D>,
+{
+    let covariance = (hessian / energy).try_inverse()?;
+    let pose_covariance: Mat6 = covariance.fixed_slice::<U6, U6>(0, 0).into_owned();
+    let adj = se3::adjoint(*pose);
+    Some(adj * pose_covariance * adj.transpose())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::core::track::keyframe::ResidualGrowth;
+    use crate::misc::type_aliases::{Mat3, Mat8, Vec3};
+    use approx;
+    use nalgebra::U3;
+
+    #[test]
+    fn change_keyframe_records_reference_energy() {
+        let policy = ResidualGrowth { max_ratio: 2.0 };
+        let info = |energy| {
+            move |reference_energy| FrameInfo {
+                optical_flow: 0.0,
+                motion: Iso3::identity(),
+                elapsed_time: 0.0,
+                visible_ratio: 1.0,
+                energy,
+                reference_energy,
+            }
+        };
+        let mut reference = None;
+        let track = |reference: &mut Option<Float>, status, energy| {
+            change_keyframe(&policy, status, reference, energy, info(energy))
+        };
+
+        // Failed tracks neither change the keyframe nor record their energy.
+        assert!(!track(&mut reference, TrackingStatus::Diverged, Float::NAN));
+        assert!(!track(
+            &mut reference,
+            TrackingStatus::CholeskyFailure,
+            100.0
+        ));
+        assert_eq!(reference, None);
+
+        // The first successful track is the reference.
+        assert!(!track(&mut reference, TrackingStatus::Ok, 10.0));
+        assert_eq!(reference, Some(10.0));
+        assert!(!track(&mut reference, TrackingStatus::Ok, 20.0));
+        assert!(track(&mut reference, TrackingStatus::Ok, 25.0));
+        assert!(!track(&mut reference, TrackingStatus::Diverged, 25.0));
+        assert_eq!(reference, Some(10.0));
+    }
+
+    #[test]
+    fn push_pose_history_keeps_last_poses() {
+        let mut history = Vec::new();
+        for i in 0..5 {
+            push_pose_history(&mut history, f64::from(i), Iso3::identity());
+        }
+        let timestamps: Vec<f64> = history.iter().map(|(t, _)| *t).collect();
+        assert_eq!(timestamps, vec![2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn pose_covariance_at_identity() {
+        let hessian = gen_hessian();
+        let energy = 4.0;
+        let covariance = pose_covariance(&Iso3::identity(), &hessian, energy).unwrap();
+        // Top left block of the inverse of the full scaled hessian,
+        // marginalizing the affine parameters.
+        let expected: Mat6 = (hessian / energy)
+            .try_inverse()
+            .unwrap()
+            .fixed_slice::<U6, U6>(0, 0)
+            .into_owned();
+        assert!(approx::relative_eq!(covariance, expected, epsilon = 1e-4));
+        assert!(pose_covariance(&Iso3::identity(), &Mat8::zeros(), energy).is_none());
+    }
+
+    #[test]
+    fn pose_covariance_rotates_with_pose() {
+        let hessian = gen_hessian();
+        let covariance = pose_covariance(&Iso3::identity(), &hessian, 1.0).unwrap();
+        let axis_angle = Vec3::new(0.3, -0.5, 1.2);
+        let rotated = pose_covariance(&Iso3::rotation(axis_angle), &hessian, 1.0).unwrap();
+        // A pure rotation rotates the translation and rotation blocks of the covariance.
+        let r: Mat3 = Iso3::rotation(axis_angle)
+            .rotation
+            .to_rotation_matrix()
+            .into_inner();
+        let block = |c: &Mat6, i, j| -> Mat3 { c.fixed_slice::<U3, U3>(i, j).into_owned() };
+        for &(i, j) in &[(0, 0), (0, 3), (3, 0), (3, 3)] {
+            assert!(approx::relative_eq!(
+                block(&rotated, i, j),
+                r * block(&covariance, i, j) * r.transpose(),
+                epsilon = 1e-4
+            ));
+        }
+        // A pure translation leaves the rotation block unchanged.
+        let pose = Iso3::translation(1.0, -2.0, 0.5);
+        let translated = pose_covariance(&pose, &hessian, 1.0).unwrap();
+        assert!(approx::relative_eq!(
+            block(&translated, 3, 3),
+            block(&covariance, 3, 3),
+            epsilon = 1e-4
+        ));
+    }
+
+    // GENERATORS ####################################################
+
+    /// Symmetric positive definite hessian of the motion and affine parameters.
+    #[allow(clippy::cast_precision_loss)]
+    fn gen_hessian() -> Mat8 {
+        let a = Mat8::from_fn(|i, j| ((3 * i + 5 * j) % 7) as Float / 7.0);
+        a * a.transpose() + Mat8::identity()
+    }
+}
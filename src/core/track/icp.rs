@@ -0,0 +1,350 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Geometric-only tracking, aligning depth maps with point-to-plane ICP.
+//!
+//! Intensity images are not used at all, which makes it suitable
+//! for scenes where they are unreliable, such as low-light environments.
+//! Points of the keyframe depth map are aligned with the current depth map
+//! using projective data association, over a multi-resolution pyramid.
+
+use nalgebra::DMatrix;
+
+use crate::core::{
+    camera::Intrinsics,
+    inverse_depth::{self, InverseDepth},
+    multires,
+    track::frame_tracker::{self, FrameTracker, LevelResult, TrackingResult, TrackingStatus},
+    track::icp_optimizer::{self, IcpOptimizerState, VertexNormal},
+    track::inverse_compositional::Levels,
+    track::keyframe::{FrameInfo, KeyframePolicy},
+    track::lm_optimizer::LMConfig,
+    track::motion_model::{MotionModel, PosePrior},
+};
+use crate::math::optimizer::State as _;
+use crate::math::robust::RobustLoss;
+use crate::misc::type_aliases::{Float, Iso3, Mat6, Point2, Point3, Vec3};
+
+/// Reasonable optimizer parameters for ICP energies,
+/// which are squared distances (usually in meters) instead of squared intensities.
+pub const DEFAULT_LM_CONFIG: LMConfig = LMConfig {
+    initial_coef: 0.1,
+    coef_increase_factor: 10.0,
+    coef_decrease_factor: 0.1,
+    max_iterations: 20,
+    min_energy_decrease: 0.0,
+    min_relative_energy_decrease: 1e-3,
+    min_step_norm: 1e-6,
+};
+
+/// Configuration of the ICP tracker.
+pub struct Config {
+    /// Number of levels in the multi-resolution pyramids of depth maps.
+    pub nb_levels: usize,
+    /// Scale of the depth 16 bit images.
+    /// This is 5000.0 for the TUM RGB-D dataset.
+    pub depth_scale: Float,
+    /// Camera intrinsic parameters.
+    pub intrinsics: Intrinsics,
+    /// Max distance (in the depth unit) between associated points.
+    pub max_distance: Float,
+    /// Robust loss weighting the point-to-plane residuals (in the depth unit).
+    /// Use `robust::Squared` for a plain least squares optimization.
+    pub robust_loss: Box<dyn RobustLoss>,
+    /// Policy deciding when to change the keyframe.
+    /// Changing at every frame aligns consecutive depth maps.
+    pub keyframe_policy: Box<dyn KeyframePolicy>,
+    /// Motion model used to initialize the pose of a new frame.
+    pub motion_model: MotionModel,
+    /// Parameters of the Levenberg-Marquardt optimizer at each level.
+    /// Must contain `nb_levels` elements, the first one being for the highest resolution.
+    pub lm_configs: Levels<LMConfig>,
+}
+
+/// Struct used for tracking the camera at each frame.
+/// Can only be constructed by initialization from a `Config`.
+pub struct Tracker {
+    config: Config,
+    state: State,
+}
+
+/// Internal state of the tracker.
+struct State {
+    keyframe_points: Levels<Vec<Point3>>,
+    keyframe_depth_timestamp: f64,
+    keyframe_pose: Iso3,
+    keyframe_reference_energy: Option<Float>,
+    current_frame_depth_timestamp: f64,
+    current_frame_pose: Iso3,
+    current_frame_covariance: Option<Mat6>,
+    /// Timestamps and poses of the last successfully tracked frames, oldest first.
+    pose_history: Vec<(f64, Iso3)>,
+    /// External prior for the next frame to track.
+    pose_prior: Option<PosePrior>,
+    intrinsics_multires: Levels<Intrinsics>,
+}
+
+impl Config {
+    /// Initialize a tracker with the first depth map.
+    pub fn init(self, keyframe_depth_timestamp: f64, depth_map: &DMatrix<u16>) -> Tracker {
+        assert_eq!(
+            self.nb_levels,
+            self.lm_configs.len(),
+            "There must be one LMConfig per level"
+        );
+        let intrinsics_multires = self.intrinsics.clone().multi_res(self.nb_levels);
+        let vertex_normals_multires =
+            vertex_normals_pyramid(&self, &intrinsics_multires, depth_map);
+        Tracker {
+            state: State {
+                keyframe_points: vertex_normals_multires.iter().map(extract_points).collect(),
+                keyframe_depth_timestamp,
+                keyframe_pose: Iso3::identity(),
+                keyframe_reference_energy: None,
+                current_frame_depth_timestamp: keyframe_depth_timestamp,
+                current_frame_pose: Iso3::identity(),
+                current_frame_covariance: Some(Mat6::zeros()),
+                pose_history: vec![(keyframe_depth_timestamp, Iso3::identity())],
+                pose_prior: None,
+                intrinsics_multires,
+            },
+            config: self,
+        }
+    }
+} // impl Config
+
+impl Tracker {
+    /// Track a new depth map.
+    /// You can use `tracker.current_frame()` after tracking to retrieve the new frame pose.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn track(&mut self, depth_time: f64, depth_map: &DMatrix<u16>) -> TrackingResult {
+        // Initialize the motion with the external prior or the motion model.
+        let pose_prior = self.state.pose_prior.take();
+        let predicted_pose = match pose_prior {
+            Some(prior) => prior.pose,
+            None => self
+                .config
+                .motion_model
+                .predict(&self.state.pose_history, depth_time),
+        };
+        let motion_prior =
+            pose_prior.and_then(|prior| prior.motion_prior(&self.state.keyframe_pose));
+        let mut motion = predicted_pose.inverse() * self.state.keyframe_pose;
+
+        let intrinsics_multires = &self.state.intrinsics_multires;
+        let vertex_normals_multires =
+            vertex_normals_pyramid(&self.config, intrinsics_multires, depth_map);
+        let mut status = TrackingStatus::Ok;
+        let mut levels = vec![None; self.config.nb_levels];
+        let mut energy = Float::INFINITY;
+        let mut nb_residuals = 0;
+        let mut hessian = Mat6::zeros();
+        for lvl in (0..self.config.nb_levels).rev() {
+            let obs = icp_optimizer::Obs {
+                intrinsics: &intrinsics_multires[lvl],
+                points: &self.state.keyframe_points[lvl],
+                vertex_normals: &vertex_normals_multires[lvl],
+                max_distance: self.config.max_distance,
+                robust_loss: self.config.robust_loss.as_ref(),
+                motion_prior,
+                lm_config: self.config.lm_configs[lvl],
+            };
+            match IcpOptimizerState::iterative_solve(&obs, motion) {
+                Ok((icp_state, nb_iterations)) => {
+                    let eval_data = icp_state.eval_data;
+                    levels[lvl] = Some(LevelResult {
+                        energy: eval_data.energy,
+                        nb_iterations,
+                        nb_residuals: eval_data.nb_residuals,
+                    });
+                    if !eval_data.energy.is_finite() {
+                        status = TrackingStatus::Diverged;
+                        break;
+                    }
+                    motion = eval_data.model;
+                    energy = eval_data.energy;
+                    nb_residuals = eval_data.nb_residuals;
+                    hessian = eval_data.hessian;
+                }
+                Err(_) => {
+                    status = TrackingStatus::CholeskyFailure;
+                    break;
+                }
+            }
+        }
+
+        // Update current frame info in tracker.
+        self.state.current_frame_depth_timestamp = depth_time;
+        self.state.current_frame_covariance = None;
+        if status == TrackingStatus::Ok {
+            self.state.current_frame_pose = self.state.keyframe_pose * motion.inverse();
+            // The step is applied as `exp(-delta) * motion`, in the current frame.
+            self.state.current_frame_covariance =
+                frame_tracker::pose_covariance(&self.state.current_frame_pose, &hessian, energy);
+            frame_tracker::push_pose_history(
+                &mut self.state.pose_history,
+                depth_time,
+                self.state.current_frame_pose,
+            );
+        }
+
+        // Check if we need to change the keyframe.
+        let coarsest_points = self.state.keyframe_points.last().unwrap();
+        let intrinsics = intrinsics_multires.last().unwrap();
+        let optical_flow_sum: Float = coarsest_points
+            .iter()
+            .map(|point| {
+                let (x, y) = project(intrinsics, point);
+                let (u, v) = project(intrinsics, &(motion * point));
+                (x - u).abs() + (y - v).abs()
+            })
+            .sum();
+        let optical_flow = optical_flow_sum / coarsest_points.len() as Float;
+
+        let nb_points = self.state.keyframe_points[0].len();
+        let elapsed_time = depth_time - self.state.keyframe_depth_timestamp;
+        let change_keyframe = frame_tracker::change_keyframe(
+            self.config.keyframe_policy.as_ref(),
+            status,
+            &mut self.state.keyframe_reference_energy,
+            energy,
+            |reference_energy| FrameInfo {
+                optical_flow,
+                motion,
+                elapsed_time,
+                visible_ratio: nb_residuals as Float / nb_points as Float,
+                energy,
+                reference_energy,
+            },
+        );
+
+        // In case of keyframe change, update all keyframe info with current frame.
+        if change_keyframe {
+            self.state.keyframe_points =
+                vertex_normals_multires.iter().map(extract_points).collect();
+            self.state.keyframe_depth_timestamp = depth_time;
+            self.state.keyframe_pose = self.state.current_frame_pose;
+            self.state.keyframe_reference_energy = None;
+        }
+
+        TrackingResult {
+            status,
+            levels,
+            optical_flow,
+            keyframe_created: change_keyframe,
+            window: None,
+        }
+    } // track
+
+    /// Provide an external prior (e.g. from wheel odometry) for the next frame to track.
+    /// It replaces the motion model prediction for that frame.
+    pub fn set_pose_prior(&mut self, prior: PosePrior) {
+        self.state.pose_prior = Some(prior);
+    }
+
+    /// Retrieve the covariance of the current frame pose, expressed in the world frame
+    /// (`exp(xi) * pose` with `xi ~ N(0, covariance)`).
+    ///
+    /// It only accounts for the uncertainty of the motion relative to the keyframe,
+    /// and is `None` if the last tracking failed.
+    pub fn current_frame_covariance(&self) -> Option<Mat6> {
+        self.state.current_frame_covariance
+    }
+
+    /// Retrieve the current frame timestamp (of depth image) and pose.
+    pub fn current_frame(&self) -> (f64, Iso3) {
+        (
+            self.state.current_frame_depth_timestamp,
+            self.state.current_frame_pose,
+        )
+    }
+} // impl Tracker
+
+impl FrameTracker for Tracker {
+    fn track(
+        &mut self,
+        depth_time: f64,
+        depth_map: &DMatrix<u16>,
+        _img_time: f64,
+        _img: DMatrix<u8>,
+    ) -> TrackingResult {
+        Tracker::track(self, depth_time, depth_map)
+    }
+
+    fn set_pose_prior(&mut self, prior: PosePrior) {
+        Tracker::set_pose_prior(self, prior)
+    }
+
+    fn current_pose(&self) -> (f64, Iso3) {
+        self.current_frame()
+    }
+
+    fn current_frame_covariance(&self) -> Option<Mat6> {
+        Tracker::current_frame_covariance(self)
+    }
+}
+
+// Helper ######################################################################
+
+/// Multi-resolution vertex and normal maps of a depth image.
+fn vertex_normals_pyramid(
+    config: &Config,
+    intrinsics_multires: &[Intrinsics],
+    depth_map: &DMatrix<u16>,
+) -> Levels<DMatrix<VertexNormal>> {
+    let idepth_map = depth_map.map(|z| inverse_depth::from_depth(config.depth_scale, z, 1.0));
+    let fuse = |a, b, c, d| inverse_depth::fuse(a, b, c, d, inverse_depth::strategy_dso_mean);
+    let idepth_multires =
+        multires::limited_sequence(config.nb_levels, idepth_map, |m| multires::halve(m, fuse));
+    idepth_multires
+        .iter()
+        .zip(intrinsics_multires)
+        .map(|(idepth_map, intrinsics)| vertex_normals(intrinsics, idepth_map))
+        .collect()
+}
+
+/// Compute the vertex and normal maps of an inverse depth map.
+/// Normals are estimated with centered differences of neighbour vertices,
+/// and oriented towards the camera.
+#[allow(clippy::cast_precision_loss)]
+fn vertex_normals(
+    intrinsics: &Intrinsics,
+    idepth_map: &DMatrix<InverseDepth>,
+) -> DMatrix<VertexNormal> {
+    let (nb_rows, nb_cols) = idepth_map.shape();
+    let vertex = |r: usize, c: usize| match idepth_map[(r, c)] {
+        InverseDepth::WithVariance(idepth, _) if idepth > 0.0 => {
+            Some(intrinsics.back_project(Point2::new(c as Float, r as Float), 1.0 / idepth))
+        }
+        _ => None,
+    };
+    DMatrix::from_fn(nb_rows, nb_cols, |r, c| {
+        if r == 0 || c == 0 || r + 1 >= nb_rows || c + 1 >= nb_cols {
+            return None;
+        }
+        let p = vertex(r, c)?;
+        let d_x: Vec3 = vertex(r, c + 1)? - vertex(r, c - 1)?;
+        let d_y: Vec3 = vertex(r + 1, c)? - vertex(r - 1, c)?;
+        let n = d_x.cross(&d_y).try_normalize(Float::EPSILON)?;
+        if n.dot(&p.coords) > 0.0 {
+            Some((p, -n))
+        } else {
+            Some((p, n))
+        }
+    })
+}
+
+/// Extract the vertices with a known normal.
+fn extract_points(vertex_normals: &DMatrix<VertexNormal>) -> Vec<Point3> {
+    vertex_normals
+        .iter()
+        .filter_map(|vn| vn.map(|(p, _)| p))
+        .collect()
+}
+
+/// Project a point into pixel coordinates.
+fn project(intrinsics: &Intrinsics, point: &Point3) -> (Float, Float) {
+    let uvz = intrinsics.project(*point);
+    (uvz.x / uvz.z, uvz.y / uvz.z)
+}
@@ -0,0 +1,275 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Levenberg-Marquardt implementation of the `optimizer::State` trait
+//! for point-to-plane ICP with projective data association.
+//!
+//! The keyframe points are transformed by the current motion estimate,
+//! projected in the current depth map, and associated with the vertex
+//! (and normal) of the pixel they fall into, as in KinectFusion.
+//! The residual of a point is its signed distance to the tangent plane
+//! of its associated vertex.
+
+use nalgebra::DMatrix;
+
+use crate::core::camera::Intrinsics;
+use crate::core::track::lm_optimizer::{self, LMConfig, MotionPrior};
+use crate::math::optimizer::{self, Continue};
+use crate::math::robust::RobustLoss;
+use crate::math::se3;
+use crate::misc::type_aliases::{Float, Iso3, Mat6, Point3, Vec3, Vec6};
+
+/// A vertex and its unit normal, both in the camera frame.
+/// `None` where the depth or the normal is unknown.
+pub type VertexNormal = Option<(Point3, Vec3)>;
+
+/// State of the Levenberg-Marquardt optimizer.
+pub struct IcpOptimizerState {
+    /// Parameters of the optimizer.
+    pub config: LMConfig,
+    /// Levenberg-Marquardt hessian diagonal coefficient.
+    pub lm_coef: Float,
+    /// Data resulting of a successful model evaluation.
+    pub eval_data: EvalData,
+}
+
+/// Either a successfully constructed `EvalData`
+/// or an error containing the energy of a given model.
+///
+/// The error is returned when the new computed energy
+/// is higher than the previous iteration energy.
+pub type EvalState = Result<EvalData, Float>;
+
+/// Data resulting of a successful model evaluation.
+pub struct EvalData {
+    /// The hessian matrix of the system.
+    pub hessian: Mat6,
+    /// The gradient of the system.
+    pub gradient: Vec6,
+    /// Energy associated with the current model.
+    pub energy: Float,
+    /// Number of residuals used for the energy, i.e. of associated points.
+    pub nb_residuals: usize,
+    /// Estimated motion at the current state of iterations.
+    pub model: Iso3,
+    /// Scale of the residuals used for the energy,
+    /// fixed at the initial motion of the optimization (see `RobustLoss::scale`).
+    pub scale: Float,
+}
+
+/// Precomputed data available for the optimizer iterations:
+pub struct Obs<'a> {
+    /// Intrinsic parameters of the camera.
+    pub intrinsics: &'a Intrinsics,
+    /// Points of the reference ("keyframe") depth map, in the keyframe frame.
+    pub points: &'a [Point3],
+    /// Vertices and normals of the current depth map.
+    pub vertex_normals: &'a DMatrix<VertexNormal>,
+    /// Points further than this distance (in the depth unit)
+    /// from their associated vertex are rejected as outliers.
+    pub max_distance: Float,
+    /// Robust loss used to weight the residuals.
+    pub robust_loss: &'a dyn RobustLoss,
+    /// Optional prior on the motion, adding a term to the energy.
+    pub motion_prior: Option<MotionPrior>,
+    /// Parameters of the optimizer.
+    pub lm_config: LMConfig,
+}
+
+/// `(energy, residuals_and_jacobians, scale)`.
+type Precomputed = (Float, Vec<(Float, Vec6)>, Float);
+
+impl IcpOptimizerState {
+    /// Precompute the energy of a motion.
+    /// Also return the residuals with their jacobians, and the residuals scale.
+    /// If `scale` is `None`, it is estimated by the robust loss with the residuals
+    /// of this motion, otherwise the given scale is used.
+    #[allow(clippy::cast_precision_loss)]
+    fn eval_energy(obs: &Obs, motion: &Iso3, scale: Option<Float>) -> Precomputed {
+        let mut residuals = Vec::new();
+        for point in obs.points.iter() {
+            let x2 = motion * point;
+            if let Some((q, n)) = associate(obs.intrinsics, obs.vertex_normals, &x2) {
+                let diff = x2 - q;
+                if diff.norm() <= obs.max_distance {
+                    residuals.push(point_to_plane(&x2, &q, &n));
+                }
+            }
+        }
+        let r: Vec<Float> = residuals.iter().map(|(r, _)| *r).collect();
+        let scale = scale.unwrap_or_else(|| obs.robust_loss.scale(&r));
+        let mut energy_sum: Float = r.iter().map(|&r| obs.robust_loss.rho(r, scale)).sum();
+        if let Some(prior) = &obs.motion_prior {
+            let e = se3::log(prior.motion.inverse() * motion);
+            energy_sum += e.dot(&(prior.information * e));
+        }
+        let energy = energy_sum / r.len() as Float;
+        (energy, residuals, scale)
+    }
+
+    /// Fully evaluate a model.
+    /// Each residual is weighted by the robust loss (IRLS) in both the gradient and the hessian.
+    fn compute_eval_data(obs: &Obs, model: Iso3, pre: Precomputed) -> EvalData {
+        let (energy, residuals, scale) = pre;
+        let nb_residuals = residuals.len();
+        let mut hessian = Mat6::zeros();
+        let mut gradient = Vec6::zeros();
+        for (r, jac) in residuals {
+            let w = obs.robust_loss.weight(r, scale);
+            hessian += (w * jac) * jac.transpose();
+            gradient += (w * r) * jac;
+        }
+
+        // Add the prior term. With `e = log(prior^-1 * motion)`,
+        // the jacobian of `e` with respect to `delta` is approximately `Ad(motion^-1)`.
+        if let Some(prior) = &obs.motion_prior {
+            let e = se3::log(prior.motion.inverse() * model);
+            let adj = se3::adjoint(model.inverse());
+            hessian += adj.transpose() * prior.information * adj;
+            gradient += adj.transpose() * (prior.information * e);
+        }
+        EvalData {
+            hessian,
+            gradient,
+            energy,
+            nb_residuals,
+            model,
+            scale,
+        }
+    }
+}
+
+/// `impl<'a> optimizer::State<Obs<'a>, EvalState, Iso3, String> for IcpOptimizerState`.
+impl<'a> optimizer::State<Obs<'a>, EvalState, Iso3, String> for IcpOptimizerState {
+    /// Initialize the optimizer state.
+    fn init(obs: &Obs, model: Iso3) -> Self {
+        Self {
+            config: obs.lm_config,
+            lm_coef: obs.lm_config.initial_coef,
+            eval_data: Self::compute_eval_data(obs, model, Self::eval_energy(obs, &model, None)),
+        }
+    }
+
+    /// Compute the step using Levenberg-Marquardt.
+    /// Apply the step in a forward compositional approach: `exp(-delta) * motion`.
+    /// May return an error at the Cholesky decomposition of the hessian.
+    fn step(&self) -> Result<Iso3, String> {
+        let mut hessian = self.eval_data.hessian;
+        for k in 0..6 {
+            hessian[(k, k)] *= 1.0 + self.lm_coef;
+        }
+        let cholesky = hessian
+            .cholesky()
+            .ok_or("Error at Cholesky decomposition of hessian")?;
+        let delta = cholesky.solve(&self.eval_data.gradient);
+        let motion = se3::exp(-delta) * self.eval_data.model;
+        Ok(lm_optimizer::renormalize(motion))
+    }
+
+    /// Compute residuals and energy of the new model.
+    /// Then, evaluate the new hessian and gradient if the energy has decreased.
+    /// Residuals are evaluated with the scale of the current motion.
+    fn eval(&self, obs: &Obs, model: Iso3) -> EvalState {
+        let pre = Self::eval_energy(obs, &model, Some(self.eval_data.scale));
+        let energy = pre.0;
+        let old_energy = self.eval_data.energy;
+        if energy > old_energy {
+            Err(energy)
+        } else {
+            Ok(Self::compute_eval_data(obs, model, pre))
+        }
+    }
+
+    /// Stop after too many iterations,
+    /// or if the energy variation or the step is too low.
+    ///
+    /// Also update the Levenberg-Marquardt coefficient
+    /// depending on if the energy increased or decreased.
+    fn stop_criterion(self, nb_iter: usize, eval_state: EvalState) -> (Self, Continue) {
+        match eval_state {
+            Err(_) => {
+                let (lm_coef, continuation) = self.config.stop(nb_iter, self.lm_coef, None);
+                (Self { lm_coef, ..self }, continuation)
+            }
+            Ok(eval_data) => {
+                let old_energy = self.eval_data.energy;
+                let step_norm = se3::log(self.eval_data.model.inverse() * eval_data.model).norm();
+                let accepted = Some((old_energy, eval_data.energy, step_norm));
+                let (lm_coef, continuation) = self.config.stop(nb_iter, self.lm_coef, accepted);
+                let kept_state = Self {
+                    lm_coef,
+                    eval_data,
+                    ..self
+                };
+                (kept_state, continuation)
+            }
+        }
+    } // fn stop_criterion
+} // impl optimizer::State<...> for IcpOptimizerState
+
+// Helper ######################################################################
+
+/// Point-to-plane residual `n^T (x2 - q)` of a point `x2` associated
+/// with the vertex `q` of normal `n`, and its jacobian.
+///
+/// The jacobian with respect to `delta` in `exp(delta) * motion` is `[ n, x2 x n ]`.
+fn point_to_plane(x2: &Point3, q: &Point3, n: &Vec3) -> (Float, Vec6) {
+    let w = x2.coords.cross(n);
+    (n.dot(&(x2 - q)), Vec6::new(n.x, n.y, n.z, w.x, w.y, w.z))
+}
+
+/// Projective data association: vertex and normal of the pixel
+/// in which a point (in the current camera frame) is projected.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+#[allow(clippy::cast_precision_loss)]
+fn associate(
+    intrinsics: &Intrinsics,
+    vertex_normals: &DMatrix<VertexNormal>,
+    point: &Point3,
+) -> VertexNormal {
+    if point.z <= 0.0 {
+        return None;
+    }
+    let (height, width) = vertex_normals.shape();
+    let uvz = intrinsics.project(*point);
+    let u = (uvz.x / uvz.z).round();
+    let v = (uvz.y / uvz.z).round();
+    if u >= 0.0 && u < width as Float && v >= 0.0 && v < height as Float {
+        vertex_normals[(v as usize, u as usize)]
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::misc::helper::bounded;
+    use approx;
+    use quickcheck_macros;
+
+    // Precision of centered finite differences with f32 computations.
+    const EPSILON_FINITE_DIFF: Float = 1e-2;
+
+    // PROPERTY TESTS ################################################
+
+    #[quickcheck_macros::quickcheck]
+    fn point_to_plane_jacobian(x: Float, y: Float, n1: Float, n2: Float) -> bool {
+        let x2 = Point3::new(bounded(x), bounded(y), 2.0);
+        let q = Point3::new(0.1, -0.2, 2.1);
+        let n = Vec3::new(bounded(n1), bounded(n2), -1.0).normalize();
+        let (_, jacobian) = point_to_plane(&x2, &q, &n);
+        let h = 1e-3;
+        let numeric = Vec6::from_fn(|k, _| {
+            let mut delta = Vec6::zeros();
+            delta[k] = h;
+            let r_plus = point_to_plane(&(se3::exp(delta) * x2), &q, &n).0;
+            let r_minus = point_to_plane(&(se3::exp(-delta) * x2), &q, &n).0;
+            (r_plus - r_minus) / (2.0 * h)
+        });
+        approx::relative_eq!(jacobian, numeric, epsilon = EPSILON_FINITE_DIFF)
+    }
+}
@@ -0,0 +1,212 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Inertial prediction of the poses of tracked frames.
+//!
+//! IMU measurements between two frames are preintegrated
+//! to predict the pose of the new frame from the pose and velocity of the previous one.
+//! The prediction and its covariance form a pose prior,
+//! coupled with the photometric energy of the tracking.
+//! The velocity is then re-estimated from the tracked pose.
+//!
+//! The coupling is loose: the inertial term is only a prior on the pose
+//! and the IMU biases are not part of the estimated states.
+//! Gravity and biases are initialized assuming the camera is at rest
+//! during the measurements received before the first tracked frame,
+//! and are then kept fixed.
+
+use nalgebra::U3;
+
+use crate::core::imu::{self, Bias, ImuMeasurement, NavState, Noise, Preintegration};
+use crate::core::track::motion_model::PosePrior;
+use crate::math::se3;
+use crate::misc::type_aliases::{Float, Iso3, Mat3, Mat6, Vec3};
+
+/// Configuration of the inertial prediction.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Config {
+    /// Noise densities of the IMU.
+    pub noise: Noise,
+    /// Pose of the camera in the body (IMU) frame.
+    pub camera_to_body: Iso3,
+    /// Magnitude of gravity (in m/s^2), 9.81 on Earth.
+    pub gravity_magnitude: Float,
+    /// Variance of each component of the velocity estimate (in m^2/s^2).
+    pub velocity_variance: Float,
+    /// Variance of the photometric residuals noise,
+    /// to express the inertial term in the unit of the photometric energy.
+    pub image_noise_variance: Float,
+}
+
+/// Inertial state at the last tracked frame.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct InertialState {
+    /// Timestamp of the frame (of color image).
+    pub timestamp: f64,
+    /// Pose and velocity of the body.
+    pub nav: NavState,
+    /// Gravity vector in the world frame.
+    pub gravity: Vec3,
+    /// Biases of the IMU, fixed since the initialization.
+    pub bias: Bias,
+}
+
+/// Buffer of IMU measurements and inertial state of the last tracked frame.
+pub struct Inertial {
+    config: Config,
+    measurements: Vec<ImuMeasurement>,
+    state: Option<InertialState>,
+}
+
+impl Inertial {
+    /// Create an uninitialized inertial predictor.
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            measurements: Vec::new(),
+            state: None,
+        }
+    }
+
+    /// Configuration of the inertial prediction.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Inertial state of the last frame, `None` before initialization.
+    pub fn state(&self) -> Option<&InertialState> {
+        self.state.as_ref()
+    }
+
+    /// Add new measurements, with increasing timestamps.
+    pub fn add_measurements(&mut self, measurements: &[ImuMeasurement]) {
+        self.measurements.extend_from_slice(measurements);
+    }
+
+    /// Initialize gravity and biases with the measurements up to a frame,
+    /// assuming the body was at rest, and start at this frame with a null velocity.
+    /// Return `false` if there is no measurement before the frame yet.
+    pub fn init(&mut self, timestamp: f64, camera_pose: Iso3) -> bool {
+        let nb_before = self
+            .measurements
+            .iter()
+            .take_while(|m| m.timestamp <= timestamp)
+            .count();
+        let init = imu::static_init(
+            &self.measurements[..nb_before],
+            self.config.gravity_magnitude,
+        );
+        if let Some((gravity_body, bias)) = init {
+            let pose = camera_pose * self.config.camera_to_body.inverse();
+            self.state = Some(InertialState {
+                timestamp,
+                nav: NavState {
+                    pose,
+                    velocity: Vec3::zeros(),
+                },
+                gravity: pose.rotation * gravity_body,
+                bias,
+            });
+            self.drop_measurements(timestamp);
+        }
+        self.state.is_some()
+    }
+
+    /// Predict the camera pose of a new frame with its covariance,
+    /// and return the preintegration used for the prediction.
+    /// `None` before initialization.
+    pub fn predict(&self, timestamp: f64) -> Option<(PosePrior, Preintegration)> {
+        let state = self.state.as_ref()?;
+        let preintegration = Preintegration::between(
+            &self.measurements,
+            state.timestamp,
+            timestamp,
+            state.bias,
+            &self.config.noise,
+        );
+        let nav = preintegration.predict(&state.nav, &state.gravity);
+
+        // Covariance of the body pose twist, with a right perturbation.
+        // Rotation and position errors of the preintegration are expressed
+        // respectively in the body frame at j and at i.
+        let cov = &preintegration.covariance;
+        let delta_rot_t = preintegration
+            .delta_rotation
+            .to_rotation_matrix()
+            .into_inner()
+            .transpose();
+        let dt = preintegration.delta_t;
+        let cov_pp: Mat3 = cov.fixed_slice::<U3, U3>(6, 6).into_owned();
+        let cov_pr: Mat3 = cov.fixed_slice::<U3, U3>(6, 0).into_owned();
+        let cov_rr: Mat3 = cov.fixed_slice::<U3, U3>(0, 0).into_owned();
+        let velocity_cov = Mat3::identity() * (self.config.velocity_variance * dt * dt);
+        let mut body_cov = Mat6::zeros();
+        body_cov
+            .fixed_slice_mut::<U3, U3>(0, 0)
+            .copy_from(&(delta_rot_t * cov_pp * delta_rot_t.transpose() + velocity_cov));
+        body_cov
+            .fixed_slice_mut::<U3, U3>(0, 3)
+            .copy_from(&(delta_rot_t * cov_pr));
+        body_cov
+            .fixed_slice_mut::<U3, U3>(3, 0)
+            .copy_from(&(cov_pr.transpose() * delta_rot_t.transpose()));
+        body_cov.fixed_slice_mut::<U3, U3>(3, 3).copy_from(&cov_rr);
+
+        // Camera pose, with the perturbation moved to the camera frame.
+        let adj = se3::adjoint(self.config.camera_to_body.inverse());
+        let camera_cov: Mat6 = adj * body_cov * adj.transpose();
+        let prior = PosePrior {
+            pose: nav.pose * self.config.camera_to_body,
+            covariance: Some(camera_cov / self.config.image_noise_variance),
+        };
+        Some((prior, preintegration))
+    }
+
+    /// Update the state with a new frame.
+    /// If it was tracked successfully, its velocity is re-estimated from the tracked pose,
+    /// otherwise the inertial prediction is kept.
+    pub fn update(
+        &mut self,
+        timestamp: f64,
+        camera_pose: Option<Iso3>,
+        preintegration: &Preintegration,
+    ) {
+        let camera_to_body = self.config.camera_to_body;
+        if let Some(state) = self.state.as_mut() {
+            let predicted = preintegration.predict(&state.nav, &state.gravity);
+            let dt = preintegration.delta_t;
+            state.nav = match camera_pose {
+                Some(camera_pose) if dt > 0.0 => {
+                    let pose = camera_pose * camera_to_body.inverse();
+                    let rotation_i = state.nav.pose.rotation;
+                    let velocity_i = (pose.translation.vector
+                        - state.nav.pose.translation.vector
+                        - 0.5 * state.gravity * dt * dt
+                        - rotation_i * preintegration.delta_position)
+                        / dt;
+                    NavState {
+                        pose,
+                        velocity: velocity_i
+                            + state.gravity * dt
+                            + rotation_i * preintegration.delta_velocity,
+                    }
+                }
+                _ => predicted,
+            };
+            state.timestamp = timestamp;
+        }
+        self.drop_measurements(timestamp);
+    }
+
+    /// Drop the measurements that are not needed anymore after a frame,
+    /// keeping the last one before the frame that is still valid after it.
+    fn drop_measurements(&mut self, timestamp: f64) {
+        let nb_before = self
+            .measurements
+            .iter()
+            .take_while(|m| m.timestamp <= timestamp)
+            .count();
+        self.measurements.drain(..nb_before.saturating_sub(1));
+    }
+}
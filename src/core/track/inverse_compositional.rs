@@ -12,29 +12,47 @@ use itertools::izip;
 use nalgebra::DMatrix;
 
 use crate::core::{
-    camera::Intrinsics,
+    camera::{CameraModel, Intrinsics},
     candidates::coarse_to_fine as candidates,
+    depth_noise::{self, DepthNoiseModel},
     gradient,
+    imu::ImuMeasurement,
     inverse_depth::{self, InverseDepth},
-    multires,
-    track::lm_optimizer::{self, LMOptimizerState},
+    loop_closure, multires, photometric,
+    track::frame_tracker::{self, FrameTracker},
+    track::inertial::{self, Inertial},
+    track::keyframe::{FrameInfo, KeyframePolicy},
+    track::lm_optimizer::{
+        self, AffineBrightness, IdepthUncertainty, LMConfig, LMOptimizerState, Readout,
+        RollingShutter,
+    },
+    track::monocular,
+    track::motion_model::{self, MotionModel, PosePrior},
+    track::sliding_window::{self, SlidingWindow, WindowResult},
 };
 use crate::math::optimizer::State as _;
+use crate::math::robust::RobustLoss;
 use crate::misc::helper;
-use crate::misc::type_aliases::{Float, Iso3, Mat6, Point2, Vec6};
+use crate::misc::type_aliases::{Float, Iso3, Mat6, Mat8, Point2, Point3, Vec2, Vec6};
+
+pub use crate::core::track::frame_tracker::{LevelResult, TrackingResult, TrackingStatus};
 
 /// Type alias to easily spot vectors that are indexed over multi-resolution levels.
 pub type Levels<T> = Vec<T>;
 
+/// Max squared Mahalanobis distance between a keyframe inverse depth
+/// and a new measurement to fuse them (95% quantile of a chi-squared with 1 dof).
+const IDEPTH_FUSION_GATE: Float = 3.84;
+
 /// Struct used for tracking the camera at each frame.
 /// Can only be constructed by initialization from a `Config`.
-pub struct Tracker {
-    config: Config,
-    state: State,
+pub struct Tracker<C: CameraModel = Intrinsics> {
+    config: Config<C>,
+    state: State<C>,
 }
 
 /// Configuration of the Tracker.
-pub struct Config {
+pub struct Config<C: CameraModel = Intrinsics> {
     /// Number of levels in the multi-resolution pyramids of images.
     pub nb_levels: usize,
     /// Threshold for the candidates selection algorithm.
@@ -42,34 +60,106 @@ pub struct Config {
     /// Scale of the depth 16 bit images.
     /// This is 5000.0 for the TUM RGB-D dataset.
     pub depth_scale: Float,
-    /// Camera intrinsic parameters.
-    pub intrinsics: Intrinsics,
-    /// Default variance of the inverse depth values coming from the depth map.
-    pub idepth_variance: Float,
+    /// Camera model, for example the pinhole `Intrinsics` of undistorted images,
+    /// or a distortion model to track raw images.
+    pub intrinsics: C,
+    /// Noise model of the depth sensor, giving the variance of each inverse depth
+    /// of the depth maps, for example `depth_noise::KINECT_V1`.
+    /// Use `depth_noise::ConstantIdepth` for the same variance everywhere.
+    pub depth_noise: Box<dyn DepthNoiseModel>,
+    /// Strategy merging inverse depths in the multi-resolution pyramids,
+    /// usually `inverse_depth::strategy_dso_mean`.
+    /// `inverse_depth::strategy_inverse_variance` takes advantage of the depth noise model.
+    pub idepth_fusion: inverse_depth::Strategy,
+    /// Robust loss weighting the photometric residuals.
+    /// Use `robust::Squared` for a plain least squares optimization.
+    pub robust_loss: Box<dyn RobustLoss>,
+    /// Do not estimate the affine brightness change between frames,
+    /// i.e. assume brightness constancy.
+    pub fix_affine_brightness: bool,
+    /// Policy deciding when to change the keyframe.
+    pub keyframe_policy: Box<dyn KeyframePolicy>,
+    /// Motion model used to initialize the pose of a new frame.
+    pub motion_model: MotionModel,
+    /// Parameters of the Levenberg-Marquardt optimizer at each level.
+    /// Must contain `nb_levels` elements, the first one being for the highest resolution.
+    pub lm_configs: Levels<LMConfig>,
+    /// Weight of the inverse depth residuals relative to the photometric residuals,
+    /// for a joint photometric and geometric optimization as in DVO-SLAM.
+    /// If `None`, only photometric residuals are used.
+    pub depth_residual_weight: Option<Float>,
+    /// Refine the keyframe inverse depths with the depth map of each tracked frame.
+    pub refine_keyframe_idepth: bool,
+    /// Jointly refine the poses and inverse depths of the last keyframes
+    /// each time a new keyframe is created, as the sliding window backend of DSO.
+    /// If `None`, keyframes are independent.
+    pub sliding_window: Option<sliding_window::Config>,
+    /// Predict the pose of each frame by preintegration of IMU measurements,
+    /// added with `tracker.add_imu_measurements(...)`.
+    /// The prediction is coupled with the photometric energy as a pose prior,
+    /// unless an external prior is provided for the frame.
+    pub inertial: Option<inertial::Config>,
+    /// Readout time (in seconds) between the first and last rows of a rolling shutter camera.
+    /// The pose of each row is interpolated with the constant twist velocity
+    /// of the camera between the previous frame and the tracked one.
+    /// The sliding window, monocular inverse depths and loop closures ignore it.
+    /// If `None`, the camera has a global shutter.
+    pub readout_time: Option<Float>,
+    /// Photometric calibration applied to each image before building its pyramid.
+    /// If `None`, images are used as they are.
+    pub photometric: Option<photometric::Calibration>,
+    /// Variance of the image noise (in squared intensities), to weight each photometric
+    /// residual by its predicted variance, adding the inverse depth variance
+    /// of its point projected through the warp. Points with uncertain depths count less.
+    /// If `None`, all points have the same weight.
+    pub intensity_variance: Option<Float>,
 }
 
 /// Internal state of the tracker.
-struct State {
-    keyframe_multires_data: MultiresData,
+struct State<C: CameraModel> {
+    keyframe_multires_data: MultiresData<C>,
     keyframe_depth_timestamp: f64,
     keyframe_img_timestamp: f64,
     keyframe_pose: Iso3,
+    keyframe_affine: AffineBrightness,
+    keyframe_reference_energy: Option<Float>,
     current_frame_depth_timestamp: f64,
     current_frame_img_timestamp: f64,
     current_frame_pose: Iso3,
+    current_frame_affine: AffineBrightness,
+    current_frame_covariance: Option<Mat6>,
+    /// Timestamps and poses of the last successfully tracked frames, oldest first.
+    pose_history: Vec<(f64, Iso3)>,
+    /// External prior for the next frame to track.
+    pose_prior: Option<PosePrior>,
+    /// Configuration of the inverse depth estimation in monocular mode,
+    /// with the pinhole intrinsics of the highest resolution.
+    monocular: Option<(monocular::Config, Intrinsics)>,
+    /// Optimization backend of the last keyframes.
+    sliding_window: Option<SlidingWindow<C>>,
+    /// Buffer of IMU measurements and inertial state.
+    inertial: Option<Inertial>,
 }
 
 /// Mostly multi-resolution data related to the frame.
 #[allow(clippy::type_complexity)]
-struct MultiresData {
-    intrinsics_multires: Levels<Intrinsics>,
+struct MultiresData<C: CameraModel> {
+    intrinsics_multires: Levels<C>,
     img_multires: Levels<DMatrix<u8>>,
+    /// Mask of candidate points, at the highest resolution.
+    candidates_points: DMatrix<bool>,
+    /// Inverse depths of candidate points, at the highest resolution.
+    idepth_candidates: DMatrix<InverseDepth>,
     usable_candidates_multires: Levels<(Vec<(usize, usize)>, Vec<Float>)>,
+    idepth_variances_multires: Levels<Vec<Float>>,
+    gradients_multires: Levels<(DMatrix<i16>, DMatrix<i16>)>,
     jacobians_multires: Levels<Vec<Vec6>>,
     hessians_multires: Levels<Vec<Mat6>>,
+    /// Rolling shutter readout of the frame at each level.
+    readout_multires: Option<Levels<Readout>>,
 }
 
-impl Config {
+impl<C: CameraModel> Config<C> {
     /// Initialize a tracker with the first RGB-D frame.
     pub fn init(
         self,
@@ -77,12 +167,81 @@ impl Config {
         depth_map: &DMatrix<u16>,
         keyframe_img_timestamp: f64,
         img: DMatrix<u8>,
-    ) -> Tracker {
+    ) -> Tracker<C> {
+        let idepth_map = to_idepth_map(&self, depth_map);
+        self.init_impl(
+            keyframe_depth_timestamp,
+            &idepth_map,
+            keyframe_img_timestamp,
+            img,
+            None,
+        )
+    }
+
+    /// Initialize a tracker with the first image and an inverse depth map,
+    /// for example computed by stereo matching.
+    /// Use `tracker.track_with_idepth(...)` to track the next frames.
+    pub fn init_with_idepth(
+        self,
+        keyframe_depth_timestamp: f64,
+        idepth_map: &DMatrix<InverseDepth>,
+        keyframe_img_timestamp: f64,
+        img: DMatrix<u8>,
+    ) -> Tracker<C> {
+        self.init_impl(
+            keyframe_depth_timestamp,
+            idepth_map,
+            keyframe_img_timestamp,
+            img,
+            None,
+        )
+    }
+
+    /// Initialize a tracker with the first image and its inverse depth map.
+    fn init_impl(
+        mut self,
+        keyframe_depth_timestamp: f64,
+        idepth_map: &DMatrix<InverseDepth>,
+        keyframe_img_timestamp: f64,
+        img: DMatrix<u8>,
+        monocular: Option<(monocular::Config, Intrinsics)>,
+    ) -> Tracker<C> {
+        assert_eq!(
+            self.nb_levels,
+            self.lm_configs.len(),
+            "There must be one LMConfig per level"
+        );
+
         // Precompute multi-resolution first frame data.
         let intrinsics_multires = self.intrinsics.clone().multi_res(self.nb_levels);
+        let img = correct_image(self.photometric.as_ref(), img);
         let img_multires = multires::mean_pyramid(self.nb_levels, img);
-        let keyframe_multires_data =
-            precompute_multires_data(&self, depth_map, intrinsics_multires, img_multires);
+        let keyframe_multires_data = precompute_multires_data(
+            &self,
+            idepth_map,
+            intrinsics_multires,
+            img_multires,
+            Vec6::zeros(),
+        );
+
+        // Start the sliding window with the first keyframe.
+        let sliding_window = self.sliding_window.take().map(|window_config| {
+            assert!(
+                window_config.level < self.nb_levels,
+                "The sliding window level must be one of the pyramid levels"
+            );
+            let mut window = SlidingWindow::new(window_config);
+            window.add_keyframe(window_keyframe(
+                &window,
+                &keyframe_multires_data,
+                keyframe_depth_timestamp,
+                Iso3::identity(),
+                AffineBrightness::identity(),
+            ));
+            window
+        });
+
+        let inertial = self.inertial.take().map(Inertial::new);
 
         // Regroup everything under the returned Tracker.
         Tracker {
@@ -91,23 +250,67 @@ impl Config {
                 keyframe_depth_timestamp,
                 keyframe_img_timestamp,
                 keyframe_pose: Iso3::identity(),
+                keyframe_affine: AffineBrightness::identity(),
+                keyframe_reference_energy: None,
                 current_frame_depth_timestamp: keyframe_depth_timestamp,
                 current_frame_img_timestamp: keyframe_img_timestamp,
                 current_frame_pose: Iso3::identity(),
+                current_frame_affine: AffineBrightness::identity(),
+                current_frame_covariance: Some(Mat6::zeros()),
+                pose_history: vec![(keyframe_depth_timestamp, Iso3::identity())],
+                pose_prior: None,
+                monocular,
+                sliding_window,
+                inertial,
             },
             config: self,
         }
     }
 } // impl Config
 
-/// Precompute the multi-resolution data of a frame.
-#[allow(clippy::used_underscore_binding)]
-fn precompute_multires_data(
-    config: &Config,
-    depth_map: &DMatrix<u16>,
-    intrinsics_multires: Levels<Intrinsics>,
+impl Config<Intrinsics> {
+    /// Initialize a tracker for a monocular camera, with the first image.
+    /// Inverse depths of keyframes are then estimated by epipolar line search,
+    /// which requires a pinhole camera.
+    /// Use `tracker.track_monocular(...)` to track the next frames.
+    pub fn init_monocular(
+        self,
+        keyframe_img_timestamp: f64,
+        img: DMatrix<u8>,
+        monocular: monocular::Config,
+    ) -> Tracker {
+        let idepth_map = monocular::init_idepth_map(img.shape(), monocular.init);
+        let intrinsics = self.intrinsics.clone();
+        self.init_impl(
+            keyframe_img_timestamp,
+            &idepth_map,
+            keyframe_img_timestamp,
+            img,
+            Some((monocular, intrinsics)),
+        )
+    }
+}
+
+/// Precompute the multi-resolution data of a frame,
+/// given the twist velocity of the camera during its readout.
+#[allow(clippy::cast_precision_loss)]
+fn precompute_multires_data<C: CameraModel>(
+    config: &Config<C>,
+    idepth_map: &DMatrix<InverseDepth>,
+    intrinsics_multires: Levels<C>,
     img_multires: Levels<DMatrix<u8>>,
-) -> MultiresData {
+    velocity: Vec6,
+) -> MultiresData<C> {
+    let readout_multires = config.readout_time.map(|readout_time| {
+        img_multires
+            .iter()
+            .map(|img| Readout {
+                line_delay: readout_time / img.nrows() as Float,
+                velocity,
+            })
+            .collect()
+    });
+
     // Precompute multi-resolution of keyframe gradients.
     let mut gradients_multires = multires::gradients_xy(&img_multires);
     gradients_multires.insert(0, gradient::centered(&img_multires[0]));
@@ -123,62 +326,190 @@ fn precompute_multires_data(
     )
     .pop()
     .unwrap();
+    let idepth_candidates =
+        helper::zip_mask_map(idepth_map, &candidates_points, InverseDepth::Unknown, |x| x);
 
-    // Only keep the "usable" points, i.e. those with a known depth information.
-    let from_depth = |z| inverse_depth::from_depth(config.depth_scale, z, config.idepth_variance);
-    let idepth_candidates = helper::zip_mask_map(
-        depth_map,
-        &candidates_points,
-        InverseDepth::Unknown,
-        from_depth,
+    // Regroup everything under a MultiresData.
+    let mut data = MultiresData {
+        intrinsics_multires,
+        img_multires,
+        candidates_points,
+        idepth_candidates: DMatrix::repeat(0, 0, InverseDepth::Unknown),
+        usable_candidates_multires: Vec::new(),
+        idepth_variances_multires: Vec::new(),
+        gradients_multires,
+        jacobians_multires: Vec::new(),
+        hessians_multires: Vec::new(),
+        readout_multires,
+    };
+    update_idepth_data(
+        config.nb_levels,
+        config.idepth_fusion,
+        &mut data,
+        idepth_candidates,
     );
-    let fuse = |a, b, c, d| inverse_depth::fuse(a, b, c, d, inverse_depth::strategy_dso_mean);
-    let idepth_multires = multires::limited_sequence(config.nb_levels, idepth_candidates, |m| {
-        multires::halve(m, fuse)
-    });
-    let usable_candidates_multires: Levels<_> = idepth_multires.iter().map(extract_z).collect();
+    data
+}
+
+/// Update the multi-resolution data depending on the inverse depths of candidates.
+#[allow(clippy::used_underscore_binding)]
+fn update_idepth_data<C: CameraModel>(
+    nb_levels: usize,
+    idepth_fusion: inverse_depth::Strategy,
+    data: &mut MultiresData<C>,
+    idepth_candidates: DMatrix<InverseDepth>,
+) {
+    // Only keep the "usable" points, i.e. those with a known depth information
+    // and inside the valid domain of the camera model.
+    let idepth_multires: Vec<_> =
+        idepth_pyramid(nb_levels, idepth_fusion, idepth_candidates.clone())
+            .into_iter()
+            .zip(data.intrinsics_multires.iter())
+            .map(|(idepth_mat, intrinsics)| mask_unprojectable(intrinsics, idepth_mat))
+            .collect();
+    data.usable_candidates_multires = idepth_multires.iter().map(extract_z).collect();
+    data.idepth_variances_multires = idepth_multires.iter().map(extract_variances).collect();
+    data.idepth_candidates = idepth_candidates;
 
     // Precompute the Jacobians.
-    let jacobians_multires: Levels<Vec<Vec6>> = izip!(
-        &intrinsics_multires,
-        &usable_candidates_multires,
-        &gradients_multires,
+    data.jacobians_multires = izip!(
+        &data.intrinsics_multires,
+        &data.usable_candidates_multires,
+        &data.gradients_multires,
     )
-    .map(|(intrinsics, (coord, _z), (gx, gy))| warp_jacobians(intrinsics, coord, _z, gx, gy))
+    .enumerate()
+    .map(|(lvl, (intrinsics, (coord, _z), (gx, gy)))| {
+        let readout = data.readout_multires.as_ref().map(|r| &r[lvl]);
+        warp_jacobians(intrinsics, coord, _z, gx, gy, readout)
+    })
     .collect();
 
     // Precompute the Hessians.
-    let hessians_multires: Levels<_> = jacobians_multires.iter().map(hessians_vec).collect();
-
-    // Regroup everything under a MultiresData.
-    MultiresData {
-        intrinsics_multires,
-        img_multires,
-        usable_candidates_multires,
-        jacobians_multires,
-        hessians_multires,
-    }
+    data.hessians_multires = data.jacobians_multires.iter().map(hessians_vec).collect();
 }
 
-impl Tracker {
+impl<C: CameraModel> Tracker<C> {
     /// Track a new frame.
     /// Internally mutates the tracker state.
     ///
     /// You can use `tracker.current_frame()` after tracking to retrieve the new frame pose.
-    #[allow(clippy::used_underscore_binding)]
-    #[allow(clippy::cast_precision_loss)]
+    /// The returned result gives details about the optimization and keyframe decision.
     pub fn track(
         &mut self,
         depth_time: f64,
         depth_map: &DMatrix<u16>,
         img_time: f64,
         img: DMatrix<u8>,
-    ) {
-        let mut lm_model = self.state.current_frame_pose.inverse() * self.state.keyframe_pose;
+    ) -> TrackingResult {
+        let idepth_map = to_idepth_map(&self.config, depth_map);
+        self.track_impl(depth_time, Some(&idepth_map), img_time, img)
+    }
+
+    /// Track a new frame with an inverse depth map instead of a depth map,
+    /// for example computed by stereo matching.
+    pub fn track_with_idepth(
+        &mut self,
+        depth_time: f64,
+        idepth_map: &DMatrix<InverseDepth>,
+        img_time: f64,
+        img: DMatrix<u8>,
+    ) -> TrackingResult {
+        self.track_impl(depth_time, Some(idepth_map), img_time, img)
+    }
+
+    /// Track a new frame of a monocular camera,
+    /// for a tracker initialized with `config.init_monocular(...)`.
+    /// Inverse depths of the keyframe are refined with this frame,
+    /// if it was tracked successfully or if a pose prior was provided for it.
+    pub fn track_monocular(&mut self, img_time: f64, img: DMatrix<u8>) -> TrackingResult {
+        assert!(
+            self.state.monocular.is_some(),
+            "The tracker was not initialized in monocular mode"
+        );
+        self.track_impl(img_time, None, img_time, img)
+    }
+
+    /// Track a new frame, with or without its inverse depth map.
+    #[allow(clippy::used_underscore_binding)]
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::too_many_lines)]
+    fn track_impl(
+        &mut self,
+        depth_time: f64,
+        idepth_map: Option<&DMatrix<InverseDepth>>,
+        img_time: f64,
+        img: DMatrix<u8>,
+    ) -> TrackingResult {
+        // Preintegrate IMU measurements since the last frame.
+        let (last_time, last_pose) = (
+            self.state.current_frame_img_timestamp,
+            self.state.current_frame_pose,
+        );
+        let preintegration = self.state.inertial.as_mut().and_then(|inertial| {
+            if inertial.state().is_none() {
+                inertial.init(last_time, last_pose);
+            }
+            inertial.predict(img_time)
+        });
+
+        // Initialize the motion with the external prior, the inertial prediction,
+        // or the motion model.
+        let pose_prior = self
+            .state
+            .pose_prior
+            .take()
+            .or_else(|| preintegration.map(|(prior, _)| prior));
+        let predicted_pose = match pose_prior {
+            Some(prior) => prior.pose,
+            None => self
+                .config
+                .motion_model
+                .predict(&self.state.pose_history, depth_time),
+        };
+        let motion_prior =
+            pose_prior.and_then(|prior| prior.motion_prior(&self.state.keyframe_pose));
+        let mut lm_model = lm_optimizer::Model {
+            motion: predicted_pose.inverse() * self.state.keyframe_pose,
+            affine: self
+                .state
+                .current_frame_affine
+                .compose(&self.state.keyframe_affine.inverse()),
+        };
+        let img = correct_image(self.config.photometric.as_ref(), img);
         let img_multires = multires::mean_pyramid(self.config.nb_levels, img);
+        let idepth_multires = idepth_map.and_then(|idepth_map| {
+            if self.config.depth_residual_weight.is_some() || self.config.refine_keyframe_idepth {
+                Some(idepth_pyramid(
+                    self.config.nb_levels,
+                    self.config.idepth_fusion,
+                    idepth_map.clone(),
+                ))
+            } else {
+                None
+            }
+        });
         let keyframe_data = &self.state.keyframe_multires_data;
-        let mut optimization_went_well = true;
+        let mut status = TrackingStatus::Ok;
+        let mut levels = vec![None; self.config.nb_levels];
+        let mut energy = Float::INFINITY;
+        let mut nb_residuals = 0;
+        let mut hessian = Mat8::zeros();
+        let mut image_velocity = Vec6::zeros();
         for lvl in (0..self.config.nb_levels).rev() {
+            // Camera velocity during the image readout, from the current motion estimate.
+            let rolling_shutter = keyframe_data.readout_multires.as_ref().map(|readouts| {
+                let &(last_time, last_pose) = self.state.pose_history.last().unwrap();
+                let pose = self.state.keyframe_pose * lm_model.motion.inverse();
+                image_velocity =
+                    motion_model::twist_velocity(last_time, &last_pose, depth_time, &pose);
+                RollingShutter {
+                    template: readouts[lvl],
+                    image: Readout {
+                        line_delay: readouts[lvl].line_delay,
+                        velocity: image_velocity,
+                    },
+                }
+            });
             let obs = lm_optimizer::Obs {
                 intrinsics: &keyframe_data.intrinsics_multires[lvl],
                 template: &keyframe_data.img_multires[lvl],
@@ -187,14 +518,46 @@ impl Tracker {
                 _z_candidates: &keyframe_data.usable_candidates_multires[lvl].1,
                 jacobians: &keyframe_data.jacobians_multires[lvl],
                 hessians: &keyframe_data.hessians_multires[lvl],
+                robust_loss: self.config.robust_loss.as_ref(),
+                fix_affine_brightness: self.config.fix_affine_brightness,
+                motion_prior,
+                lm_config: self.config.lm_configs[lvl],
+                depth: self.config.depth_residual_weight.and_then(|weight| {
+                    idepth_multires
+                        .as_ref()
+                        .map(|idepth_multires| lm_optimizer::DepthObs {
+                            idepth: &idepth_multires[lvl],
+                            weight,
+                        })
+                }),
+                rolling_shutter,
+                idepth_uncertainty: self.config.intensity_variance.map(|intensity_variance| {
+                    IdepthUncertainty {
+                        variances: &keyframe_data.idepth_variances_multires[lvl],
+                        gradients: &keyframe_data.gradients_multires[lvl],
+                        intensity_variance,
+                    }
+                }),
             };
             match LMOptimizerState::iterative_solve(&obs, lm_model) {
-                Ok((lm_state, _)) => {
-                    lm_model = lm_state.eval_data.model;
+                Ok((lm_state, nb_iterations)) => {
+                    let eval_data = lm_state.eval_data;
+                    levels[lvl] = Some(LevelResult {
+                        energy: eval_data.energy,
+                        nb_iterations,
+                        nb_residuals: eval_data.nb_residuals,
+                    });
+                    if !eval_data.energy.is_finite() {
+                        status = TrackingStatus::Diverged;
+                        break;
+                    }
+                    lm_model = eval_data.model;
+                    energy = eval_data.energy;
+                    nb_residuals = eval_data.nb_residuals;
+                    hessian = eval_data.hessian;
                 }
-                Err(err) => {
-                    eprintln!("{}", err);
-                    optimization_went_well = false;
+                Err(_) => {
+                    status = TrackingStatus::CholeskyFailure;
                     break;
                 }
             }
@@ -203,51 +566,270 @@ impl Tracker {
         // Update current frame info in tracker.
         self.state.current_frame_depth_timestamp = depth_time;
         self.state.current_frame_img_timestamp = img_time;
-        if optimization_went_well {
-            self.state.current_frame_pose = self.state.keyframe_pose * lm_model.inverse();
+        self.state.current_frame_covariance = None;
+        if status == TrackingStatus::Ok {
+            self.state.current_frame_pose = self.state.keyframe_pose * lm_model.motion.inverse();
+            self.state.current_frame_affine = lm_model.affine.compose(&self.state.keyframe_affine);
+            // The step is applied as `motion * exp(-delta)`, in the keyframe frame.
+            self.state.current_frame_covariance =
+                frame_tracker::pose_covariance(&self.state.keyframe_pose, &hessian, energy);
+            frame_tracker::push_pose_history(
+                &mut self.state.pose_history,
+                depth_time,
+                self.state.current_frame_pose,
+            );
+        }
+        if let (Some(inertial), Some((_, preintegration))) =
+            (&mut self.state.inertial, &preintegration)
+        {
+            let tracked_pose = if status == TrackingStatus::Ok {
+                Some(self.state.current_frame_pose)
+            } else {
+                None
+            };
+            inertial.update(img_time, tracked_pose, preintegration);
         }
 
         // Check if we need to change the keyframe.
         let (coordinates, _z_candidates) = keyframe_data.usable_candidates_multires.last().unwrap();
         let intrinsics = keyframe_data.intrinsics_multires.last().unwrap();
+        let rolling_shutter = keyframe_data.readout_multires.as_ref().map(|readouts| {
+            let template = *readouts.last().unwrap();
+            RollingShutter {
+                template,
+                image: Readout {
+                    line_delay: template.line_delay,
+                    velocity: image_velocity,
+                },
+            }
+        });
         let optical_flow_sum: Float = _z_candidates
             .iter()
             .zip(coordinates.iter())
-            .map(|(&_z, &(x, y))| {
-                let (u, v) = warp(&lm_model, x as Float, y as Float, _z, intrinsics);
-                (x as Float - u).abs() + (y as Float - v).abs()
+            .filter_map(|(&_z, &(x, y))| {
+                let (u, v) = warp(
+                    &lm_model.motion,
+                    x as Float,
+                    y as Float,
+                    _z,
+                    intrinsics,
+                    rolling_shutter.as_ref(),
+                )?;
+                Some((x as Float - u).abs() + (y as Float - v).abs())
             })
             .sum();
         let optical_flow = optical_flow_sum / _z_candidates.len() as Float;
-        eprintln!("Optical_flow: {}", optical_flow);
 
-        let change_keyframe = optical_flow >= 1.0;
+        let nb_candidates = keyframe_data.usable_candidates_multires[0].0.len();
+        let elapsed_time = depth_time - self.state.keyframe_depth_timestamp;
+        let change_keyframe = frame_tracker::change_keyframe(
+            self.config.keyframe_policy.as_ref(),
+            status,
+            &mut self.state.keyframe_reference_energy,
+            energy,
+            |reference_energy| FrameInfo {
+                optical_flow,
+                motion: lm_model.motion,
+                elapsed_time,
+                visible_ratio: nb_residuals as Float / nb_candidates as Float,
+                energy,
+                reference_energy,
+            },
+        );
 
         // In case of keyframe change, update all keyframe info with current frame.
+        let mut window_result = None;
         if change_keyframe {
-            let delta_time = depth_time - self.state.keyframe_depth_timestamp;
-            eprintln!("Changing keyframe after: {} seconds", delta_time);
+            let idepth_map = match (idepth_map, &self.state.monocular) {
+                (Some(idepth_map), _) => idepth_map.clone(),
+                (None, Some((mono, intrinsics))) => monocular::propagate_idepth_map(
+                    &keyframe_data.idepth_candidates,
+                    intrinsics,
+                    &lm_model.motion,
+                    mono,
+                ),
+                (None, None) => unreachable!(),
+            };
             self.state.keyframe_multires_data = precompute_multires_data(
                 &self.config,
-                depth_map,
+                &idepth_map,
                 keyframe_data.intrinsics_multires.clone(),
                 img_multires,
+                image_velocity,
             );
             self.state.keyframe_depth_timestamp = depth_time;
             self.state.keyframe_img_timestamp = img_time;
             self.state.keyframe_pose = self.state.current_frame_pose;
+            self.state.keyframe_affine = self.state.current_frame_affine;
+            self.state.keyframe_reference_energy = None;
+            if self.state.sliding_window.is_some() {
+                window_result = self.optimize_window();
+            }
+        } else if let Some((mono, intrinsics)) = &self.state.monocular {
+            if status == TrackingStatus::Ok || pose_prior.is_some() {
+                let data = &mut self.state.keyframe_multires_data;
+                let idepth_candidates = monocular::refine_idepth_map(
+                    &data.idepth_candidates,
+                    &data.candidates_points,
+                    &data.img_multires[0],
+                    &img_multires[0],
+                    intrinsics,
+                    &lm_model.motion,
+                    &mono.search,
+                );
+                update_idepth_data(
+                    self.config.nb_levels,
+                    self.config.idepth_fusion,
+                    data,
+                    idepth_candidates,
+                );
+            }
+        } else if status == TrackingStatus::Ok && self.config.refine_keyframe_idepth {
+            if let Some(idepth_multires) = &idepth_multires {
+                refine_idepths(
+                    &mut self.state.keyframe_multires_data,
+                    &lm_model.motion,
+                    idepth_multires,
+                );
+            }
+        }
+
+        TrackingResult {
+            status,
+            levels,
+            optical_flow,
+            keyframe_created: change_keyframe,
+            window: window_result,
         }
     } // track
 
-    /// Retrieve the current frame timestamp (of depth image) and pose.
-    pub fn current_frame(&self) -> (f64, Iso3) {
+    /// Add the new keyframe to the sliding window and optimize it.
+    /// The new keyframe then takes its refined pose and inverse depths.
+    fn optimize_window(&mut self) -> Option<WindowResult> {
+        let state = &mut self.state;
+        let window = state.sliding_window.as_mut()?;
+        let keyframe = window_keyframe(
+            window,
+            &state.keyframe_multires_data,
+            state.keyframe_depth_timestamp,
+            state.keyframe_pose,
+            state.keyframe_affine,
+        );
+        window.add_keyframe(keyframe);
+        let result = window.optimize().ok()?;
+        let refined = window.keyframes().last().unwrap();
+        state.keyframe_pose = refined.pose;
+        state.current_frame_pose = refined.pose;
+        if let Some(last) = state.pose_history.last_mut() {
+            *last = (state.keyframe_depth_timestamp, refined.pose);
+        }
+        let idepths: Vec<Float> = refined.points.iter().map(|p| p.idepth).collect();
+        set_level_idepths(
+            &mut state.keyframe_multires_data,
+            window.config().level,
+            idepths,
+        );
+        Some(result)
+    }
+
+    /// Retrieve the sliding window, with the refined poses of the last keyframes.
+    pub fn sliding_window(&self) -> Option<&SlidingWindow<C>> {
+        self.state.sliding_window.as_ref()
+    }
+
+    /// Add IMU measurements, with increasing timestamps,
+    /// for a tracker configured with `config.inertial`.
+    /// Measurements up to the next frame to track must be added before tracking it.
+    pub fn add_imu_measurements(&mut self, measurements: &[ImuMeasurement]) {
+        self.state
+            .inertial
+            .as_mut()
+            .expect("The tracker was not configured with an inertial sensor")
+            .add_measurements(measurements);
+    }
+
+    /// Retrieve the inertial state, with the initial gravity and biases and the estimated velocity.
+    pub fn inertial(&self) -> Option<&Inertial> {
+        self.state.inertial.as_ref()
+    }
+
+    /// Provide an external prior (e.g. from wheel odometry) for the next frame to track.
+    /// It replaces the motion model prediction for that frame.
+    pub fn set_pose_prior(&mut self, prior: PosePrior) {
+        self.state.pose_prior = Some(prior);
+    }
+
+    /// Retrieve the covariance of the current frame pose, expressed in the world frame
+    /// (`exp(xi) * pose` with `xi ~ N(0, covariance)`).
+    ///
+    /// It only accounts for the uncertainty of the motion relative to the keyframe,
+    /// and is `None` if the last tracking failed.
+    pub fn current_frame_covariance(&self) -> Option<Mat6> {
+        self.state.current_frame_covariance
+    }
+
+    /// Retrieve the current keyframe for the loop closure database,
+    /// with its multi-resolution data from the configured `min_level`.
+    /// Typically called when tracking returns `keyframe_created`.
+    pub fn loop_closure_keyframe(
+        &self,
+        config: &loop_closure::Config,
+    ) -> loop_closure::Keyframe<C> {
+        let data = &self.state.keyframe_multires_data;
+        let levels = (config.min_level..self.config.nb_levels)
+            .map(|lvl| loop_closure::KeyframeLevel {
+                intrinsics: data.intrinsics_multires[lvl].clone(),
+                image: data.img_multires[lvl].clone(),
+                coordinates: data.usable_candidates_multires[lvl].0.clone(),
+                idepths: data.usable_candidates_multires[lvl].1.clone(),
+                jacobians: data.jacobians_multires[lvl].clone(),
+                hessians: data.hessians_multires[lvl].clone(),
+            })
+            .collect();
+        loop_closure::Keyframe {
+            timestamp: self.state.keyframe_depth_timestamp,
+            pose: self.state.keyframe_pose,
+            descriptor: loop_closure::thumbnail(&data.img_multires[0], config.thumbnail_size),
+            levels,
+        }
+    }
+
+    /// Retrieve the current frame timestamp (of depth image), pose,
+    /// and affine brightness relative to the first frame.
+    pub fn current_frame(&self) -> (f64, Iso3, AffineBrightness) {
         (
             self.state.current_frame_depth_timestamp,
             self.state.current_frame_pose,
+            self.state.current_frame_affine,
         )
     }
 } // impl Tracker
 
+impl<C: CameraModel> FrameTracker for Tracker<C> {
+    fn track(
+        &mut self,
+        depth_time: f64,
+        depth_map: &DMatrix<u16>,
+        img_time: f64,
+        img: DMatrix<u8>,
+    ) -> TrackingResult {
+        Tracker::track(self, depth_time, depth_map, img_time, img)
+    }
+
+    fn set_pose_prior(&mut self, prior: PosePrior) {
+        Tracker::set_pose_prior(self, prior)
+    }
+
+    fn current_pose(&self) -> (f64, Iso3) {
+        let (timestamp, pose, _) = self.current_frame();
+        (timestamp, pose)
+    }
+
+    fn current_frame_covariance(&self) -> Option<Mat6> {
+        Tracker::current_frame_covariance(self)
+    }
+}
+
 // Helper ######################################################################
 
 // fn angle(uq: UnitQuaternion<Float>) -> Float {
@@ -255,6 +837,175 @@ impl Tracker {
 //     2.0 * uq.into_inner().vector().norm().atan2(w)
 // }
 
+/// Image corrected with the photometric calibration, if any.
+fn correct_image(calibration: Option<&photometric::Calibration>, img: DMatrix<u8>) -> DMatrix<u8> {
+    match calibration {
+        Some(calibration) => calibration.correct(&img),
+        None => img,
+    }
+}
+
+/// Inverse depth map of a depth image, with the configured scale and noise model.
+fn to_idepth_map<C: CameraModel>(
+    config: &Config<C>,
+    depth_map: &DMatrix<u16>,
+) -> DMatrix<InverseDepth> {
+    depth_noise::inverse_depth_map(
+        config.depth_noise.as_ref(),
+        &config.intrinsics,
+        config.depth_scale,
+        depth_map,
+    )
+}
+
+/// Multi-resolution inverse depth maps of a full inverse depth image.
+fn idepth_pyramid(
+    nb_levels: usize,
+    idepth_fusion: inverse_depth::Strategy,
+    idepth_map: DMatrix<InverseDepth>,
+) -> Levels<DMatrix<InverseDepth>> {
+    let fuse = |a, b, c, d| inverse_depth::fuse(a, b, c, d, idepth_fusion);
+    multires::limited_sequence(nb_levels, idepth_map, |m| multires::halve(m, fuse))
+}
+
+/// Refine the keyframe inverse depths with the inverse depths of a tracked frame,
+/// and update the jacobians and hessians accordingly.
+///
+/// The depth measured at the pixel where a keyframe point is warped
+/// is transferred back into the keyframe along the predicted ray,
+/// and its variance is propagated through this transfer.
+/// The uncertainty of the motion itself is neglected.
+/// Measurements incompatible with the current estimate are ignored.
+#[allow(clippy::used_underscore_binding)]
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+#[allow(clippy::cast_precision_loss)]
+fn refine_idepths<C: CameraModel>(
+    data: &mut MultiresData<C>,
+    motion: &Iso3,
+    idepth_multires: &[DMatrix<InverseDepth>],
+) {
+    let motion_inverse = motion.inverse();
+    for (lvl, idepth_map) in idepth_multires.iter().enumerate() {
+        let intrinsics = &data.intrinsics_multires[lvl];
+        let (nb_rows, nb_cols) = idepth_map.shape();
+        let (coordinates, _z_candidates) = &mut data.usable_candidates_multires[lvl];
+        let variances = &mut data.idepth_variances_multires[lvl];
+        for (idx, &(x, y)) in coordinates.iter().enumerate() {
+            let x1 = match intrinsics.unproject(&Point2::new(x as Float, y as Float)) {
+                Some(ray) => Point3::from(ray / _z_candidates[idx]),
+                None => continue,
+            };
+            let x2 = motion * x1;
+            let (u, v) = match intrinsics.project(&x2) {
+                Some(uv2) => (uv2.x.round(), uv2.y.round()),
+                None => continue,
+            };
+            if u < 0.0 || u >= nb_cols as Float || v < 0.0 || v >= nb_rows as Float {
+                continue;
+            }
+            if let InverseDepth::WithVariance(d2, v2) = idepth_map[(v as usize, u as usize)] {
+                // Measured point along the predicted ray, in the keyframe.
+                let ray = x2.coords / intrinsics.depth(&x2);
+                let x1_measured = motion_inverse * Point3::from(ray / d2);
+                let depth1 = intrinsics.depth(&x1_measured);
+                let d1 = 1.0 / depth1;
+                let d_depth1 = intrinsics
+                    .depth_gradient(&x1_measured)
+                    .dot(&(motion_inverse.rotation * ray));
+                let d_d1_d_d2 = d_depth1 * d1 * d1 / (d2 * d2);
+                let v1 = d_d1_d_d2 * d_d1_d_d2 * v2;
+                let prior = InverseDepth::WithVariance(_z_candidates[idx], variances[idx]);
+                let diff = _z_candidates[idx] - d1;
+                if depth1 > 0.0 && diff * diff <= IDEPTH_FUSION_GATE * (variances[idx] + v1) {
+                    if let InverseDepth::WithVariance(d, var) =
+                        inverse_depth::kalman_update(prior, (d1, v1))
+                    {
+                        _z_candidates[idx] = d;
+                        variances[idx] = var;
+                    }
+                }
+            }
+        }
+        let (gx, gy) = &data.gradients_multires[lvl];
+        let readout = data.readout_multires.as_ref().map(|r| &r[lvl]);
+        data.jacobians_multires[lvl] =
+            warp_jacobians(intrinsics, coordinates, _z_candidates, gx, gy, readout);
+        data.hessians_multires[lvl] = hessians_vec(&data.jacobians_multires[lvl]);
+    }
+}
+
+/// Keyframe of the sliding window, with the candidate points at the window level.
+fn window_keyframe<C: CameraModel>(
+    window: &SlidingWindow<C>,
+    data: &MultiresData<C>,
+    timestamp: f64,
+    pose: Iso3,
+    affine: AffineBrightness,
+) -> sliding_window::Keyframe<C> {
+    let lvl = window.config().level;
+    let (coordinates, _z_candidates) = &data.usable_candidates_multires[lvl];
+    sliding_window::Keyframe::new(
+        timestamp,
+        pose,
+        affine,
+        data.intrinsics_multires[lvl].clone(),
+        data.img_multires[lvl].clone(),
+        coordinates,
+        _z_candidates,
+        &data.idepth_variances_multires[lvl],
+    )
+}
+
+/// Replace the inverse depths of the candidate points at one level,
+/// and update the jacobians and hessians accordingly.
+fn set_level_idepths<C: CameraModel>(data: &mut MultiresData<C>, lvl: usize, idepths: Vec<Float>) {
+    let (coordinates, _) = &data.usable_candidates_multires[lvl];
+    let (gx, gy) = &data.gradients_multires[lvl];
+    data.jacobians_multires[lvl] = warp_jacobians(
+        &data.intrinsics_multires[lvl],
+        coordinates,
+        &idepths,
+        gx,
+        gy,
+        data.readout_multires.as_ref().map(|r| &r[lvl]),
+    );
+    data.hessians_multires[lvl] = hessians_vec(&data.jacobians_multires[lvl]);
+    data.usable_candidates_multires[lvl].1 = idepths;
+}
+
+/// Forget the inverse depths of pixels that the camera model cannot unproject.
+fn mask_unprojectable<C: CameraModel>(
+    intrinsics: &C,
+    mut idepth_mat: DMatrix<InverseDepth>,
+) -> DMatrix<InverseDepth> {
+    for (y, mut row) in idepth_mat.row_iter_mut().enumerate() {
+        for (x, idepth) in row.iter_mut().enumerate() {
+            if let InverseDepth::WithVariance(_, _) = idepth {
+                if intrinsics
+                    .unproject(&Point2::new(x as Float, y as Float))
+                    .is_none()
+                {
+                    *idepth = InverseDepth::Unknown;
+                }
+            }
+        }
+    }
+    idepth_mat
+}
+
+/// Extract the variances of known inverse depth values,
+/// in the same order than `extract_z`.
+fn extract_variances(idepth_mat: &DMatrix<InverseDepth>) -> Vec<Float> {
+    idepth_mat
+        .iter()
+        .filter_map(|idepth| match *idepth {
+            InverseDepth::WithVariance(_, var) => Some(var),
+            _ => None,
+        })
+        .collect()
+}
+
 /// Extract known inverse depth values (and coordinates) into vectorized data.
 #[allow(clippy::used_underscore_binding)]
 fn extract_z(idepth_mat: &DMatrix<InverseDepth>) -> (Vec<(usize, usize)>, Vec<Float>) {
@@ -279,20 +1030,17 @@ fn extract_z(idepth_mat: &DMatrix<InverseDepth>) -> (Vec<(usize, usize)>, Vec<Fl
 }
 
 /// Precompute jacobians for each candidate.
+/// Candidates are expected to be in the valid domain of the camera model.
 #[allow(clippy::used_underscore_binding)]
 #[allow(clippy::cast_precision_loss)]
-fn warp_jacobians(
-    intrinsics: &Intrinsics,
+fn warp_jacobians<C: CameraModel>(
+    intrinsics: &C,
     coordinates: &[(usize, usize)],
     _z_candidates: &[Float],
     grad_x: &DMatrix<i16>,
     grad_y: &DMatrix<i16>,
+    readout: Option<&Readout>,
 ) -> Vec<Vec6> {
-    // Bind intrinsics to shorter names
-    let (cu, cv) = intrinsics.principal_point;
-    let (fu, fv) = intrinsics.focal;
-    let s = intrinsics.skew;
-
     // Iterate on inverse depth candidates
     coordinates
         .iter()
@@ -300,44 +1048,40 @@ fn warp_jacobians(
         .map(|(&(u, v), &_z)| {
             let gu = Float::from(grad_x[(v, u)]);
             let gv = Float::from(grad_y[(v, u)]);
-            warp_jacobian_at(gu, gv, u as Float, v as Float, _z, cu, cv, fu, fv, s)
+            match intrinsics.unproject(&Point2::new(u as Float, v as Float)) {
+                Some(ray) => {
+                    let x = Point3::from(ray / _z);
+                    warp_jacobian_at(intrinsics, gu, gv, v as Float, &x, readout)
+                }
+                None => Vec6::zeros(),
+            }
         })
         .collect()
 }
 
-/// Jacobian of the warping function for the inverse compositional algorithm.
-#[allow(clippy::too_many_arguments)]
-#[allow(clippy::many_single_char_names)]
-#[allow(clippy::used_underscore_binding)]
-#[allow(clippy::similar_names)]
-fn warp_jacobian_at(
+/// Jacobian of the warping function for the inverse compositional algorithm,
+/// at a point `x` of the template, observed at row `v`.
+///
+/// With `g = P^T * (gu, gv)` where `P` is the projection jacobian at `x`,
+/// the jacobian is `[ g, x cross g ]`.
+///
+/// With a rolling shutter, the step `motion * exp(-delta)` is expressed in the frame
+/// of the first row, so the jacobian at row `v` is transformed by the adjoint of its pose.
+fn warp_jacobian_at<C: CameraModel>(
+    intrinsics: &C,
     gu: Float,
     gv: Float,
-    u: Float,
     v: Float,
-    _z: Float,
-    cu: Float,
-    cv: Float,
-    fu: Float,
-    fv: Float,
-    s: Float,
+    x: &Point3,
+    readout: Option<&Readout>,
 ) -> Vec6 {
-    // Intermediate computations
-    let a = u - cu;
-    let b = v - cv;
-    let c = a * fv - s * b;
-    let _fv = 1.0 / fv;
-    let _fuv = 1.0 / (fu * fv);
-
-    // Jacobian of the warp
-    Vec6::new(
-        gu * _z * fu,                                       //
-        _z * (gu * s + gv * fv),                            //  linear velocity terms
-        -_z * (gu * a + gv * b),                            //  ___
-        gu * (-a * b * _fv - s) + gv * (-b * b * _fv - fv), //
-        gu * (a * c * _fuv + fu) + gv * (b * c * _fuv),     //  angular velocity terms
-        gu * (-fu * fu * b + s * c) * _fuv + gv * (c / fu), //
-    )
+    let g = intrinsics.project_jacobian(x).transpose() * Vec2::new(gu, gv);
+    let w = x.coords.cross(&g);
+    let jacobian = Vec6::new(g.x, g.y, g.z, w.x, w.y, w.z);
+    match readout {
+        Some(readout) => readout.row_jacobian(v, jacobian),
+        None => jacobian,
+    }
 }
 
 /// Compute hessians components for each candidate point.
@@ -347,12 +1091,196 @@ fn hessians_vec(jacobians: &Vec<Vec6>) -> Vec<Mat6> {
     jacobians.iter().map(|j| j * j.transpose()).collect()
 }
 
-/// Warp a point from an image to another by a given rigid body motion.
+/// Warp a point from an image to another by a given rigid body motion,
+/// and the rolling shutter readouts of both images if any.
+/// Return `None` if the point cannot be unprojected or projected.
 #[allow(clippy::used_underscore_binding)]
-fn warp(model: &Iso3, x: Float, y: Float, _z: Float, intrinsics: &Intrinsics) -> (Float, Float) {
-    // TODO: maybe move into the camera module?
-    let x1 = intrinsics.back_project(Point2::new(x, y), 1.0 / _z);
-    let x2 = model * x1;
-    let uvz2 = intrinsics.project(x2);
-    (uvz2.x / uvz2.z, uvz2.y / uvz2.z)
+fn warp<C: CameraModel>(
+    model: &Iso3,
+    x: Float,
+    y: Float,
+    _z: Float,
+    intrinsics: &C,
+    rolling_shutter: Option<&RollingShutter>,
+) -> Option<(Float, Float)> {
+    let x1 = Point3::from(intrinsics.unproject(&Point2::new(x, y))? / _z);
+    let motion = rolling_shutter.map_or(*model, |rs| rs.row_motion(intrinsics, model, y, &x1));
+    let uv2 = intrinsics.project(&(motion * x1))?;
+    Some((uv2.x, uv2.y))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::core::track::keyframe::OpticalFlow;
+    use crate::math::robust::Squared;
+    use crate::misc::helper::gen_texture;
+    use crate::misc::type_aliases::Vec3;
+    use approx;
+
+    #[test]
+    fn track_translated_frame() {
+        let (depth_map, img) = gen_frame();
+        let mut tracker = gen_config().init(0.0, &depth_map, 0.0, img.clone());
+        // Camera translated along x by 1 pixel at the depth of the plane.
+        let shifted = shift_columns(&img, 1);
+        let result = tracker.track(1.0, &depth_map, 1.0, shifted);
+        assert_eq!(result.status, TrackingStatus::Ok);
+        assert!(result.levels.iter().all(Option::is_some));
+        assert!(!result.keyframe_created);
+        let (_, pose, _) = tracker.current_frame();
+        let expected = Vec3::new(PLANE_DEPTH / FOCAL, 0.0, 0.0);
+        assert!(approx::relative_eq!(
+            pose.translation.vector,
+            expected,
+            epsilon = 2e-3
+        ));
+        assert!(pose.rotation.angle() < 2e-3);
+        assert!(tracker.current_frame_covariance().is_some());
+    }
+
+    #[test]
+    fn track_failures_keep_pose() {
+        let (depth_map, img) = gen_frame();
+        let lost = PosePrior {
+            pose: Iso3::translation(100.0, 0.0, 0.0),
+            covariance: Some(Mat6::identity()),
+        };
+
+        // No point is warped inside the image, so the energy is not finite.
+        let mut config = gen_config();
+        config.fix_affine_brightness = true;
+        let mut tracker = config.init(0.0, &depth_map, 0.0, img.clone());
+        tracker.set_pose_prior(lost);
+        let result = tracker.track(1.0, &depth_map, 1.0, img.clone());
+        assert_eq!(result.status, TrackingStatus::Diverged);
+        assert!(result.levels[..2].iter().all(Option::is_none));
+        assert!(!result.keyframe_created);
+        assert_eq!(tracker.current_frame().1, Iso3::identity());
+        assert!(tracker.current_frame_covariance().is_none());
+
+        // Without residuals, the affine parameters make the hessian singular.
+        let mut tracker = gen_config().init(0.0, &depth_map, 0.0, img.clone());
+        tracker.set_pose_prior(lost);
+        let result = tracker.track(1.0, &depth_map, 1.0, img);
+        assert_eq!(result.status, TrackingStatus::CholeskyFailure);
+        assert!(result.levels.iter().all(Option::is_none));
+        assert_eq!(tracker.current_frame().1, Iso3::identity());
+    }
+
+    #[test]
+    #[allow(clippy::used_underscore_binding)]
+    fn refine_idepths_with_translated_frame() {
+        // Statistical variances at all levels, for the fusion gate.
+        let mut config = gen_config();
+        config.depth_noise = Box::new(depth_noise::ConstantIdepth(1e-3));
+        config.idepth_fusion = inverse_depth::strategy_inverse_variance;
+        let (depth_map, img) = gen_frame();
+        let idepth_map = to_idepth_map(&config, &depth_map);
+        let intrinsics_multires = config.intrinsics.clone().multi_res(config.nb_levels);
+        let img_multires = multires::mean_pyramid(config.nb_levels, img);
+        let gen_data = || {
+            precompute_multires_data(
+                &config,
+                &idepth_map,
+                intrinsics_multires.clone(),
+                img_multires.clone(),
+                Vec6::zeros(),
+            )
+        };
+        let data = gen_data();
+        // The frame is 0.5 behind the keyframe, so the plane is at depth 2.5.
+        let motion = Iso3::translation(0.0, 0.0, 0.5);
+        let refined = |measured_idepth: Float| {
+            let mut data = gen_data();
+            let measured =
+                DMatrix::repeat(60, 80, InverseDepth::WithVariance(measured_idepth, 1e-3));
+            let measured_multires =
+                idepth_pyramid(config.nb_levels, config.idepth_fusion, measured);
+            refine_idepths(&mut data, &motion, &measured_multires);
+            data
+        };
+
+        // A consistent measurement keeps the inverse depths and shrinks their variance.
+        // The variance transferred to the keyframe is (d1 / d2)^4 times the measured one.
+        let consistent = refined(1.0 / 2.5);
+        let v1 = (2.5_f32 / 2.0).powi(4) * 1e-3;
+        let expected_variance = 1e-3 * v1 / (1e-3 + v1);
+        let (_, _z) = &consistent.usable_candidates_multires[0];
+        assert!(!_z.is_empty());
+        assert!(_z
+            .iter()
+            .all(|&d| approx::relative_eq!(d, 0.5, epsilon = 1e-4)));
+        assert!(consistent.idepth_variances_multires[0]
+            .iter()
+            .all(|&v| approx::relative_eq!(v, expected_variance, epsilon = 1e-6)));
+        for lvl in 1..config.nb_levels {
+            let before = &data.idepth_variances_multires[lvl];
+            let after = &consistent.idepth_variances_multires[lvl];
+            assert!(before.iter().zip(after.iter()).all(|(b, a)| a < b));
+        }
+
+        // An inconsistent measurement is rejected by the gate.
+        let inconsistent = refined(1.0 / 1.5);
+        for lvl in 0..config.nb_levels {
+            assert_eq!(
+                inconsistent.usable_candidates_multires[lvl],
+                data.usable_candidates_multires[lvl]
+            );
+            assert_eq!(
+                inconsistent.idepth_variances_multires[lvl],
+                data.idepth_variances_multires[lvl]
+            );
+        }
+    }
+
+    // GENERATORS ####################################################
+
+    const FOCAL: Float = 60.0;
+    const PLANE_DEPTH: Float = 2.0;
+    const DEPTH_SCALE: Float = 1000.0;
+
+    fn gen_config() -> Config {
+        Config {
+            nb_levels: 3,
+            candidates_diff_threshold: 7,
+            depth_scale: DEPTH_SCALE,
+            intrinsics: Intrinsics {
+                principal_point: (39.5, 29.5),
+                focal: (FOCAL, FOCAL),
+                skew: 0.0,
+            },
+            depth_noise: Box::new(depth_noise::ConstantIdepth(1e-4)),
+            idepth_fusion: inverse_depth::strategy_dso_mean,
+            robust_loss: Box::new(Squared),
+            fix_affine_brightness: false,
+            keyframe_policy: Box::new(OpticalFlow { threshold: 2.0 }),
+            motion_model: MotionModel::Static,
+            lm_configs: vec![LMConfig::default(); 3],
+            depth_residual_weight: None,
+            refine_keyframe_idepth: false,
+            sliding_window: None,
+            inertial: None,
+            readout_time: None,
+            photometric: None,
+            intensity_variance: None,
+        }
+    }
+
+    /// Textured fronto-parallel plane.
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn gen_frame() -> (DMatrix<u16>, DMatrix<u8>) {
+        let depth = (PLANE_DEPTH * DEPTH_SCALE) as u16;
+        (DMatrix::repeat(60, 80, depth), gen_texture(60, 80))
+    }
+
+    /// Image shifted to the left by `shift` columns, repeating the last column.
+    fn shift_columns(img: &DMatrix<u8>, shift: usize) -> DMatrix<u8> {
+        let (nb_rows, nb_cols) = img.shape();
+        DMatrix::from_fn(nb_rows, nb_cols, |y, x| {
+            img[(y, (x + shift).min(nb_cols - 1))]
+        })
+    }
 }
@@ -0,0 +1,218 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Policies deciding when the tracker should change its keyframe.
+
+use crate::misc::type_aliases::{Float, Iso3};
+
+/// Information about the last tracked frame, available to keyframe policies.
+/// Policies are only asked about frames that were successfully tracked.
+pub struct FrameInfo {
+    /// Mean L1 optical flow of the candidate points on the coarsest level.
+    pub optical_flow: Float,
+    /// Rigid body motion from the keyframe to the current frame.
+    pub motion: Iso3,
+    /// Time (in seconds) since the keyframe depth image.
+    pub elapsed_time: f64,
+    /// Ratio of keyframe candidate points warped inside the current image
+    /// on the finest level.
+    pub visible_ratio: Float,
+    /// Final energy of the optimization on the finest level.
+    pub energy: Float,
+    /// Final energy of the first frame tracked with the current keyframe.
+    pub reference_energy: Float,
+}
+
+/// A policy deciding if the frame just tracked should become the new keyframe.
+pub trait KeyframePolicy {
+    /// Return `true` if the keyframe should be changed.
+    fn change_keyframe(&self, info: &FrameInfo) -> bool;
+}
+
+/// Change keyframe when the mean optical flow exceeds a threshold (in pixels).
+pub struct OpticalFlow {
+    /// Threshold of the mean optical flow, on the coarsest level.
+    pub threshold: Float,
+}
+
+impl KeyframePolicy for OpticalFlow {
+    fn change_keyframe(&self, info: &FrameInfo) -> bool {
+        info.optical_flow >= self.threshold
+    }
+}
+
+/// Change keyframe when the camera moved too far away from the keyframe.
+pub struct Distance {
+    /// Max translation distance (in the depth unit, usually meters).
+    pub translation: Float,
+    /// Max rotation angle (in radians).
+    pub rotation: Float,
+}
+
+impl KeyframePolicy for Distance {
+    fn change_keyframe(&self, info: &FrameInfo) -> bool {
+        info.motion.translation.vector.norm() >= self.translation
+            || info.motion.rotation.angle() >= self.rotation
+    }
+}
+
+/// Change keyframe after a given amount of time.
+pub struct ElapsedTime {
+    /// Max duration (in seconds) of a keyframe.
+    pub seconds: f64,
+}
+
+impl KeyframePolicy for ElapsedTime {
+    fn change_keyframe(&self, info: &FrameInfo) -> bool {
+        info.elapsed_time >= self.seconds
+    }
+}
+
+/// Change keyframe when too many candidate points fall outside of the current image.
+/// A non-finite ratio, for example without any candidate point, never changes the keyframe.
+pub struct VisibleRatio {
+    /// Min ratio of visible candidate points, in [0,1].
+    pub min_ratio: Float,
+}
+
+impl KeyframePolicy for VisibleRatio {
+    fn change_keyframe(&self, info: &FrameInfo) -> bool {
+        info.visible_ratio.is_finite() && info.visible_ratio < self.min_ratio
+    }
+}
+
+/// Change keyframe when the energy grows too much compared to
+/// the first frame tracked with the keyframe, as done in DSO.
+/// Without a finite and positive reference energy, the growth is unknown
+/// and the keyframe is not changed.
+pub struct ResidualGrowth {
+    /// Max ratio between the current energy and the reference energy.
+    pub max_ratio: Float,
+}
+
+impl KeyframePolicy for ResidualGrowth {
+    fn change_keyframe(&self, info: &FrameInfo) -> bool {
+        let valid = info.energy.is_finite()
+            && info.reference_energy.is_finite()
+            && info.reference_energy > 0.0;
+        valid && info.energy > self.max_ratio * info.reference_energy
+    }
+}
+
+/// Change keyframe as soon as one of the policies says so.
+pub struct AnyOf(pub Vec<Box<dyn KeyframePolicy>>);
+
+impl KeyframePolicy for AnyOf {
+    fn change_keyframe(&self, info: &FrameInfo) -> bool {
+        self.0.iter().any(|policy| policy.change_keyframe(info))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::misc::type_aliases::Vec3;
+
+    #[test]
+    fn optical_flow_threshold() {
+        let policy = OpticalFlow { threshold: 2.0 };
+        assert!(!policy.change_keyframe(&gen_info()));
+        assert!(policy.change_keyframe(&FrameInfo {
+            optical_flow: 2.0,
+            ..gen_info()
+        }));
+        assert!(!policy.change_keyframe(&FrameInfo {
+            optical_flow: Float::NAN,
+            ..gen_info()
+        }));
+    }
+
+    #[test]
+    fn distance_threshold() {
+        let policy = Distance {
+            translation: 0.5,
+            rotation: 0.2,
+        };
+        let info = |motion| FrameInfo {
+            motion,
+            ..gen_info()
+        };
+        assert!(!policy.change_keyframe(&gen_info()));
+        assert!(!policy.change_keyframe(&info(Iso3::translation(0.49, 0.0, 0.0))));
+        assert!(policy.change_keyframe(&info(Iso3::translation(0.0, 0.5, 0.0))));
+        assert!(!policy.change_keyframe(&info(Iso3::rotation(Vec3::new(0.19, 0.0, 0.0)))));
+        assert!(policy.change_keyframe(&info(Iso3::rotation(Vec3::new(0.0, 0.0, 0.21)))));
+        assert!(!policy.change_keyframe(&info(Iso3::translation(Float::NAN, 0.0, 0.0))));
+    }
+
+    #[test]
+    fn elapsed_time_threshold() {
+        let policy = ElapsedTime { seconds: 1.0 };
+        let info = |elapsed_time| FrameInfo {
+            elapsed_time,
+            ..gen_info()
+        };
+        assert!(!policy.change_keyframe(&info(0.99)));
+        assert!(policy.change_keyframe(&info(1.0)));
+        assert!(!policy.change_keyframe(&info(f64::NAN)));
+    }
+
+    #[test]
+    fn visible_ratio_threshold() {
+        let policy = VisibleRatio { min_ratio: 0.7 };
+        let info = |visible_ratio| FrameInfo {
+            visible_ratio,
+            ..gen_info()
+        };
+        assert!(!policy.change_keyframe(&info(0.7)));
+        assert!(policy.change_keyframe(&info(0.69)));
+        assert!(!policy.change_keyframe(&info(Float::NAN)));
+        assert!(!policy.change_keyframe(&info(Float::NEG_INFINITY)));
+    }
+
+    #[test]
+    fn residual_growth_threshold() {
+        let policy = ResidualGrowth { max_ratio: 2.0 };
+        let info = |energy, reference_energy| FrameInfo {
+            energy,
+            reference_energy,
+            ..gen_info()
+        };
+        assert!(!policy.change_keyframe(&info(20.0, 10.0)));
+        assert!(policy.change_keyframe(&info(20.1, 10.0)));
+        assert!(!policy.change_keyframe(&info(Float::NAN, 10.0)));
+        assert!(!policy.change_keyframe(&info(Float::INFINITY, 10.0)));
+        assert!(!policy.change_keyframe(&info(20.1, 0.0)));
+        assert!(!policy.change_keyframe(&info(20.1, Float::NAN)));
+    }
+
+    #[test]
+    fn any_of() {
+        let policy = AnyOf(vec![
+            Box::new(OpticalFlow { threshold: 2.0 }),
+            Box::new(ElapsedTime { seconds: 1.0 }),
+        ]);
+        assert!(!policy.change_keyframe(&gen_info()));
+        assert!(policy.change_keyframe(&FrameInfo {
+            elapsed_time: 1.5,
+            ..gen_info()
+        }));
+        assert!(!AnyOf(Vec::new()).change_keyframe(&gen_info()));
+    }
+
+    // GENERATORS ####################################################
+
+    /// Frame close to its keyframe, not triggering any policy of the tests.
+    fn gen_info() -> FrameInfo {
+        FrameInfo {
+            optical_flow: 1.0,
+            motion: Iso3::identity(),
+            elapsed_time: 0.5,
+            visible_ratio: 0.9,
+            energy: 10.0,
+            reference_energy: 10.0,
+        }
+    }
+}
@@ -4,22 +4,101 @@
 
 //! Levenberg-Marquardt implementation of the `optimizer::State` trait
 //! for the inverse compositional tracking algorithm.
+//!
+//! The estimated model is composed of the rigid body motion
+//! and of an affine brightness transfer function between the template and the image,
+//! as in DSO, to handle auto-exposure changes.
 
-use nalgebra::{DMatrix, UnitQuaternion};
+use nalgebra::{DMatrix, UnitQuaternion, U6};
 
-use crate::core::camera::Intrinsics;
+use crate::core::camera::{CameraModel, Intrinsics};
+use crate::core::inverse_depth::InverseDepth;
 use crate::math::optimizer::{self, Continue};
-use crate::math::se3;
-use crate::misc::type_aliases::{Float, Iso3, Mat6, Point2, Vec6};
+use crate::math::robust::RobustLoss;
+use crate::math::{se3, so3};
+use crate::misc::helper::interpolate;
+use crate::misc::type_aliases::{Float, Iso3, Mat6, Mat8, Point2, Point3, Vec2, Vec6, Vec8};
 
 /// State of the Levenberg-Marquardt optimizer.
 pub struct LMOptimizerState {
+    /// Parameters of the optimizer.
+    pub config: LMConfig,
     /// Levenberg-Marquardt hessian diagonal coefficient.
     pub lm_coef: Float,
     /// Data resulting of a successful model evaluation.
     pub eval_data: EvalData,
 }
 
+/// Parameters of the Levenberg-Marquardt optimizer and its stopping criteria.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LMConfig {
+    /// Initial Levenberg-Marquardt hessian diagonal coefficient.
+    pub initial_coef: Float,
+    /// Factor applied to the coefficient when a step increases the energy.
+    pub coef_increase_factor: Float,
+    /// Factor applied to the coefficient when a step decreases the energy.
+    pub coef_decrease_factor: Float,
+    /// Maximum number of iterations.
+    pub max_iterations: usize,
+    /// Stop when the energy decreases by less than this value.
+    pub min_energy_decrease: Float,
+    /// Stop when the energy decreases by less than this ratio of the previous energy.
+    pub min_relative_energy_decrease: Float,
+    /// Stop when the norm of the parameters step is lower than this value.
+    pub min_step_norm: Float,
+}
+
+impl Default for LMConfig {
+    /// Default parameters of the tracker, stopping after 21 iterations at most
+    /// as the tracker always did.
+    /// The min energy decrease of 1.0 is totally empiric.
+    fn default() -> Self {
+        Self {
+            initial_coef: 0.1,
+            coef_increase_factor: 10.0,
+            coef_decrease_factor: 0.1,
+            max_iterations: 21,
+            min_energy_decrease: 1.0,
+            min_relative_energy_decrease: 0.0,
+            min_step_norm: 0.0,
+        }
+    }
+}
+
+impl LMConfig {
+    /// Stopping criterion and damping update of the Levenberg-Marquardt iterations,
+    /// shared by all optimizers, after the evaluation of the step of iteration `nb_iter`.
+    ///
+    /// `accepted` is `Some((old_energy, new_energy, step_norm))` if the step
+    /// decreased the energy and was accepted, `None` otherwise.
+    /// Return the coefficient for the next iteration and if iterations should continue.
+    pub fn stop(
+        &self,
+        nb_iter: usize,
+        lm_coef: Float,
+        accepted: Option<(Float, Float, Float)>,
+    ) -> (Float, Continue) {
+        if nb_iter >= self.max_iterations {
+            return (lm_coef, Continue::Stop);
+        }
+        match accepted {
+            None => (self.coef_increase_factor * lm_coef, Continue::Forward),
+            Some((old_energy, new_energy, step_norm)) => {
+                let d_energy = old_energy - new_energy;
+                let continuation = if d_energy > self.min_energy_decrease
+                    && d_energy > self.min_relative_energy_decrease * old_energy
+                    && step_norm > self.min_step_norm
+                {
+                    Continue::Forward
+                } else {
+                    Continue::Stop
+                };
+                (self.coef_decrease_factor * lm_coef, continuation)
+            }
+        }
+    }
+}
+
 /// Either a successfully constructed `EvalData`
 /// or an error containing the energy of a given model.
 ///
@@ -30,19 +109,72 @@ pub type EvalState = Result<EvalData, Float>;
 /// Data resulting of a successful model evaluation.
 pub struct EvalData {
     /// The hessian matrix of the system.
-    pub hessian: Mat6,
+    /// Ordered as the 6 twist parameters followed by the affine brightness `a` and `b`.
+    pub hessian: Mat8,
     /// The gradient of the system.
-    pub gradient: Vec6,
+    pub gradient: Vec8,
     /// Energy associated with the current model.
     pub energy: Float,
-    /// Estimated motion at the current state of iterations.
-    pub model: Iso3,
+    /// Number of residuals used for the energy, i.e. of points warped inside the image.
+    pub nb_residuals: usize,
+    /// Estimated model at the current state of iterations.
+    pub model: Model,
+    /// Scales `(photometric, depth)` of the residuals used for the energy,
+    /// fixed at the initial model of the optimization (see `RobustLoss::scale`).
+    pub scales: (Float, Float),
+}
+
+/// Model estimated by the optimizer.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Model {
+    /// Rigid body motion from the template to the image.
+    pub motion: Iso3,
+    /// Affine brightness transfer from the template to the image.
+    pub affine: AffineBrightness,
+}
+
+/// Affine brightness transfer function `I' = exp(a) * I + b`, as in DSO.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct AffineBrightness {
+    /// Logarithm of the brightness gain.
+    pub a: Float,
+    /// Brightness offset.
+    pub b: Float,
+}
+
+impl AffineBrightness {
+    /// The transfer function leaving intensities unchanged.
+    pub fn identity() -> Self {
+        Self { a: 0.0, b: 0.0 }
+    }
+
+    /// Apply the transfer function to an intensity value.
+    pub fn apply(&self, intensity: Float) -> Float {
+        self.a.exp() * intensity + self.b
+    }
+
+    /// Composition of transfer functions, such that
+    /// `self.compose(&other).apply(x) == self.apply(other.apply(x))`.
+    pub fn compose(&self, other: &Self) -> Self {
+        Self {
+            a: self.a + other.a,
+            b: self.a.exp() * other.b + self.b,
+        }
+    }
+
+    /// Inverse transfer function.
+    pub fn inverse(&self) -> Self {
+        Self {
+            a: -self.a,
+            b: -(-self.a).exp() * self.b,
+        }
+    }
 }
 
 /// Precomputed data available for the optimizer iterations:
-pub struct Obs<'a> {
+pub struct Obs<'a, C: CameraModel = Intrinsics> {
     /// Intrinsic parameters of the camera.
-    pub intrinsics: &'a Intrinsics,
+    pub intrinsics: &'a C,
     /// Reference ("keyframe") image.
     pub template: &'a DMatrix<u8>,
     /// Current image to track.
@@ -55,90 +187,407 @@ pub struct Obs<'a> {
     pub jacobians: &'a Vec<Vec6>,
     /// Hessian matrices precomputed for the points used for the tracking.
     pub hessians: &'a Vec<Mat6>,
+    /// Robust loss used to weight the residuals.
+    pub robust_loss: &'a dyn RobustLoss,
+    /// Keep the affine brightness parameters fixed during the optimization.
+    pub fix_affine_brightness: bool,
+    /// Optional prior on the motion, adding a term to the energy.
+    pub motion_prior: Option<MotionPrior>,
+    /// Parameters of the optimizer.
+    pub lm_config: LMConfig,
+    /// Depth of the current image, to add geometric residuals as in DVO-SLAM.
+    pub depth: Option<DepthObs<'a>>,
+    /// Rolling shutter readouts of the template and image.
+    /// If `None`, all rows of an image are captured at the same time.
+    pub rolling_shutter: Option<RollingShutter>,
+    /// Inverse depth uncertainty of the points, to weight each photometric residual
+    /// by its predicted variance. If `None`, all residuals have the same weight.
+    pub idepth_uncertainty: Option<IdepthUncertainty<'a>>,
 }
 
-/// `(energy, inside_indices, residuals)`.
-type Precomputed = (Float, Vec<usize>, Vec<Float>);
+/// Uncertainty of the inverse depths of the points used for the tracking.
+///
+/// The variance of a photometric residual is predicted as
+/// `intensity_variance + j^2 * idepth_variance`, where `j` is the derivative
+/// of the residual with respect to the inverse depth of the point through the warp,
+/// approximated with the template gradient as the inverse compositional jacobians.
+/// Residuals are weighted by `intensity_variance` over this variance,
+/// such that points with a perfectly known depth keep a weight of 1.
+pub struct IdepthUncertainty<'a> {
+    /// Variance of the inverse depth of the points used for the tracking.
+    pub variances: &'a Vec<Float>,
+    /// Horizontal and vertical gradients of the template.
+    pub gradients: &'a (DMatrix<i16>, DMatrix<i16>),
+    /// Variance of the image noise, in squared intensities.
+    pub intensity_variance: Float,
+}
+
+impl<'a> IdepthUncertainty<'a> {
+    /// Weight of the photometric residual of the point `idx`,
+    /// at the template pixel `(x, y)` and with inverse depth `_z`,
+    /// warped from `x1` in the template to `x2` in the image.
+    #[allow(clippy::too_many_arguments)]
+    #[allow(clippy::used_underscore_binding)]
+    fn weight<C: CameraModel>(
+        &self,
+        intrinsics: &C,
+        motion: &Iso3,
+        idx: usize,
+        (x, y): (usize, usize),
+        _z: Float,
+        x1: &Point3,
+        x2: &Point3,
+        exp_a: Float,
+    ) -> Float {
+        let (gx, gy) = self.gradients;
+        let gradient = Vec2::new(Float::from(gx[(y, x)]), Float::from(gy[(y, x)]));
+        // Derivative of x2 with respect to the inverse depth, since x1 = ray / _z.
+        let d_x2 = motion.rotation * (-x1.coords / _z);
+        let j = exp_a * gradient.dot(&(intrinsics.project_jacobian(x2) * d_x2));
+        self.intensity_variance / (self.intensity_variance + j * j * self.variances[idx])
+    }
+}
+
+/// Depth observations of the current image, used for inverse depth residuals.
+///
+/// The residual is the difference between the measured inverse depth
+/// at the warped pixel and the inverse depth predicted by the warp.
+pub struct DepthObs<'a> {
+    /// Inverse depth map of the current image.
+    pub idepth: &'a DMatrix<InverseDepth>,
+    /// Weight of the squared inverse depth residuals,
+    /// relative to the squared intensity residuals.
+    pub weight: Float,
+}
+
+/// Prior on the motion, adding the energy `e^T * information * e`
+/// where `e = log(motion_prior^-1 * motion)`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MotionPrior {
+    /// Prior motion from the template to the image.
+    pub motion: Iso3,
+    /// Information matrix (inverse of the covariance) of the prior,
+    /// in the tangent space of the template frame.
+    pub information: Mat6,
+}
+
+/// Rolling shutter readout of an image, whose rows are captured sequentially
+/// while the camera moves with a constant twist velocity.
+/// The pose of an image is the pose of its first row.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Readout {
+    /// Time (in seconds) between the capture of two consecutive rows.
+    pub line_delay: Float,
+    /// Twist velocity (per second) of the camera during the readout,
+    /// such that the pose of row `v` is `pose * exp(v * line_delay * velocity)`.
+    pub velocity: Vec6,
+}
+
+impl Readout {
+    /// Pose of a row relative to the first row.
+    pub fn row_pose(&self, row: Float) -> Iso3 {
+        se3::exp((row * self.line_delay) * self.velocity)
+    }
+
+    /// Transform a jacobian with respect to a step `motion * exp(-delta)`
+    /// expressed in the frame of a row, into the frame of the first row.
+    pub fn row_jacobian(&self, row: Float, jacobian: Vec6) -> Vec6 {
+        se3::adjoint(self.row_pose(row).inverse()).transpose() * jacobian
+    }
+}
+
+/// Rolling shutter readouts of the template and of the image.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RollingShutter {
+    /// Readout of the template.
+    pub template: Readout,
+    /// Readout of the image.
+    pub image: Readout,
+}
+
+/// Number of fixed point iterations to find the image row observing a point.
+const ROLLING_SHUTTER_ITERATIONS: usize = 3;
+
+impl RollingShutter {
+    /// Motion from the template row `y` to the image row where the point `x1`
+    /// (in the frame of the template row) is observed, i.e.
+    /// `image.row_pose(v)^-1 * motion * template.row_pose(y)`.
+    /// Since the image row `v` depends on the projection of the point,
+    /// it is found by fixed point iterations.
+    pub fn row_motion<C: CameraModel>(
+        &self,
+        intrinsics: &C,
+        motion: &Iso3,
+        y: Float,
+        x1: &Point3,
+    ) -> Iso3 {
+        let first_row_motion = motion * self.template.row_pose(y);
+        let mut row_motion = first_row_motion;
+        for _ in 0..ROLLING_SHUTTER_ITERATIONS {
+            match intrinsics.project(&(row_motion * x1)) {
+                Some(uv2) => {
+                    row_motion = self.image.row_pose(uv2.y).inverse() * first_row_motion;
+                }
+                None => break,
+            }
+        }
+        row_motion
+    }
+}
+
+/// `(energy, inside_indices, residuals, variance_weights, scales, depth_residuals)`.
+type Precomputed = (
+    Float,
+    Vec<usize>,
+    Vec<Float>,
+    Vec<Float>,
+    Scales,
+    DepthResiduals,
+);
+
+/// `(photometric_scale, depth_scale)` of the residuals, estimated by the robust loss.
+type Scales = (Float, Float);
+
+/// Residuals and jacobians of inverse depth residuals,
+/// already multiplied by the square root of their weight.
+type DepthResiduals = Vec<(Float, Vec6)>;
 
 impl LMOptimizerState {
     /// Precompute the energy of a model.
-    /// Also return the residuals vector and the indices of candidate points used.
+    /// Also return the residuals vector, the indices of candidate points used,
+    /// the weights of the residuals predicted by the inverse depth uncertainty,
+    /// the residuals scales, and the inverse depth residuals if depth observations are available.
+    ///
+    /// If `scales` is `None`, they are estimated by the robust loss with the residuals
+    /// of this model, otherwise the given scales are used.
     #[allow(clippy::cast_precision_loss)]
     #[allow(clippy::used_underscore_binding)]
-    fn eval_energy(obs: &Obs, model: &Iso3) -> Precomputed {
+    fn eval_energy<C: CameraModel>(
+        obs: &Obs<C>,
+        model: &Model,
+        scales: Option<Scales>,
+    ) -> Precomputed {
         let mut inside_indices = Vec::new();
         let mut residuals = Vec::new();
-        let mut energy_sum = 0.0;
+        let mut variance_weights = Vec::new();
+        let mut depth_residuals = Vec::new();
+        let exp_a = model.affine.a.exp();
         for (idx, &(x, y)) in obs.coordinates.iter().enumerate() {
             let _z = obs._z_candidates[idx];
             // check if warp(x,y) is inside the image
-            let (u, v) = warp(model, x as Float, y as Float, _z, obs.intrinsics);
+            let x1 = match obs
+                .intrinsics
+                .unproject(&Point2::new(x as Float, y as Float))
+            {
+                Some(ray) => Point3::from(ray / _z),
+                None => continue,
+            };
+            let motion = obs.rolling_shutter.map_or(model.motion, |rs| {
+                rs.row_motion(obs.intrinsics, &model.motion, y as Float, &x1)
+            });
+            let x2 = motion * x1;
+            let (u, v) = match obs.intrinsics.project(&x2) {
+                Some(uv2) => (uv2.x, uv2.y),
+                None => continue,
+            };
             if let Some(im) = interpolate(u, v, obs.image) {
                 // precompute residuals and energy
                 let tmp = obs.template[(y, x)];
-                let r = im - Float::from(tmp);
-                energy_sum += r * r;
-                residuals.push(r);
+                residuals.push(im - model.affine.apply(Float::from(tmp)));
                 inside_indices.push(idx); // keep only inside points
+                variance_weights.push(obs.idepth_uncertainty.as_ref().map_or(1.0, |u| {
+                    u.weight(obs.intrinsics, &motion, idx, (x, y), _z, &x1, &x2, exp_a)
+                }));
+                if let Some(depth) = &obs.depth {
+                    if let Some(idepth) = interpolate_idepth(u, v, depth.idepth) {
+                        let (r, mut jac) =
+                            depth_residual(obs.intrinsics, &motion, &x1, &x2, idepth);
+                        if let Some(rs) = &obs.rolling_shutter {
+                            jac = rs.template.row_jacobian(y as Float, jac);
+                        }
+                        let sqrt_weight = depth.weight.sqrt();
+                        depth_residuals.push((sqrt_weight * r, sqrt_weight * jac));
+                    }
+                }
             }
         }
-        let energy = energy_sum / residuals.len() as Float;
-        (energy, inside_indices, residuals)
+        let weighted: Vec<Float> = residuals
+            .iter()
+            .zip(variance_weights.iter())
+            .map(|(r, w)| w.sqrt() * r)
+            .collect();
+        let depth_r: Vec<Float> = depth_residuals.iter().map(|(r, _)| *r).collect();
+        let (scale, depth_scale) = scales.unwrap_or_else(|| {
+            (
+                obs.robust_loss.scale(&weighted),
+                obs.robust_loss.scale(&depth_r),
+            )
+        });
+        let mut energy_sum: Float = weighted
+            .iter()
+            .map(|&r| obs.robust_loss.rho(r, scale))
+            .sum();
+        energy_sum += depth_r
+            .iter()
+            .map(|&r| obs.robust_loss.rho(r, depth_scale))
+            .sum::<Float>();
+        if let Some(prior) = &obs.motion_prior {
+            let e = se3::log(prior.motion.inverse() * model.motion);
+            energy_sum += e.dot(&(prior.information * e));
+        }
+        let energy = energy_sum / (residuals.len() + depth_r.len()) as Float;
+        (
+            energy,
+            inside_indices,
+            residuals,
+            variance_weights,
+            (scale, depth_scale),
+            depth_residuals,
+        )
     }
 
     /// Fully evaluate a model.
-    fn compute_eval_data(obs: &Obs, model: Iso3, pre: Precomputed) -> EvalData {
-        let (energy, inside_indices, residuals) = pre;
-        let mut gradient = Vec6::zeros();
-        let mut hessian = Mat6::zeros();
+    /// Each residual is weighted by the robust loss (IRLS) in both the gradient and the hessian,
+    /// and by its variance weight.
+    ///
+    /// The jacobian of a residual is `[ exp(a) * jac, exp(a) * template, 1 ]`
+    /// so the hessian blocks are accumulated separately,
+    /// to reuse the precomputed hessians of the motion part.
+    #[allow(clippy::similar_names)]
+    fn compute_eval_data<C: CameraModel>(obs: &Obs<C>, model: Model, pre: Precomputed) -> EvalData {
+        let (energy, inside_indices, residuals, variance_weights, scales, depth_residuals) = pre;
+        let (scale, depth_scale) = scales;
+        let nb_residuals = residuals.len();
+        let mut h_motion = Mat6::zeros();
+        let mut h_motion_a = Vec6::zeros();
+        let mut h_motion_b = Vec6::zeros();
+        let (mut h_aa, mut h_ab, mut h_bb) = (0.0, 0.0, 0.0);
+        let mut g_motion = Vec6::zeros();
+        let (mut g_a, mut g_b) = (0.0, 0.0);
         for (i, idx) in inside_indices.into_iter().enumerate() {
             let jac = obs.jacobians[idx];
             let hes = obs.hessians[idx];
+            let (x, y) = obs.coordinates[idx];
+            let tmp = Float::from(obs.template[(y, x)]);
             let r = residuals[i];
-            gradient += jac * r;
-            hessian += hes;
+            let w_var = variance_weights[i];
+            let w = w_var * obs.robust_loss.weight(w_var.sqrt() * r, scale);
+            h_motion += w * hes;
+            h_motion_a += (w * tmp) * jac;
+            h_motion_b += w * jac;
+            h_aa += w * tmp * tmp;
+            h_ab += w * tmp;
+            h_bb += w;
+            g_motion += (w * r) * jac;
+            g_a += w * r * tmp;
+            g_b += w * r;
+        }
+
+        // Assemble the full system.
+        let exp_a = model.affine.a.exp();
+        let exp_2a = exp_a * exp_a;
+        let mut hessian = Mat8::zeros();
+        let mut gradient = Vec8::zeros();
+        hessian
+            .fixed_slice_mut::<U6, U6>(0, 0)
+            .copy_from(&(exp_2a * h_motion));
+        gradient
+            .fixed_rows_mut::<U6>(0)
+            .copy_from(&(exp_a * g_motion));
+        if obs.fix_affine_brightness {
+            // Identity block with a null gradient results in a null affine step.
+            hessian[(6, 6)] = 1.0;
+            hessian[(7, 7)] = 1.0;
+        } else {
+            for k in 0..6 {
+                hessian[(k, 6)] = exp_2a * h_motion_a[k];
+                hessian[(6, k)] = hessian[(k, 6)];
+                hessian[(k, 7)] = exp_a * h_motion_b[k];
+                hessian[(7, k)] = hessian[(k, 7)];
+            }
+            hessian[(6, 6)] = exp_2a * h_aa;
+            hessian[(6, 7)] = exp_a * h_ab;
+            hessian[(7, 6)] = hessian[(6, 7)];
+            hessian[(7, 7)] = h_bb;
+            gradient[6] = exp_a * g_a;
+            gradient[7] = g_b;
+        }
+
+        // Add the inverse depth residuals, only depending on the motion.
+        let mut h_depth = Mat6::zeros();
+        let mut g_depth = Vec6::zeros();
+        for (r, jac) in depth_residuals {
+            let w = obs.robust_loss.weight(r, depth_scale);
+            h_depth += (w * jac) * jac.transpose();
+            g_depth += (w * r) * jac;
+        }
+        let mut h_block = hessian.fixed_slice_mut::<U6, U6>(0, 0);
+        h_block += h_depth;
+        let mut g_block = gradient.fixed_rows_mut::<U6>(0);
+        g_block += g_depth;
+
+        // Add the prior term, whose jacobian is -identity since
+        // the step is applied as `motion * exp(-delta)`.
+        if let Some(prior) = &obs.motion_prior {
+            let e = se3::log(prior.motion.inverse() * model.motion);
+            let mut h_block = hessian.fixed_slice_mut::<U6, U6>(0, 0);
+            h_block += prior.information;
+            let mut g_block = gradient.fixed_rows_mut::<U6>(0);
+            g_block += prior.information * e;
         }
         EvalData {
             hessian,
             gradient,
             energy,
+            nb_residuals,
             model,
+            scales,
         }
     }
 }
 
-/// `impl<'a> optimizer::State<Obs<'a>, EvalState, Iso3, String> for LMOptimizerState`.
-impl<'a> optimizer::State<Obs<'a>, EvalState, Iso3, String> for LMOptimizerState {
+/// `impl<'a, C> optimizer::State<Obs<'a, C>, EvalState, Model, String> for LMOptimizerState`.
+impl<'a, C: CameraModel> optimizer::State<Obs<'a, C>, EvalState, Model, String>
+    for LMOptimizerState
+{
     /// Initialize the optimizer state.
-    fn init(obs: &Obs, model: Iso3) -> Self {
+    fn init(obs: &Obs<C>, model: Model) -> Self {
         Self {
-            lm_coef: 0.1,
-            eval_data: Self::compute_eval_data(obs, model, Self::eval_energy(obs, &model)),
+            config: obs.lm_config,
+            lm_coef: obs.lm_config.initial_coef,
+            eval_data: Self::compute_eval_data(obs, model, Self::eval_energy(obs, &model, None)),
         }
     }
 
     /// Compute the step using Levenberg-Marquardt.
     /// Apply the step in an inverse compositional approach to compute the next motion estimation.
+    /// The affine brightness parameters are simply updated additively.
     /// May return an error at the Cholesky decomposition of the hessian.
-    fn step(&self) -> Result<Iso3, String> {
+    fn step(&self) -> Result<Model, String> {
         let mut hessian = self.eval_data.hessian;
-        hessian.m11 *= 1.0 + self.lm_coef;
-        hessian.m22 *= 1.0 + self.lm_coef;
-        hessian.m33 *= 1.0 + self.lm_coef;
-        hessian.m44 *= 1.0 + self.lm_coef;
-        hessian.m55 *= 1.0 + self.lm_coef;
-        hessian.m66 *= 1.0 + self.lm_coef;
+        for k in 0..8 {
+            hessian[(k, k)] *= 1.0 + self.lm_coef;
+        }
         let cholesky = hessian
             .cholesky()
             .ok_or("Error at Cholesky decomposition of hessian")?;
-        let delta_warp = se3::exp(cholesky.solve(&self.eval_data.gradient));
-        Ok(renormalize(self.eval_data.model * delta_warp.inverse()))
+        let delta = cholesky.solve(&self.eval_data.gradient);
+        let delta_warp = se3::exp(delta.fixed_rows::<U6>(0).into_owned());
+        let model = self.eval_data.model;
+        Ok(Model {
+            motion: renormalize(model.motion * delta_warp.inverse()),
+            affine: AffineBrightness {
+                a: model.affine.a + delta[6],
+                b: model.affine.b + delta[7],
+            },
+        })
     }
 
     /// Compute residuals and energy of the new model.
     /// Then, evaluate the new hessian and gradient if the energy has decreased.
-    fn eval(&self, obs: &Obs, model: Iso3) -> EvalState {
-        let pre = Self::eval_energy(obs, &model);
+    /// Residuals are evaluated with the scales of the current model.
+    fn eval(&self, obs: &Obs<C>, model: Model) -> EvalState {
+        let pre = Self::eval_energy(obs, &model, Some(self.eval_data.scales));
         let energy = pre.0;
         let old_energy = self.eval_data.energy;
         if energy > old_energy {
@@ -149,42 +598,25 @@ impl<'a> optimizer::State<Obs<'a>, EvalState, Iso3, String> for LMOptimizerState
     }
 
     /// Stop after too many iterations,
-    /// or if the energy variation is too low.
+    /// or if the energy variation or the step is too low.
     ///
     /// Also update the Levenberg-Marquardt coefficient
     /// depending on if the energy increased or decreased.
     fn stop_criterion(self, nb_iter: usize, eval_state: EvalState) -> (Self, Continue) {
-        let too_many_iterations = nb_iter > 20;
-        match (eval_state, too_many_iterations) {
-            // Max number of iterations reached:
-            (Err(_), true) => (self, Continue::Stop),
-            (Ok(eval_data), true) => {
-                // eprintln!("Energy: {}", eval_data.energy);
-                let kept_state = Self {
-                    lm_coef: self.lm_coef, // does not matter actually
-                    eval_data,
-                };
-                (kept_state, Continue::Stop)
-            }
-            // Can continue to iterate:
-            (Err(_energy), false) => {
-                // eprintln!("\t back from: {}", energy);
-                let mut kept_state = self;
-                kept_state.lm_coef *= 10.0;
-                (kept_state, Continue::Forward)
+        match eval_state {
+            Err(_) => {
+                let (lm_coef, continuation) = self.config.stop(nb_iter, self.lm_coef, None);
+                (Self { lm_coef, ..self }, continuation)
             }
-            (Ok(eval_data), false) => {
-                let d_energy = self.eval_data.energy - eval_data.energy;
-                // 1.0 is totally empiric here
-                let continuation = if d_energy > 1.0 {
-                    Continue::Forward
-                } else {
-                    Continue::Stop
-                };
-                // eprintln!("Energy: {}", eval_data.energy);
+            Ok(eval_data) => {
+                let old_energy = self.eval_data.energy;
+                let step_norm = step_norm(&self.eval_data.model, &eval_data.model);
+                let accepted = Some((old_energy, eval_data.energy, step_norm));
+                let (lm_coef, continuation) = self.config.stop(nb_iter, self.lm_coef, accepted);
                 let kept_state = Self {
-                    lm_coef: 0.1 * self.lm_coef,
+                    lm_coef,
                     eval_data,
+                    ..self
                 };
                 (kept_state, continuation)
             }
@@ -194,8 +626,16 @@ impl<'a> optimizer::State<Obs<'a>, EvalState, Iso3, String> for LMOptimizerState
 
 // Helper ######################################################################
 
+/// Norm of the parameters step between two models.
+fn step_norm(old_model: &Model, new_model: &Model) -> Float {
+    let delta_motion = se3::log(old_model.motion.inverse() * new_model.motion);
+    let delta_a = new_model.affine.a - old_model.affine.a;
+    let delta_b = new_model.affine.b - old_model.affine.b;
+    (delta_motion.norm_squared() + delta_a * delta_a + delta_b * delta_b).sqrt()
+}
+
 /// First order Taylor approximation for renormalization of rotation part of motion.
-fn renormalize(motion: Iso3) -> Iso3 {
+pub fn renormalize(motion: Iso3) -> Iso3 {
     let mut motion = motion;
     motion.rotation = renormalize_unit_quaternion(motion.rotation);
     motion
@@ -208,44 +648,455 @@ fn renormalize_unit_quaternion(uq: UnitQuaternion<Float>) -> UnitQuaternion<Floa
     UnitQuaternion::new_unchecked(0.5 * (3.0 - sq_norm) * q)
 }
 
-/// Warp a point from an image to another by a given rigid body motion.
-#[allow(clippy::used_underscore_binding)]
-fn warp(model: &Iso3, x: Float, y: Float, _z: Float, intrinsics: &Intrinsics) -> (Float, Float) {
-    // TODO: maybe move into the camera module?
-    let x1 = intrinsics.back_project(Point2::new(x, y), 1.0 / _z);
-    let x2 = model * x1;
-    let uvz2 = intrinsics.project(x2);
-    (uvz2.x / uvz2.z, uvz2.y / uvz2.z)
+/// Inverse depth residual of a point, and its jacobian.
+///
+/// The residual is `measured - 1 / depth(x2)` with `x2 = motion * x1`.
+/// To match the convention of the inverse compositional photometric jacobians,
+/// the returned jacobian is the opposite of the derivative of the residual
+/// with respect to `delta` in `motion * exp(-delta)`.
+fn depth_residual<C: CameraModel>(
+    intrinsics: &C,
+    motion: &Iso3,
+    x1: &Point3,
+    x2: &Point3,
+    (measured, grad_u, grad_v): (Float, Float, Float),
+) -> (Float, Vec6) {
+    let _z = 1.0 / intrinsics.depth(x2);
+    // Derivative of the residual with respect to x2.
+    let d_uv = intrinsics.project_jacobian(x2).transpose() * Vec2::new(grad_u, grad_v);
+    let d_r = d_uv + intrinsics.depth_gradient(x2) * (_z * _z);
+    // Derivative of x2 with respect to delta is [ -R, R * hat(x1) ],
+    // so the opposite derivative of the residual is [ g, x1 cross g ],
+    // the same form as the photometric jacobians.
+    let g = motion.rotation.inverse() * d_r;
+    let g_w = so3::hat(x1.coords) * g;
+    (measured - _z, Vec6::new(g.x, g.y, g.z, g_w.x, g_w.y, g_w.z))
 }
 
-/// Simple linear interpolation of a pixel with floating point coordinates.
-/// Return `None` if the point is outside of the image boundaries.
+/// Linear interpolation of an inverse depth map with floating point coordinates.
+/// Return the inverse depth and its horizontal and vertical gradients.
+/// Return `None` if the point is outside of the image boundaries
+/// or if one of the four neighbours has an unknown inverse depth.
 #[allow(clippy::many_single_char_names)]
 #[allow(clippy::cast_possible_truncation)]
 #[allow(clippy::cast_sign_loss)]
 #[allow(clippy::cast_precision_loss)]
-fn interpolate(x: Float, y: Float, image: &DMatrix<u8>) -> Option<Float> {
-    let (height, width) = image.shape();
+fn interpolate_idepth(
+    x: Float,
+    y: Float,
+    idepth: &DMatrix<InverseDepth>,
+) -> Option<(Float, Float, Float)> {
+    let (height, width) = idepth.shape();
     let u = x.floor();
     let v = y.floor();
     if u >= 0.0 && u < (width - 2) as Float && v >= 0.0 && v < (height - 2) as Float {
         let u_0 = u as usize;
         let v_0 = v as usize;
-        let u_1 = u_0 + 1;
-        let v_1 = v_0 + 1;
-        let vu_00 = Float::from(image[(v_0, u_0)]);
-        let vu_10 = Float::from(image[(v_1, u_0)]);
-        let vu_01 = Float::from(image[(v_0, u_1)]);
-        let vu_11 = Float::from(image[(v_1, u_1)]);
+        let known = |v_i, u_i| match idepth[(v_i, u_i)] {
+            InverseDepth::WithVariance(d, _) => Some(d),
+            _ => None,
+        };
+        let vu_00 = known(v_0, u_0)?;
+        let vu_10 = known(v_0 + 1, u_0)?;
+        let vu_01 = known(v_0, u_0 + 1)?;
+        let vu_11 = known(v_0 + 1, u_0 + 1)?;
         let a = x - u;
         let b = y - v;
-        Some(
-            (1.0 - b) * (1.0 - a) * vu_00
-                + b * (1.0 - a) * vu_10
-                + (1.0 - b) * a * vu_01
-                + b * a * vu_11,
-        )
+        let value = (1.0 - b) * (1.0 - a) * vu_00
+            + b * (1.0 - a) * vu_10
+            + (1.0 - b) * a * vu_01
+            + b * a * vu_11;
+        let grad_u = (1.0 - b) * (vu_01 - vu_00) + b * (vu_11 - vu_10);
+        let grad_v = (1.0 - a) * (vu_10 - vu_00) + a * (vu_11 - vu_01);
+        Some((value, grad_u, grad_v))
     } else {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::math::optimizer::State;
+    use crate::math::robust::Squared;
+    use crate::misc::helper::bounded;
+    use crate::misc::type_aliases::Vec3;
+    use approx;
+    use nalgebra::{Matrix2, Matrix6x2, Vector2, U2};
+    use quickcheck_macros;
+
+    // Precision of centered finite differences with f32 computations.
+    const EPSILON_FINITE_DIFF: Float = 1e-2;
+
+    #[test]
+    fn stop_criterion() {
+        let config = LMConfig {
+            max_iterations: 10,
+            min_energy_decrease: 1.0,
+            min_relative_energy_decrease: 0.01,
+            min_step_norm: 1e-3,
+            ..LMConfig::default()
+        };
+        let forward = |c| matches!(c, Continue::Forward);
+        let stop = |nb_iter, accepted| config.stop(nb_iter, 1.0, accepted);
+        let continues = |nb_iter, accepted| forward(stop(nb_iter, accepted).1);
+
+        // Rejected steps increase the damping and continue.
+        let (coef, c) = stop(1, None);
+        assert!(approx::relative_eq!(coef, config.coef_increase_factor) && forward(c));
+
+        // Accepted steps decrease the damping, and continue if the energy decreases enough.
+        let (coef, c) = stop(1, Some((50.0, 40.0, 0.1)));
+        assert!(approx::relative_eq!(coef, config.coef_decrease_factor) && forward(c));
+        assert!(!continues(1, Some((50.0, 49.0, 0.1))));
+        assert!(continues(1, Some((50.0, 48.9, 0.1))));
+
+        // Relative energy decrease.
+        assert!(!continues(1, Some((1000.0, 995.0, 0.1))));
+        assert!(continues(1, Some((1000.0, 989.0, 0.1))));
+
+        // Step norm.
+        assert!(!continues(1, Some((50.0, 40.0, 1e-3))));
+
+        // Max iterations, whatever the step.
+        assert!(!continues(10, None));
+        assert!(!continues(10, Some((50.0, 40.0, 0.1))));
+        assert!(continues(9, Some((50.0, 40.0, 0.1))));
+    }
+
+    #[test]
+    fn fixed_affine_brightness() {
+        let affine = AffineBrightness { a: 0.2, b: -10.0 };
+        let (template, image) = gen_images(affine);
+        let data = gen_points();
+        let mut obs = gen_obs(&template, &image, &data);
+        obs.fix_affine_brightness = true;
+        let model = Model {
+            motion: Iso3::identity(),
+            affine: AffineBrightness::identity(),
+        };
+        let state = LMOptimizerState::init(&obs, model);
+        let hessian = state.eval_data.hessian;
+        assert_eq!(
+            hessian.fixed_slice::<U2, U2>(6, 6).into_owned(),
+            Matrix2::identity()
+        );
+        assert_eq!(
+            hessian.fixed_slice::<U6, U2>(0, 6).into_owned(),
+            Matrix6x2::zeros()
+        );
+        assert_eq!(
+            state.eval_data.gradient.fixed_rows::<U2>(6).into_owned(),
+            Vector2::zeros()
+        );
+        let stepped = step(&state);
+        assert_eq!(stepped.affine, AffineBrightness::identity());
+
+        // Without fixing them, the affine parameters are also updated.
+        obs.fix_affine_brightness = false;
+        let state = LMOptimizerState::init(&obs, model);
+        assert_ne!(step(&state).affine, AffineBrightness::identity());
+    }
+
+    #[test]
+    fn rolling_shutter_global_shutter_limits() {
+        let intrinsics = gen_intrinsics();
+        let motion = gen_motion(0.3, -0.5, 0.2, 0.7);
+        let x1 = Point3::new(0.2, -0.1, 2.0);
+        let readout = gen_readout(0.4, -0.6);
+        let still = Readout {
+            velocity: Vec6::zeros(),
+            ..readout
+        };
+        let global = Readout {
+            line_delay: 0.0,
+            ..readout
+        };
+        for &(template, image) in [(still, still), (global, global), (still, global)].iter() {
+            let rolling_shutter = RollingShutter { template, image };
+            for &y in [0.0, 100.0, 479.0].iter() {
+                let row_motion = rolling_shutter.row_motion(&intrinsics, &motion, y, &x1);
+                assert!(approx::relative_eq!(row_motion, motion, epsilon = 1e-5));
+                let jacobian = Vec6::new(1.0, -2.0, 3.0, -4.0, 5.0, -6.0);
+                assert_eq!(template.row_jacobian(y, jacobian), jacobian);
+            }
+        }
+    }
+
+    // PROPERTY TESTS ################################################
+
+    #[quickcheck_macros::quickcheck]
+    fn affine_compose_inverse(a: Float, b: Float, x: Float) -> bool {
+        let affine = AffineBrightness {
+            a: bounded(a),
+            b: 50.0 * bounded(b),
+        };
+        let other = AffineBrightness { a: -0.3, b: 12.0 };
+        let x = 255.0 * bounded(x).abs();
+        let identity = affine.compose(&affine.inverse());
+        approx::relative_eq!(identity.a, 0.0)
+            && approx::relative_eq!(identity.b, 0.0, epsilon = EPSILON_FINITE_DIFF)
+            && approx::relative_eq!(
+                affine.compose(&other).apply(x),
+                affine.apply(other.apply(x)),
+                epsilon = EPSILON_FINITE_DIFF,
+                max_relative = 1e-5
+            )
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn affine_jacobian(a: Float, b: Float) -> bool {
+        let (template, image) = gen_images(AffineBrightness { a: 0.2, b: -10.0 });
+        let data = gen_points();
+        let obs = gen_obs(&template, &image, &data);
+        let model = |a, b| Model {
+            motion: Iso3::identity(),
+            affine: AffineBrightness { a, b },
+        };
+        let (a, b) = (0.3 * bounded(a), 20.0 * bounded(b));
+        let pre = LMOptimizerState::eval_energy(&obs, &model(a, b), None);
+        let eval_data = LMOptimizerState::compute_eval_data(&obs, model(a, b), pre);
+        // With the squared loss, the energy is the mean of the n squared residuals,
+        // so the gradient is its derivative times -n/2.
+        let half_n = 0.5 * eval_data.nb_residuals as Float;
+        let energy = |a, b| LMOptimizerState::eval_energy(&obs, &model(a, b), None).0;
+        let d_a = (energy(a + 1e-3, b) - energy(a - 1e-3, b)) / 2e-3;
+        let d_b = (energy(a, b + 1e-2) - energy(a, b - 1e-2)) / 2e-2;
+        // Precision relative to the magnitude of the energy and residuals, in f32.
+        let e = energy(a, b);
+        approx::relative_eq!(
+            eval_data.gradient[6],
+            -half_n * d_a,
+            epsilon = 1e-3 * half_n * e,
+            max_relative = 1e-2
+        ) && approx::relative_eq!(
+            eval_data.gradient[7],
+            -half_n * d_b,
+            epsilon = 1e-3 * half_n * e.sqrt(),
+            max_relative = 1e-2
+        )
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn depth_residual_jacobian(
+        x: Float,
+        y: Float,
+        z: Float,
+        t1: Float,
+        t2: Float,
+        a1: Float,
+        a2: Float,
+    ) -> bool {
+        let intrinsics = gen_intrinsics();
+        let x1 = Point3::new(bounded(x), bounded(y), 2.0 + bounded(z));
+        let motion = gen_motion(t1, t2, a1, a2);
+        // Linear inverse depth map of the current frame.
+        let (grad_u, grad_v) = (1e-3, -2e-3);
+        let residual = |motion: &Iso3| {
+            let x2 = motion * x1;
+            let uv = CameraModel::project(&intrinsics, &x2).unwrap();
+            let measured = 0.5 + grad_u * uv.x + grad_v * uv.y;
+            depth_residual(&intrinsics, motion, &x1, &x2, (measured, grad_u, grad_v))
+        };
+        let (_, jacobian) = residual(&motion);
+        let numeric = finite_differences(|delta| residual(&(motion * se3::exp(-delta))).0);
+        approx::relative_eq!(
+            -jacobian,
+            numeric,
+            epsilon = EPSILON_FINITE_DIFF * numeric.amax().max(1e-2)
+        )
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn row_jacobian_finite_differences(row: Float, w1: Float, w2: Float) -> bool {
+        let intrinsics = gen_intrinsics();
+        let motion = gen_motion(0.3, -0.5, 0.2, 0.7);
+        let x1 = Point3::new(0.2, -0.1, 2.0);
+        let row = 240.0 + 240.0 * bounded(row);
+        // Large readout motion, to be far from the identity adjoint.
+        let readout = Readout {
+            line_delay: 1e-3,
+            ..gen_readout(w1, w2)
+        };
+        let row_pose = readout.row_pose(row);
+        let f = |m: Iso3| {
+            let uv = CameraModel::project(&intrinsics, &(m * x1)).unwrap();
+            uv.x - 0.5 * uv.y
+        };
+        let row_numeric = finite_differences(|delta| f(motion * row_pose * se3::exp(-delta)));
+        let numeric = finite_differences(|delta| f(motion * se3::exp(-delta) * row_pose));
+        approx::relative_eq!(
+            readout.row_jacobian(row, row_numeric),
+            numeric,
+            epsilon = EPSILON_FINITE_DIFF * numeric.amax()
+        )
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn row_motion_fixed_point(x: Float, y: Float, w1: Float, w2: Float) -> bool {
+        let intrinsics = gen_intrinsics();
+        let motion = gen_motion(0.3, -0.5, 0.2, 0.7);
+        let rolling_shutter = RollingShutter {
+            template: gen_readout(w2, w1),
+            image: gen_readout(w1, w2),
+        };
+        let template_row = 240.0 + 200.0 * bounded(y);
+        let x1 = Point3::new(0.5 * bounded(x), 0.2, 2.0);
+        let row_motion = rolling_shutter.row_motion(&intrinsics, &motion, template_row, &x1);
+        // The point is observed in the image row whose pose defines the row motion.
+        let uv2 = CameraModel::project(&intrinsics, &(row_motion * x1)).unwrap();
+        let first_row_motion = motion * rolling_shutter.template.row_pose(template_row);
+        let expected = rolling_shutter.image.row_pose(uv2.y).inverse() * first_row_motion;
+        approx::relative_eq!(row_motion, expected, epsilon = 1e-4)
+    }
+
+    #[quickcheck_macros::quickcheck]
+    #[allow(clippy::used_underscore_binding)]
+    fn idepth_uncertainty_derivative(
+        x: Float,
+        y: Float,
+        z: Float,
+        t1: Float,
+        t2: Float,
+        a1: Float,
+        a2: Float,
+    ) -> bool {
+        let intrinsics = gen_intrinsics();
+        let x1 = Point3::new(bounded(x), bounded(y), 2.0 + bounded(z));
+        let motion = gen_motion(t1, t2, a1, a2);
+        let gradients = (
+            DMatrix::from_element(1, 1, 40),
+            DMatrix::from_element(1, 1, -25),
+        );
+        let variances = vec![1e-3];
+        let uncertainty = IdepthUncertainty {
+            variances: &variances,
+            gradients: &gradients,
+            intensity_variance: 4.0,
+        };
+        let exp_a = 1.1;
+        let _z = 1.0 / x1.z;
+        let weight = uncertainty.weight(
+            &intrinsics,
+            &motion,
+            0,
+            (0, 0),
+            _z,
+            &x1,
+            &(motion * x1),
+            exp_a,
+        );
+
+        // Derivative of the warped intensity with respect to the inverse depth.
+        let ray = x1.coords * _z;
+        let pixel =
+            |_z: Float| CameraModel::project(&intrinsics, &(motion * Point3::from(ray / _z)));
+        // Large step, since pixels are hundreds in f32.
+        let h = 1e-2 * _z;
+        let d_pixel = (pixel(_z + h).unwrap() - pixel(_z - h).unwrap()) / (2.0 * h);
+        let j = exp_a * Vec2::new(40.0, -25.0).dot(&d_pixel);
+        let expected = 4.0 / (4.0 + j * j * variances[0]);
+        approx::relative_eq!(weight, expected, epsilon = EPSILON_FINITE_DIFF)
+    }
+
+    // GENERATORS ####################################################
+
+    /// Points of the images, with their inverse depths and motion jacobians.
+    type Points = (Vec<(usize, usize)>, Vec<Float>, Vec<Vec6>, Vec<Mat6>);
+
+    /// Template of a smooth pattern, and image of the same pattern
+    /// with an affine brightness transfer.
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn gen_images(affine: AffineBrightness) -> (DMatrix<u8>, DMatrix<u8>) {
+        let template = DMatrix::from_fn(16, 16, |y, x| (20 + (7 * x + 5 * y) % 180) as u8);
+        let image = template.map(|i| affine.apply(Float::from(i)).round().clamp(0.0, 255.0) as u8);
+        (template, image)
+    }
+
+    /// Interior points of the images, with arbitrary independent motion jacobians.
+    #[allow(clippy::cast_precision_loss)]
+    fn gen_points() -> Points {
+        let coordinates: Vec<(usize, usize)> =
+            (2..13).flat_map(|y| (2..13).map(move |x| (x, y))).collect();
+        let idepths = vec![0.5; coordinates.len()];
+        let jacobians: Vec<Vec6> = coordinates
+            .iter()
+            .map(|&(x, y)| {
+                let (x, y) = (x as Float, y as Float);
+                Vec6::new(x, y, 1.0, x * y, x * x, y * y) / 10.0
+            })
+            .collect();
+        let hessians = jacobians.iter().map(|j| j * j.transpose()).collect();
+        (coordinates, idepths, jacobians, hessians)
+    }
+
+    /// Observations for a motion tracking with the squared loss.
+    fn gen_obs<'a>(
+        template: &'a DMatrix<u8>,
+        image: &'a DMatrix<u8>,
+        (coordinates, idepths, jacobians, hessians): &'a Points,
+    ) -> Obs<'a> {
+        Obs {
+            intrinsics: &INTRINSICS,
+            template,
+            image,
+            coordinates,
+            _z_candidates: idepths,
+            jacobians,
+            hessians,
+            robust_loss: &Squared,
+            fix_affine_brightness: false,
+            motion_prior: None,
+            lm_config: LMConfig::default(),
+            depth: None,
+            rolling_shutter: None,
+            idepth_uncertainty: None,
+        }
+    }
+
+    /// Step of the optimizer, for the camera model of the observations.
+    fn step(state: &LMOptimizerState) -> Model {
+        State::<Obs, EvalState, Model, String>::step(state).unwrap()
+    }
+
+    const INTRINSICS: Intrinsics = Intrinsics {
+        principal_point: (7.5, 7.5),
+        focal: (10.0, 10.0),
+        skew: 0.0,
+    };
+
+    fn gen_intrinsics() -> Intrinsics {
+        Intrinsics {
+            principal_point: (320.0, 240.0),
+            focal: (500.0, 510.0),
+            skew: 0.0,
+        }
+    }
+
+    fn gen_motion(t1: Float, t2: Float, a1: Float, a2: Float) -> Iso3 {
+        let t = Vec3::new(0.3 * bounded(t1), 0.3 * bounded(t2), 0.1);
+        let w = Vec3::new(0.3 * bounded(a1), 0.3 * bounded(a2), 0.1);
+        Iso3::from_parts(t.into(), so3::exp(w))
+    }
+
+    /// Readout of 480 rows in 15 ms, while moving at about 1 m/s and 1 rad/s.
+    fn gen_readout(w1: Float, w2: Float) -> Readout {
+        Readout {
+            line_delay: 0.015 / 480.0,
+            velocity: Vec6::new(0.8, bounded(w1), -0.3, 0.5 * bounded(w2), 0.6, -0.5),
+        }
+    }
+
+    /// Centered finite differences of a function of a twist.
+    fn finite_differences<F: Fn(Vec6) -> Float>(f: F) -> Vec6 {
+        let h = 1e-3;
+        Vec6::from_fn(|k, _| {
+            let mut delta = Vec6::zeros();
+            delta[k] = h;
+            (f(delta) - f(-delta)) / (2.0 * h)
+        })
+    }
+}
@@ -4,5 +4,14 @@
 
 //! Useful types and functions for tracking a camera.
 
+pub mod frame_tracker;
+pub mod icp;
+pub mod icp_optimizer;
+pub mod inertial;
 pub mod inverse_compositional;
+pub mod keyframe;
 pub mod lm_optimizer;
+pub mod monocular;
+pub mod motion_model;
+pub mod sliding_window;
+pub mod window_optimizer;
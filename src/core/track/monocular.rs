@@ -0,0 +1,214 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Inverse depth estimation of keyframes without depth sensor,
+//! for tracking with a monocular camera as in LSD-SLAM.
+//!
+//! Keyframe candidate points are initialized with unknown or random inverse depths,
+//! refined by epipolar line search in each tracked frame,
+//! and propagated to the next keyframe.
+//! As with any monocular system, the trajectory is only known up to a scale factor.
+
+use nalgebra::DMatrix;
+use rand::Rng;
+
+use crate::core::camera::Intrinsics;
+use crate::core::epipolar::{self, SearchConfig};
+use crate::core::inverse_depth::{self, InverseDepth};
+use crate::misc::type_aliases::{Float, Iso3, Point2};
+
+/// Configuration of the monocular inverse depth estimation.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Config {
+    /// Initialization of candidate points without any inverse depth information.
+    pub init: IdepthInit,
+    /// Parameters of the epipolar line search.
+    pub search: SearchConfig,
+    /// Variance added to inverse depths propagated to a new keyframe,
+    /// relative to the squared inverse depth, accounting for the prediction uncertainty.
+    pub propagation_variance: Float,
+}
+
+/// Initialization of inverse depths of new candidate points.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum IdepthInit {
+    /// Inverse depths are unknown until found by an epipolar line search.
+    /// Since unknown points cannot be tracked, this requires pose priors
+    /// (`Tracker::set_pose_prior`) for the first frames.
+    Unknown,
+    /// Inverse depths are drawn uniformly in a range, with a high variance, as in LSD-SLAM.
+    /// Tracking with these wrong inverse depths converges to a consistent solution
+    /// as long as the camera motion stays small during the first frames.
+    Random {
+        /// Range of the random inverse depths.
+        range: (Float, Float),
+        /// Variance of the random inverse depths.
+        variance: Float,
+    },
+}
+
+/// Default configuration of the monocular inverse depth estimation.
+pub const DEFAULT_CONFIG: Config = Config {
+    init: IdepthInit::Random {
+        range: (0.5, 1.5),
+        variance: 0.5,
+    },
+    search: epipolar::DEFAULT_SEARCH_CONFIG,
+    propagation_variance: 0.01,
+};
+
+/// Initial inverse depth map of a new keyframe without any prior information.
+pub fn init_idepth_map(
+    (nb_rows, nb_cols): (usize, usize),
+    init: IdepthInit,
+) -> DMatrix<InverseDepth> {
+    match init {
+        IdepthInit::Unknown => DMatrix::repeat(nb_rows, nb_cols, InverseDepth::Unknown),
+        IdepthInit::Random {
+            range: (idepth_min, idepth_max),
+            variance,
+        } => {
+            let mut rng = rand::thread_rng();
+            DMatrix::from_fn(nb_rows, nb_cols, |_, _| {
+                InverseDepth::WithVariance(rng.gen_range(idepth_min, idepth_max), variance)
+            })
+        }
+    }
+}
+
+/// Refine the inverse depths of the candidate points of a keyframe (host)
+/// by epipolar line search in a tracked frame (target),
+/// where `motion` brings points from the host frame to the target frame.
+///
+/// Successful matches are fused with the previous inverse depths
+/// as the update step of a Kalman filter.
+/// Positions outside of the candidates mask are left unchanged.
+pub fn refine_idepth_map(
+    idepth_map: &DMatrix<InverseDepth>,
+    candidates: &DMatrix<bool>,
+    host: &DMatrix<u8>,
+    target: &DMatrix<u8>,
+    intrinsics: &Intrinsics,
+    motion: &Iso3,
+    search: &SearchConfig,
+) -> DMatrix<InverseDepth> {
+    DMatrix::from_fn(idepth_map.nrows(), idepth_map.ncols(), |y, x| {
+        let idepth = idepth_map[(y, x)];
+        if !candidates[(y, x)] {
+            return idepth;
+        }
+        match epipolar::search(host, target, intrinsics, motion, (x, y), idepth, search) {
+            InverseDepth::WithVariance(d, v) => inverse_depth::kalman_update(idepth, (d, v)),
+            _ => idepth,
+        }
+    })
+}
+
+/// Inverse depth map of a new keyframe, by propagating the known inverse depths
+/// of the previous keyframe, where `motion` brings points from the previous keyframe
+/// to the new one. When several points fall in the same pixel, the closest is kept.
+/// Pixels without propagated inverse depth are initialized as configured.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+#[allow(clippy::cast_precision_loss)]
+pub fn propagate_idepth_map(
+    idepth_map: &DMatrix<InverseDepth>,
+    intrinsics: &Intrinsics,
+    motion: &Iso3,
+    config: &Config,
+) -> DMatrix<InverseDepth> {
+    let (nb_rows, nb_cols) = idepth_map.shape();
+    let mut propagated = init_idepth_map((nb_rows, nb_cols), config.init);
+    let mut propagated_mask = DMatrix::repeat(nb_rows, nb_cols, false);
+    for x in 0..nb_cols {
+        for y in 0..nb_rows {
+            if let InverseDepth::WithVariance(d1, v1) = idepth_map[(y, x)] {
+                let x1 = intrinsics.back_project(Point2::new(x as Float, y as Float), 1.0 / d1);
+                let x2 = motion * x1;
+                if x2.z <= 0.0 {
+                    continue;
+                }
+                let uvz = intrinsics.project(x2);
+                let u = (uvz.x / uvz.z).round();
+                let v = (uvz.y / uvz.z).round();
+                if u < 0.0 || u >= nb_cols as Float || v < 0.0 || v >= nb_rows as Float {
+                    continue;
+                }
+                let (u, v) = (u as usize, v as usize);
+                // Variance propagation of d2 = d1 / (1 + d1 * tz) to first order.
+                let d2 = 1.0 / x2.z;
+                let ratio = d2 / d1;
+                let v2 = ratio.powi(4) * v1 + config.propagation_variance * d2 * d2;
+                let is_closer = match propagated[(v, u)] {
+                    InverseDepth::WithVariance(d, _) => !propagated_mask[(v, u)] || d2 > d,
+                    _ => true,
+                };
+                if is_closer {
+                    propagated[(v, u)] = InverseDepth::WithVariance(d2, v2);
+                    propagated_mask[(v, u)] = true;
+                }
+            }
+        }
+    }
+    propagated
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn propagate_translated_plane() {
+        // Plane at 2 meters, seen 5 pixels to the right after the motion.
+        let intrinsics = Intrinsics {
+            principal_point: (19.5, 9.5),
+            focal: (50.0, 50.0),
+            skew: 0.0,
+        };
+        let idepth_map = DMatrix::repeat(20, 40, InverseDepth::WithVariance(0.5, 1e-3));
+        let motion = Iso3::translation(5.0 * 2.0 / 50.0, 0.0, 0.0);
+        let config = Config {
+            init: IdepthInit::Unknown,
+            ..DEFAULT_CONFIG
+        };
+        let propagated = propagate_idepth_map(&idepth_map, &intrinsics, &motion, &config);
+        let expected_variance = 1e-3 + config.propagation_variance * 0.25;
+        for y in 0..20 {
+            for x in 0..40 {
+                match propagated[(y, x)] {
+                    InverseDepth::WithVariance(d, v) => {
+                        assert!(x >= 5);
+                        assert!((d - 0.5).abs() < 1e-5);
+                        assert!((v - expected_variance).abs() < 1e-6);
+                    }
+                    InverseDepth::Unknown => assert!(x < 5),
+                    InverseDepth::Discarded => panic!("Unexpected discarded inverse depth"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn refine_only_candidates() {
+        let intrinsics = Intrinsics {
+            principal_point: (19.5, 9.5),
+            focal: (50.0, 50.0),
+            skew: 0.0,
+        };
+        let idepth_map = init_idepth_map((20, 40), DEFAULT_CONFIG.init);
+        let candidates = DMatrix::repeat(20, 40, false);
+        let img = DMatrix::from_fn(20, 40, |y, x| (7 * x + 13 * y) as u8);
+        let refined = refine_idepth_map(
+            &idepth_map,
+            &candidates,
+            &img,
+            &img,
+            &intrinsics,
+            &Iso3::translation(0.1, 0.0, 0.0),
+            &DEFAULT_CONFIG.search,
+        );
+        assert_eq!(refined, idepth_map);
+    }
+}
@@ -0,0 +1,208 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Motion models predicting the pose of a new frame,
+//! used to initialize the tracking optimization.
+
+use crate::core::track::lm_optimizer::MotionPrior;
+use crate::math::se3;
+use crate::misc::type_aliases::{Float, Iso3, Mat6};
+
+/// Model used to predict the pose of a new frame from the previous ones.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MotionModel {
+    /// The new frame has the same pose than the previous one.
+    Static,
+    /// Constant twist velocity, estimated from the two previous frames.
+    ConstantVelocity,
+    /// Constant twist acceleration, estimated from the three previous frames.
+    ConstantAcceleration,
+}
+
+/// Pose prior supplied from an external source, such as wheel odometry.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PosePrior {
+    /// Prior pose (in world coordinates) of the next tracked frame.
+    pub pose: Iso3,
+    /// Covariance of the prior, in the tangent space of the camera frame
+    /// (`pose * exp(xi)` with `xi ~ N(0, covariance)`).
+    /// If `None`, the prior is only used to initialize the optimization.
+    /// Otherwise, a prior term is also added to the energy,
+    /// in the same unit than the squared photometric residuals.
+    pub covariance: Option<Mat6>,
+}
+
+impl PosePrior {
+    /// Prior on the motion from the keyframe to the prior frame,
+    /// with its information matrix in the tangent space of the keyframe.
+    /// `None` if the prior has no covariance or if it is not invertible.
+    pub fn motion_prior(&self, keyframe_pose: &Iso3) -> Option<MotionPrior> {
+        let motion = self.pose.inverse() * keyframe_pose;
+        self.covariance.and_then(|cov| {
+            // Covariance from the current camera frame to the keyframe tangent space.
+            let adj = se3::adjoint(motion.inverse());
+            (adj * cov * adj.transpose())
+                .try_inverse()
+                .map(|information| MotionPrior {
+                    motion,
+                    information,
+                })
+        })
+    }
+}
+
+impl MotionModel {
+    /// Predict the pose at a given time, from the history of `(timestamp, pose)`
+    /// of previous frames, ordered from oldest to newest.
+    ///
+    /// If the history is too short for the model,
+    /// falls back to a lower order motion model.
+    ///
+    /// Panics if the history is empty.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn predict(self, history: &[(f64, Iso3)], time: f64) -> Iso3 {
+        let n = history.len();
+        let (t2, pose_2) = history[n - 1];
+        let dt = (time - t2) as Float;
+        match (self, n) {
+            (MotionModel::Static, _) | (_, 1) => pose_2,
+            (MotionModel::ConstantVelocity, _) | (MotionModel::ConstantAcceleration, 2) => {
+                let (t1, pose_1) = history[n - 2];
+                let velocity = twist_velocity(t1, &pose_1, t2, &pose_2);
+                pose_2 * se3::exp(dt * velocity)
+            }
+            (MotionModel::ConstantAcceleration, _) => {
+                let (t0, pose_0) = history[n - 3];
+                let (t1, pose_1) = history[n - 2];
+                let velocity_1 = twist_velocity(t0, &pose_0, t1, &pose_1);
+                let velocity_2 = twist_velocity(t1, &pose_1, t2, &pose_2);
+                // Velocities are estimated at the middle of each time interval.
+                let dt_mid = 0.5 * (t2 - t0) as Float;
+                if dt_mid <= 0.0 {
+                    return pose_2 * se3::exp(dt * velocity_2);
+                }
+                let acceleration = (velocity_2 - velocity_1) / dt_mid;
+                let velocity_at_t2 = velocity_2 + (0.5 * (t2 - t1) as Float) * acceleration;
+                pose_2 * se3::exp(dt * velocity_at_t2 + (0.5 * dt * dt) * acceleration)
+            }
+        }
+    }
+}
+
+/// Mean twist velocity (per second) between two poses.
+/// Null if the timestamps are not strictly increasing.
+#[allow(clippy::cast_possible_truncation)]
+pub fn twist_velocity(t1: f64, pose_1: &Iso3, t2: f64, pose_2: &Iso3) -> se3::Twist {
+    let dt = (t2 - t1) as Float;
+    if dt > 0.0 {
+        se3::log(pose_1.inverse() * pose_2) / dt
+    } else {
+        se3::Twist::zeros()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::misc::helper::bounded;
+    use crate::misc::type_aliases::Vec6;
+    use approx;
+    use quickcheck_macros;
+
+    const EPSILON: Float = 1e-4;
+
+    #[test]
+    fn short_history_fallbacks() {
+        let pose_1 = Iso3::translation(1.0, 0.0, 0.0);
+        let pose_2 = Iso3::translation(2.0, 0.0, 0.0);
+        let single = [(1.0, pose_1)];
+        for &model in &[
+            MotionModel::Static,
+            MotionModel::ConstantVelocity,
+            MotionModel::ConstantAcceleration,
+        ] {
+            assert_eq!(model.predict(&single, 2.0), pose_1);
+        }
+        let double = [(1.0, pose_1), (2.0, pose_2)];
+        let expected = Iso3::translation(3.0, 0.0, 0.0);
+        assert_eq!(MotionModel::Static.predict(&double, 3.0), pose_2);
+        for &model in &[
+            MotionModel::ConstantVelocity,
+            MotionModel::ConstantAcceleration,
+        ] {
+            assert!(approx::relative_eq!(
+                model.predict(&double, 3.0),
+                expected,
+                epsilon = EPSILON
+            ));
+        }
+        // Without increasing timestamps, the velocity is unknown.
+        let same_time = [(1.0, pose_1), (1.0, pose_2)];
+        assert_eq!(
+            MotionModel::ConstantVelocity.predict(&same_time, 2.0),
+            pose_2
+        );
+        let same_times = [(1.0, pose_1), (1.0, pose_1), (1.0, pose_2)];
+        assert_eq!(
+            MotionModel::ConstantAcceleration.predict(&same_times, 2.0),
+            pose_2
+        );
+    }
+
+    #[test]
+    fn constant_acceleration_translation() {
+        // Translations commute, so x(t) = t^2 is exactly predicted.
+        let pose = |t: f64| Iso3::translation((t * t) as Float, 0.0, 0.0);
+        let history: Vec<(f64, Iso3)> = [0.0, 1.0, 2.0].iter().map(|&t| (t, pose(t))).collect();
+        assert!(approx::relative_eq!(
+            MotionModel::ConstantAcceleration.predict(&history, 3.0),
+            pose(3.0),
+            epsilon = EPSILON
+        ));
+        assert!(approx::relative_eq!(
+            MotionModel::ConstantVelocity.predict(&history, 3.0),
+            Iso3::translation(7.0, 0.0, 0.0),
+            epsilon = EPSILON
+        ));
+    }
+
+    #[test]
+    fn pose_prior_without_covariance() {
+        let prior = PosePrior {
+            pose: Iso3::translation(1.0, 0.0, 0.0),
+            covariance: None,
+        };
+        assert_eq!(prior.motion_prior(&Iso3::identity()), None);
+        let singular = PosePrior {
+            covariance: Some(Mat6::zeros()),
+            ..prior
+        };
+        assert_eq!(singular.motion_prior(&Iso3::identity()), None);
+    }
+
+    // PROPERTY TESTS ################################################
+
+    #[quickcheck_macros::quickcheck]
+    fn constant_twist_trajectory(v1: Float, v2: Float, v3: Float, w1: Float, w2: Float) -> bool {
+        let twist = Vec6::new(
+            bounded(v1),
+            bounded(v2),
+            bounded(v3),
+            bounded(w1),
+            bounded(w2),
+            0.3,
+        );
+        let start = Iso3::translation(0.5, -1.0, 2.0);
+        let pose = |t: f64| start * se3::exp(t as Float * twist);
+        // Irregular timestamps.
+        let history: Vec<(f64, Iso3)> = [0.0, 0.4, 1.0].iter().map(|&t| (t, pose(t))).collect();
+        let expected = pose(1.5);
+        let predicted_ok = |model: MotionModel| {
+            approx::relative_eq!(model.predict(&history, 1.5), expected, epsilon = EPSILON)
+        };
+        predicted_ok(MotionModel::ConstantVelocity)
+            && predicted_ok(MotionModel::ConstantAcceleration)
+    }
+}
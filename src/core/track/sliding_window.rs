@@ -0,0 +1,411 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Sliding window photometric bundle adjustment, as the backend of DSO.
+//!
+//! The poses of the last keyframes and the inverse depths of their points
+//! are jointly refined by minimizing the photometric error of each point
+//! in every other keyframe of the window.
+//! When the window is full, the oldest keyframe is marginalized with its points
+//! into a prior on the remaining poses, with a Schur complement.
+//! As in DSO, residuals of points of other keyframes observed in the marginalized
+//! keyframe are dropped instead, to keep the prior free of inverse depths.
+//!
+//! Interesting reads:
+//! - Engel et al., Direct sparse odometry, PAMI 2018.
+//! - Leutenegger et al., Keyframe-based visual-inertial odometry
+//!   using nonlinear optimization, IJRR 2015.
+
+use nalgebra::{DMatrix, DVector};
+
+use crate::core::camera::{CameraModel, Intrinsics};
+use crate::core::track::lm_optimizer::{AffineBrightness, LMConfig};
+use crate::core::track::window_optimizer::{
+    self, MarginalizationPrior, Model, Obs, WindowOptimizerState,
+};
+use crate::math::optimizer::State as _;
+use crate::math::robust::RobustLoss;
+use crate::misc::type_aliases::{Float, Iso3};
+
+/// Default parameters of the Levenberg-Marquardt optimizer of the window.
+pub const DEFAULT_LM_CONFIG: LMConfig = LMConfig {
+    initial_coef: 0.1,
+    coef_increase_factor: 10.0,
+    coef_decrease_factor: 0.1,
+    max_iterations: 10,
+    min_energy_decrease: 0.0,
+    min_relative_energy_decrease: 1e-3,
+    min_step_norm: 1e-6,
+};
+
+/// Configuration of the sliding window.
+pub struct Config {
+    /// Max number of keyframes in the window.
+    pub window_size: usize,
+    /// Level of the multi-resolution pyramids of keyframes used for the optimization.
+    pub level: usize,
+    /// Variance of the image noise, balancing the photometric residuals
+    /// with the inverse depth priors of the points.
+    pub image_noise_variance: Float,
+    /// Information (inverse variance) of the prior fixing the pose of the first keyframe,
+    /// which removes the gauge freedom of the problem.
+    pub gauge_information: Float,
+    /// Robust loss weighting the photometric residuals.
+    pub robust_loss: Box<dyn RobustLoss>,
+    /// Parameters of the Levenberg-Marquardt optimizer.
+    pub lm_config: LMConfig,
+}
+
+/// A keyframe of the window.
+pub struct Keyframe<C: CameraModel = Intrinsics> {
+    /// Timestamp of the keyframe (of depth image).
+    pub timestamp: f64,
+    /// Pose of the keyframe in the world frame.
+    pub pose: Iso3,
+    /// Affine brightness of the keyframe relative to the first frame.
+    /// It is not refined by the optimization.
+    pub affine: AffineBrightness,
+    /// Camera model, at the level of the image.
+    pub intrinsics: C,
+    /// Image of the keyframe.
+    pub image: DMatrix<u8>,
+    /// Points hosted by the keyframe.
+    pub points: Vec<Point>,
+}
+
+/// A point hosted by a keyframe.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Point {
+    /// Pixel coordinates of the point in its keyframe image.
+    pub coordinates: (usize, usize),
+    /// Current estimate of the inverse depth.
+    pub idepth: Float,
+    /// Inverse depth and variance of the measurement used as prior.
+    pub prior: (Float, Float),
+}
+
+/// Result of the optimization of the window.
+#[derive(Debug, Clone, Copy)]
+pub struct WindowResult {
+    /// Final energy of the optimization.
+    pub energy: Float,
+    /// Number of Levenberg-Marquardt iterations.
+    pub nb_iterations: usize,
+    /// Number of photometric residuals.
+    pub nb_residuals: usize,
+}
+
+/// Window of the last keyframes, and prior of the marginalized ones.
+pub struct SlidingWindow<C: CameraModel = Intrinsics> {
+    config: Config,
+    keyframes: Vec<Keyframe<C>>,
+    prior: Option<MarginalizationPrior>,
+}
+
+impl<C: CameraModel> Keyframe<C> {
+    /// Create a keyframe from its candidate points with known inverse depths,
+    /// the measured inverse depths and variances being used as priors.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        timestamp: f64,
+        pose: Iso3,
+        affine: AffineBrightness,
+        intrinsics: C,
+        image: DMatrix<u8>,
+        coordinates: &[(usize, usize)],
+        idepths: &[Float],
+        variances: &[Float],
+    ) -> Self {
+        let points = coordinates
+            .iter()
+            .zip(idepths.iter().zip(variances.iter()))
+            .map(|(&coordinates, (&idepth, &variance))| Point {
+                coordinates,
+                idepth,
+                prior: (idepth, variance.max(Float::EPSILON)),
+            })
+            .collect();
+        Self {
+            timestamp,
+            pose,
+            affine,
+            intrinsics,
+            image,
+            points,
+        }
+    }
+}
+
+impl<C: CameraModel> SlidingWindow<C> {
+    /// Create an empty window.
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            keyframes: Vec::new(),
+            prior: None,
+        }
+    }
+
+    /// Configuration of the window.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Keyframes currently in the window, oldest first.
+    pub fn keyframes(&self) -> &[Keyframe<C>] {
+        &self.keyframes
+    }
+
+    /// Add a new keyframe to the window.
+    /// Its pose is used as initialization for the next optimization.
+    pub fn add_keyframe(&mut self, keyframe: Keyframe<C>) {
+        let information = self.config.gauge_information;
+        match &mut self.prior {
+            Some(prior) => prior.push(keyframe.pose),
+            None => self.prior = Some(MarginalizationPrior::gauge(keyframe.pose, information)),
+        }
+        self.keyframes.push(keyframe);
+    }
+
+    /// Jointly refine the poses and inverse depths of the keyframes of the window.
+    /// Then marginalize the oldest keyframes if the window is too big.
+    ///
+    /// Keyframes are left unchanged if the optimization fails,
+    /// but the oldest ones are still marginalized, such that the window
+    /// never holds more than `window_size` keyframes.
+    pub fn optimize(&mut self) -> Result<WindowResult, String> {
+        let prior = match &self.prior {
+            Some(prior) => prior,
+            None => return Err("No keyframe in the window".to_string()),
+        };
+        let obs = Obs {
+            keyframes: &self.keyframes,
+            prior,
+            image_noise_variance: self.config.image_noise_variance,
+            robust_loss: self.config.robust_loss.as_ref(),
+            lm_config: self.config.lm_config,
+        };
+        let solved = WindowOptimizerState::iterative_solve(&obs, self.model());
+        let result = solved.map(|(state, nb_iterations)| {
+            let eval_data = state.eval_data;
+            for (keyframe, (pose, idepths)) in self.keyframes.iter_mut().zip(
+                eval_data
+                    .model
+                    .poses
+                    .into_iter()
+                    .zip(eval_data.model.idepths),
+            ) {
+                keyframe.pose = pose;
+                for (point, idepth) in keyframe.points.iter_mut().zip(idepths) {
+                    point.idepth = idepth;
+                }
+            }
+            WindowResult {
+                energy: eval_data.energy,
+                nb_iterations,
+                nb_residuals: eval_data.nb_residuals,
+            }
+        });
+        while self.keyframes.len() > self.config.window_size.max(1) {
+            self.marginalize_oldest();
+        }
+        result
+    }
+
+    /// Current poses and inverse depths of the keyframes.
+    fn model(&self) -> Model {
+        Model {
+            poses: self.keyframes.iter().map(|kf| kf.pose).collect(),
+            idepths: self
+                .keyframes
+                .iter()
+                .map(|kf| kf.points.iter().map(|p| p.idepth).collect())
+                .collect(),
+        }
+    }
+
+    /// Marginalize the oldest keyframe and its points into the prior.
+    ///
+    /// The normal equations of the points hosted by the oldest keyframe
+    /// are added to the previous prior, then the inverse depths and the oldest pose
+    /// are eliminated with Schur complements (see `eliminate_first_pose`).
+    /// The new prior is linearized at the current poses of the remaining keyframes.
+    fn marginalize_oldest(&mut self) {
+        let model = self.model();
+        let prior = self.prior.take().unwrap();
+        let obs = Obs {
+            keyframes: &self.keyframes,
+            prior: &prior,
+            image_noise_variance: self.config.image_noise_variance,
+            robust_loss: self.config.robust_loss.as_ref(),
+            lm_config: self.config.lm_config,
+        };
+        let pre = WindowOptimizerState::eval_energy(&obs, &model, Some(0), None);
+        let mut system = WindowOptimizerState::normal_equations(&obs, &model, pre);
+        system.hessian += &prior.hessian;
+        system.gradient += prior.gradient_at(&model.poses);
+        let (hessian, gradient) = window_optimizer::schur_complement(&system, 0.0);
+        let (hessian, gradient) = eliminate_first_pose(&hessian, &gradient);
+        self.prior = Some(MarginalizationPrior {
+            hessian,
+            gradient,
+            linearization: model.poses[1..].to_vec(),
+        });
+        self.keyframes.remove(0);
+    }
+}
+
+/// Eliminate the first pose of the normal equations of the poses
+/// with a Schur complement: `H_kk - H_km * H_mm^-1 * H_mk` and `g_k - H_km * H_mm^-1 * g_m`.
+///
+/// The 6x6 block `H_mm` of the eliminated pose is inverted with a pseudo-inverse.
+/// Directions of that pose not constrained by the system (e.g. a keyframe without
+/// any point nor prior) are thus simply dropped, instead of making the elimination fail.
+fn eliminate_first_pose(
+    hessian: &DMatrix<Float>,
+    gradient: &DVector<Float>,
+) -> (DMatrix<Float>, DVector<Float>) {
+    let n = hessian.nrows() - 6;
+    let h_mm = hessian.slice((0, 0), (6, 6)).into_owned();
+    let h_km = hessian.slice((6, 0), (n, 6)).into_owned();
+    let mut h_kk = hessian.slice((6, 6), (n, n)).into_owned();
+    let mut g_k = gradient.rows(6, n).into_owned();
+    let tolerance = 6.0 * Float::EPSILON * h_mm.norm();
+    let h_mm_inverse = h_mm
+        .pseudo_inverse(tolerance)
+        .expect("Tolerance of the pseudo-inverse is non-negative");
+    let h_km_h_mm_inverse = &h_km * h_mm_inverse;
+    h_kk -= &h_km_h_mm_inverse * h_km.transpose();
+    g_k -= h_km_h_mm_inverse * gradient.rows(0, 6);
+    let h_kk_transpose = h_kk.transpose();
+    ((h_kk + h_kk_transpose) * 0.5, g_k)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::math::robust::Squared;
+    use crate::misc::helper::gen_texture;
+    use approx;
+
+    #[test]
+    fn eliminate_first_pose_is_dense_schur_complement() {
+        let a = DMatrix::from_fn(18, 18, |i, j| ((5 * i + 3 * j) % 13) as Float / 13.0);
+        let hessian = &a * a.transpose() + DMatrix::identity(18, 18);
+        let gradient = DVector::from_fn(18, |i, _| 0.5 - i as Float / 18.0);
+
+        // Dense Schur complement with the exact inverse of the first block.
+        let h_mm_inverse = hessian.slice((0, 0), (6, 6)).into_owned().try_inverse();
+        let h_km_h_mm_inverse = hessian.slice((6, 0), (12, 6)) * h_mm_inverse.unwrap();
+        let expected_hessian =
+            hessian.slice((6, 6), (12, 12)) - &h_km_h_mm_inverse * hessian.slice((0, 6), (6, 12));
+        let expected_gradient = gradient.rows(6, 12) - &h_km_h_mm_inverse * gradient.rows(0, 6);
+
+        let (reduced_hessian, reduced_gradient) = eliminate_first_pose(&hessian, &gradient);
+        assert!(approx::relative_eq!(
+            reduced_hessian,
+            expected_hessian,
+            epsilon = 1e-3
+        ));
+        assert!(approx::relative_eq!(
+            reduced_gradient,
+            expected_gradient,
+            epsilon = 1e-3
+        ));
+
+        // The remaining poses of the full system are solutions of the reduced one.
+        let full_delta = hessian.cholesky().unwrap().solve(&gradient);
+        let delta = reduced_hessian.cholesky().unwrap().solve(&reduced_gradient);
+        assert!(approx::relative_eq!(
+            delta,
+            full_delta.rows(6, 12).into_owned(),
+            epsilon = 1e-3
+        ));
+    }
+
+    #[test]
+    fn eliminate_unconstrained_first_pose() {
+        // The first pose is not constrained at all, so nothing is propagated.
+        let a = DMatrix::from_fn(12, 12, |i, j| ((5 * i + 3 * j) % 13) as Float / 13.0);
+        let mut hessian = DMatrix::zeros(18, 18);
+        hessian
+            .slice_mut((6, 6), (12, 12))
+            .copy_from(&(&a * a.transpose()));
+        let mut gradient = DVector::zeros(18);
+        gradient
+            .rows_mut(6, 12)
+            .copy_from(&DVector::from_fn(12, |i, _| i as Float));
+        let (reduced_hessian, reduced_gradient) = eliminate_first_pose(&hessian, &gradient);
+        assert_eq!(
+            reduced_hessian,
+            hessian.slice((6, 6), (12, 12)).into_owned()
+        );
+        assert_eq!(reduced_gradient, gradient.rows(6, 12).into_owned());
+    }
+
+    #[test]
+    fn window_never_exceeds_its_size() {
+        let mut window = SlidingWindow::new(gen_config());
+        for k in 0..6 {
+            window.add_keyframe(gen_keyframe(k));
+            assert!(window.optimize().is_ok());
+            let nb_keyframes = window.keyframes().len();
+            assert_eq!(nb_keyframes, (k + 1).min(3));
+            assert_eq!(
+                window.keyframes()[0].timestamp,
+                (k + 1 - nb_keyframes) as f64
+            );
+            let prior = window.prior.as_ref().unwrap();
+            assert_eq!(prior.hessian.nrows(), 6 * nb_keyframes);
+            assert_eq!(prior.linearization.len(), nb_keyframes);
+            let last = window.keyframes().last().unwrap();
+            assert!(
+                (last.pose.translation.vector - gen_keyframe(k).pose.translation.vector).norm()
+                    < 1e-2
+            );
+        }
+    }
+
+    // GENERATORS ####################################################
+
+    fn gen_config() -> Config {
+        Config {
+            window_size: 3,
+            level: 0,
+            image_noise_variance: 1.0,
+            gauge_information: 1e6,
+            robust_loss: Box::new(Squared),
+            lm_config: DEFAULT_LM_CONFIG,
+        }
+    }
+
+    /// Keyframes of a textured plane at depth 2, with a camera moving sideways.
+    fn gen_keyframe(k: usize) -> Keyframe {
+        let intrinsics = Intrinsics {
+            principal_point: (39.5, 29.5),
+            focal: (60.0, 60.0),
+            skew: 0.0,
+        };
+        let texture = gen_texture(60, 100);
+        let image = DMatrix::from_fn(60, 80, |y, x| texture[(y, x + k)]);
+        let pose = Iso3::translation(2.0 * k as Float / 60.0, 0.0, 0.0);
+        let coordinates: Vec<_> = (10..50)
+            .step_by(4)
+            .flat_map(|y| (10..70).step_by(4).map(move |x| (x, y)))
+            .collect();
+        let idepths = vec![0.5; coordinates.len()];
+        let variances = vec![1e-2; coordinates.len()];
+        Keyframe::new(
+            k as f64,
+            pose,
+            AffineBrightness::identity(),
+            intrinsics,
+            image,
+            &coordinates,
+            &idepths,
+            &variances,
+        )
+    }
+}
@@ -0,0 +1,599 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Levenberg-Marquardt implementation of the `optimizer::State` trait
+//! for the photometric bundle adjustment of a window of keyframes.
+//!
+//! The parameters are the poses of the keyframes and the inverse depths of their points.
+//! A point hosted in a keyframe has one residual in each other keyframe where it is visible:
+//! `r = I_target(warp(p)) - affine(I_host(p))`.
+//! Since an inverse depth only appears in the residuals of its own point,
+//! the inverse depths block of the hessian is diagonal,
+//! and inverse depths are eliminated with a Schur complement before solving for the poses.
+//!
+//! Normal equations follow the same convention than the other optimizers:
+//! `H * delta = g` with `g = sum(w * r * J)`, and the step is applied as `exp(-delta) * pose`.
+
+use nalgebra::{DMatrix, DVector, U6};
+
+use crate::core::camera::{CameraModel, Intrinsics};
+use crate::core::track::lm_optimizer::{self, LMConfig};
+use crate::core::track::sliding_window::Keyframe;
+use crate::math::optimizer::{self, Continue};
+use crate::math::robust::RobustLoss;
+use crate::math::se3;
+use crate::misc::helper::interpolate;
+use crate::misc::type_aliases::{Float, Iso3, Mat6, Point2, Point3, Vec2, Vec3, Vec6};
+
+/// State of the Levenberg-Marquardt optimizer.
+pub struct WindowOptimizerState {
+    /// Parameters of the optimizer.
+    pub config: LMConfig,
+    /// Levenberg-Marquardt hessian diagonal coefficient.
+    pub lm_coef: Float,
+    /// Data resulting of a successful model evaluation.
+    pub eval_data: EvalData,
+}
+
+/// Either a successfully constructed `EvalData`
+/// or an error containing the energy of a given model.
+///
+/// The error is returned when the new computed energy
+/// is higher than the previous iteration energy.
+pub type EvalState = Result<EvalData, Float>;
+
+/// Data resulting of a successful model evaluation.
+pub struct EvalData {
+    /// Normal equations of the system, including the marginalization prior.
+    pub system: NormalEquations,
+    /// Energy associated with the current model.
+    pub energy: Float,
+    /// Number of photometric residuals used for the energy.
+    pub nb_residuals: usize,
+    /// Estimated poses and inverse depths at the current state of iterations.
+    pub model: Model,
+    /// Scale of the photometric residuals used for the energy,
+    /// fixed at the initial model of the optimization (see `RobustLoss::scale`).
+    pub scale: Float,
+}
+
+/// Parameters of the bundle adjustment.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Model {
+    /// Pose of each keyframe of the window.
+    pub poses: Vec<Iso3>,
+    /// Inverse depths of the points of each keyframe.
+    pub idepths: Vec<Vec<Float>>,
+}
+
+/// Normal equations of the system, before the elimination of inverse depths.
+pub struct NormalEquations {
+    /// Hessian of the poses, of size 6n x 6n for n keyframes.
+    pub hessian: DMatrix<Float>,
+    /// Gradient of the poses, of size 6n.
+    pub gradient: DVector<Float>,
+    /// Terms involving the inverse depth of each point.
+    pub points: Vec<PointBlock>,
+}
+
+/// Terms of the normal equations involving the inverse depth of one point.
+pub struct PointBlock {
+    /// Index of the keyframe hosting the point.
+    pub keyframe: usize,
+    /// Index of the point in its keyframe.
+    pub index: usize,
+    /// Diagonal term of the hessian.
+    pub hessian: Float,
+    /// Gradient term.
+    pub gradient: Float,
+    /// Cross terms of the hessian between the poses and the inverse depth.
+    pub cross: DVector<Float>,
+}
+
+/// Prior on the poses of the keyframes, resulting of the marginalization
+/// of older keyframes, linearized at given poses.
+///
+/// With `dx_i = log(pose_i * linearization_i^-1)`,
+/// its energy is `2 * g^T * dx + dx^T * H * dx` (up to a constant).
+#[derive(Clone, PartialEq, Debug)]
+pub struct MarginalizationPrior {
+    /// Hessian of the prior.
+    pub hessian: DMatrix<Float>,
+    /// Gradient of the prior at the linearization poses.
+    pub gradient: DVector<Float>,
+    /// Poses at which the prior was linearized.
+    pub linearization: Vec<Iso3>,
+}
+
+impl MarginalizationPrior {
+    /// Prior fixing the pose of the first keyframe, to remove the gauge freedom.
+    pub fn gauge(pose: Iso3, information: Float) -> Self {
+        Self {
+            hessian: DMatrix::identity(6, 6) * information,
+            gradient: DVector::zeros(6),
+            linearization: vec![pose],
+        }
+    }
+
+    /// Extend the prior with a new keyframe, without information about it.
+    pub fn push(&mut self, pose: Iso3) {
+        let n = self.gradient.len();
+        let mut hessian = DMatrix::zeros(n + 6, n + 6);
+        hessian.slice_mut((0, 0), (n, n)).copy_from(&self.hessian);
+        self.hessian = hessian;
+        self.gradient = self.gradient.clone().resize_vertically(n + 6, 0.0);
+        self.linearization.push(pose);
+    }
+
+    /// Energy of the prior for the given poses.
+    pub fn energy(&self, poses: &[Iso3]) -> Float {
+        let dx = self.deltas(poses);
+        2.0 * self.gradient.dot(&dx) + dx.dot(&(&self.hessian * &dx))
+    }
+
+    /// Gradient of the prior for the given poses.
+    pub fn gradient_at(&self, poses: &[Iso3]) -> DVector<Float> {
+        &self.gradient + &self.hessian * self.deltas(poses)
+    }
+
+    /// Stacked twists from the linearization poses to the given poses.
+    fn deltas(&self, poses: &[Iso3]) -> DVector<Float> {
+        let mut dx = DVector::zeros(6 * poses.len());
+        for (i, (pose, lin)) in poses.iter().zip(self.linearization.iter()).enumerate() {
+            dx.fixed_rows_mut::<U6>(6 * i)
+                .copy_from(&se3::log(pose * lin.inverse()));
+        }
+        dx
+    }
+}
+
+/// Precomputed data available for the optimizer iterations:
+pub struct Obs<'a, C: CameraModel = Intrinsics> {
+    /// Keyframes of the window, with their images and points.
+    pub keyframes: &'a [Keyframe<C>],
+    /// Prior resulting of the marginalization of older keyframes.
+    pub prior: &'a MarginalizationPrior,
+    /// Variance of the image noise, weighting the photometric residuals.
+    pub image_noise_variance: Float,
+    /// Robust loss used to weight the photometric residuals.
+    pub robust_loss: &'a dyn RobustLoss,
+    /// Parameters of the optimizer.
+    pub lm_config: LMConfig,
+}
+
+/// Photometric residuals of a point, as `(target keyframe, residual, jacobian
+/// with respect to the target pose, jacobian with respect to the inverse depth)`.
+/// The jacobian with respect to the host pose is the opposite of the target one.
+type Residuals = Vec<(usize, Float, Vec6, Float)>;
+
+/// `(energy, (host, index, residuals) of each point, scale)`.
+type Precomputed = (Float, Vec<(usize, usize, Residuals)>, Float);
+
+impl WindowOptimizerState {
+    /// Precompute the energy of a model, restricted to the points hosted by one keyframe
+    /// if `host` is given (without the marginalization prior in that case).
+    /// Also return the residuals with their jacobians, and the residuals scale.
+    ///
+    /// If `scale` is `None`, it is estimated by the robust loss with the residuals
+    /// of this model, otherwise the given scale is used.
+    #[allow(clippy::many_single_char_names)]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn eval_energy<C: CameraModel>(
+        obs: &Obs<C>,
+        model: &Model,
+        host: Option<usize>,
+        scale: Option<Float>,
+    ) -> Precomputed {
+        let poses_inverse: Vec<Iso3> = model.poses.iter().map(|p| p.inverse()).collect();
+        let mut points = Vec::new();
+        let mut idepth_energy = 0.0;
+        for (h, keyframe) in obs.keyframes.iter().enumerate() {
+            if host.is_some() && host != Some(h) {
+                continue;
+            }
+            let pose_h = &model.poses[h];
+            let intrinsics = &keyframe.intrinsics;
+            for (i, point) in keyframe.points.iter().enumerate() {
+                let d = model.idepths[h][i];
+                let (d_prior, v_prior) = point.prior;
+                idepth_energy += (d - d_prior) * (d - d_prior) / v_prior;
+                let (x, y) = point.coordinates;
+                let template = Float::from(keyframe.image[(y, x)]);
+                let ray = match intrinsics.unproject(&Point2::new(x as Float, y as Float)) {
+                    Some(ray) => ray,
+                    None => {
+                        points.push((h, i, Vec::new()));
+                        continue;
+                    }
+                };
+                let ray_world = pose_h.rotation * ray;
+                let x_world = pose_h * Point3::from(ray / d);
+                let mut residuals = Vec::new();
+                for (t, target) in obs.keyframes.iter().enumerate() {
+                    if t == h {
+                        continue;
+                    }
+                    let x_t = poses_inverse[t] * x_world;
+                    let (u, v) = match intrinsics.project(&x_t) {
+                        Some(uv) => (uv.x, uv.y),
+                        None => continue,
+                    };
+                    if let Some((intensity, gu, gv)) = interpolate_gradient(u, v, &target.image) {
+                        let affine = target.affine.compose(&keyframe.affine.inverse());
+                        let r = intensity - affine.apply(template);
+                        let (jac_pose, jac_idepth) = residual_jacobians(
+                            intrinsics,
+                            &model.poses[t],
+                            &x_t,
+                            &x_world,
+                            &ray_world,
+                            d,
+                            Vec2::new(gu, gv),
+                        );
+                        residuals.push((t, r, jac_pose, jac_idepth));
+                    }
+                }
+                points.push((h, i, residuals));
+            }
+        }
+        let r: Vec<Float> = points
+            .iter()
+            .flat_map(|(_, _, residuals)| residuals.iter().map(|(_, r, _, _)| *r))
+            .collect();
+        let scale = scale.unwrap_or_else(|| obs.robust_loss.scale(&r));
+        let photometric_energy: Float = r.iter().map(|&r| obs.robust_loss.rho(r, scale)).sum();
+        let mut energy = photometric_energy / obs.image_noise_variance + idepth_energy;
+        if host.is_none() {
+            energy += obs.prior.energy(&model.poses);
+        }
+        (energy, points, scale)
+    }
+
+    /// Build the normal equations of the residuals, and of the inverse depth priors.
+    /// Each residual is weighted by the robust loss (IRLS) in both the gradient and the hessian.
+    /// The marginalization prior is not included.
+    pub fn normal_equations<C: CameraModel>(
+        obs: &Obs<C>,
+        model: &Model,
+        pre: Precomputed,
+    ) -> NormalEquations {
+        let (_, points, scale) = pre;
+        let nb_params = 6 * obs.keyframes.len();
+        let mut hessian = DMatrix::zeros(nb_params, nb_params);
+        let mut gradient = DVector::zeros(nb_params);
+        let mut blocks = Vec::with_capacity(points.len());
+        for (h, i, residuals) in points {
+            let d = model.idepths[h][i];
+            let (d_prior, v_prior) = obs.keyframes[h].points[i].prior;
+            let mut block = PointBlock {
+                keyframe: h,
+                index: i,
+                hessian: 1.0 / v_prior,
+                gradient: (d - d_prior) / v_prior,
+                cross: DVector::zeros(nb_params),
+            };
+            for (t, r, jac_pose, jac_idepth) in residuals {
+                let w = obs.robust_loss.weight(r, scale) / obs.image_noise_variance;
+                let hessian_pose: Mat6 = (w * jac_pose) * jac_pose.transpose();
+                add_block(&mut hessian, (t, t), &hessian_pose);
+                add_block(&mut hessian, (h, h), &hessian_pose);
+                add_block(&mut hessian, (t, h), &(-hessian_pose));
+                add_block(&mut hessian, (h, t), &(-hessian_pose));
+                let g = (w * r) * jac_pose;
+                add_rows(&mut gradient, t, &g);
+                add_rows(&mut gradient, h, &(-g));
+                let cross = (w * jac_idepth) * jac_pose;
+                add_rows(&mut block.cross, t, &cross);
+                add_rows(&mut block.cross, h, &(-cross));
+                block.hessian += w * jac_idepth * jac_idepth;
+                block.gradient += w * r * jac_idepth;
+            }
+            blocks.push(block);
+        }
+        NormalEquations {
+            hessian,
+            gradient,
+            points: blocks,
+        }
+    }
+
+    /// Fully evaluate a model.
+    fn compute_eval_data<C: CameraModel>(obs: &Obs<C>, model: Model, pre: Precomputed) -> EvalData {
+        let energy = pre.0;
+        let scale = pre.2;
+        let nb_residuals = pre.1.iter().map(|(_, _, r)| r.len()).sum();
+        let mut system = Self::normal_equations(obs, &model, pre);
+        system.hessian += &obs.prior.hessian;
+        system.gradient += obs.prior.gradient_at(&model.poses);
+        EvalData {
+            system,
+            energy,
+            nb_residuals,
+            model,
+            scale,
+        }
+    }
+}
+
+/// Eliminate the inverse depths of the normal equations with a Schur complement,
+/// after multiplying the hessian diagonal by `1 + lm_coef`.
+/// Return the reduced hessian and gradient of the poses.
+pub fn schur_complement(
+    system: &NormalEquations,
+    lm_coef: Float,
+) -> (DMatrix<Float>, DVector<Float>) {
+    let mut hessian = system.hessian.clone();
+    for k in 0..hessian.nrows() {
+        hessian[(k, k)] *= 1.0 + lm_coef;
+    }
+    let mut gradient = system.gradient.clone();
+    for block in system.points.iter() {
+        let h = block.hessian * (1.0 + lm_coef);
+        hessian.ger(-1.0 / h, &block.cross, &block.cross, 1.0);
+        gradient.axpy(-block.gradient / h, &block.cross, 1.0);
+    }
+    (hessian, gradient)
+}
+
+/// `impl<'a, C> optimizer::State<Obs<'a, C>, EvalState, Model, String> for WindowOptimizerState`.
+impl<'a, C: CameraModel> optimizer::State<Obs<'a, C>, EvalState, Model, String>
+    for WindowOptimizerState
+{
+    /// Initialize the optimizer state.
+    fn init(obs: &Obs<C>, model: Model) -> Self {
+        let pre = Self::eval_energy(obs, &model, None, None);
+        Self {
+            config: obs.lm_config,
+            lm_coef: obs.lm_config.initial_coef,
+            eval_data: Self::compute_eval_data(obs, model, pre),
+        }
+    }
+
+    /// Compute the step using Levenberg-Marquardt on the reduced system of the poses,
+    /// then back-substitute the inverse depths.
+    /// Apply the step in a forward compositional approach: `exp(-delta) * pose`.
+    /// May return an error at the Cholesky decomposition of the reduced hessian.
+    fn step(&self) -> Result<Model, String> {
+        let system = &self.eval_data.system;
+        let (hessian, gradient) = schur_complement(system, self.lm_coef);
+        let cholesky = hessian
+            .cholesky()
+            .ok_or("Error at Cholesky decomposition of reduced hessian")?;
+        let delta = cholesky.solve(&gradient);
+        let mut model = self.eval_data.model.clone();
+        for (i, pose) in model.poses.iter_mut().enumerate() {
+            let delta_pose: Vec6 = delta.fixed_rows::<U6>(6 * i).into_owned();
+            *pose = lm_optimizer::renormalize(se3::exp(-delta_pose) * *pose);
+        }
+        for block in system.points.iter() {
+            let h = block.hessian * (1.0 + self.lm_coef);
+            let delta_idepth = (block.gradient - block.cross.dot(&delta)) / h;
+            model.idepths[block.keyframe][block.index] -= delta_idepth;
+        }
+        Ok(model)
+    }
+
+    /// Compute residuals and energy of the new model.
+    /// Then, evaluate the new hessian and gradient if the energy has decreased.
+    fn eval(&self, obs: &Obs<C>, model: Model) -> EvalState {
+        let pre = Self::eval_energy(obs, &model, None, Some(self.eval_data.scale));
+        let energy = pre.0;
+        let old_energy = self.eval_data.energy;
+        if energy > old_energy {
+            Err(energy)
+        } else {
+            Ok(Self::compute_eval_data(obs, model, pre))
+        }
+    }
+
+    /// Stop after too many iterations,
+    /// or if the energy variation or the poses step is too low.
+    ///
+    /// Also update the Levenberg-Marquardt coefficient
+    /// depending on if the energy increased or decreased.
+    fn stop_criterion(self, nb_iter: usize, eval_state: EvalState) -> (Self, Continue) {
+        match eval_state {
+            Err(_) => {
+                let (lm_coef, continuation) = self.config.stop(nb_iter, self.lm_coef, None);
+                (Self { lm_coef, ..self }, continuation)
+            }
+            Ok(eval_data) => {
+                let old_energy = self.eval_data.energy;
+                let step_norm = self
+                    .eval_data
+                    .model
+                    .poses
+                    .iter()
+                    .zip(eval_data.model.poses.iter())
+                    .map(|(old, new)| se3::log(old.inverse() * new).norm())
+                    .fold(0.0, Float::max);
+                let accepted = Some((old_energy, eval_data.energy, step_norm));
+                let (lm_coef, continuation) = self.config.stop(nb_iter, self.lm_coef, accepted);
+                let kept_state = Self {
+                    lm_coef,
+                    eval_data,
+                    ..self
+                };
+                (kept_state, continuation)
+            }
+        }
+    } // fn stop_criterion
+} // impl optimizer::State<...> for WindowOptimizerState
+
+// Helper ######################################################################
+
+/// Add a 6x6 block to the hessian, at the given pair of keyframes.
+fn add_block(hessian: &mut DMatrix<Float>, (i, j): (usize, usize), block: &Mat6) {
+    let mut slice = hessian.fixed_slice_mut::<U6, U6>(6 * i, 6 * j);
+    slice += block;
+}
+
+/// Add a 6 vector to the rows of a keyframe.
+fn add_rows(vector: &mut DVector<Float>, i: usize, rows: &Vec6) {
+    let mut slice = vector.fixed_rows_mut::<U6>(6 * i);
+    slice += rows;
+}
+
+/// Jacobians of a residual with respect to `delta` in `exp(delta) * pose_target`
+/// and to the inverse depth `d` of the point, given the image gradient
+/// at the projection of `x_target`, the point in the target camera frame.
+///
+/// With `a = R_target * dr/dx_target` and `X` the point in the world frame,
+/// the pose jacobian is `[ -a, a x X ]`, and the inverse depth one is
+/// `-a^T * R_host * ray / d^2`, where `R_host * ray` is given as `ray_world`.
+#[allow(clippy::many_single_char_names)]
+fn residual_jacobians<C: CameraModel>(
+    intrinsics: &C,
+    pose_target: &Iso3,
+    x_target: &Point3,
+    x_world: &Point3,
+    ray_world: &Vec3,
+    d: Float,
+    gradient: Vec2,
+) -> (Vec6, Float) {
+    let g3 = intrinsics.project_jacobian(x_target).transpose() * gradient;
+    let a = pose_target.rotation * g3;
+    let w = a.cross(&x_world.coords);
+    let jac_pose = Vec6::new(-a.x, -a.y, -a.z, w.x, w.y, w.z);
+    let jac_idepth = -a.dot(ray_world) / (d * d);
+    (jac_pose, jac_idepth)
+}
+
+/// Interpolated intensity and centered gradient of an image.
+fn interpolate_gradient(x: Float, y: Float, image: &DMatrix<u8>) -> Option<(Float, Float, Float)> {
+    let intensity = interpolate(x, y, image)?;
+    let gu = 0.5 * (interpolate(x + 1.0, y, image)? - interpolate(x - 1.0, y, image)?);
+    let gv = 0.5 * (interpolate(x, y + 1.0, image)? - interpolate(x, y - 1.0, image)?);
+    Some((intensity, gu, gv))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::misc::helper::bounded;
+    use approx;
+    use quickcheck_macros;
+
+    // Precision of centered finite differences of pixel coordinates with f32 computations.
+    const EPSILON_FINITE_DIFF: Float = 1e-1;
+
+    #[test]
+    fn schur_complement_solves_full_system() {
+        let a = DMatrix::from_fn(12, 12, |i, j| ((3 * i + 7 * j) % 11) as Float / 11.0);
+        let hessian = &a * a.transpose() + DMatrix::identity(12, 12);
+        let gradient = DVector::from_fn(12, |i, _| i as Float / 12.0 - 0.5);
+        let points: Vec<PointBlock> = (0..3)
+            .map(|k| PointBlock {
+                keyframe: k % 2,
+                index: k,
+                hessian: 2.0 + k as Float,
+                gradient: 0.3 - 0.2 * k as Float,
+                cross: DVector::from_fn(12, |i, _| ((i + k) % 5) as Float / 10.0),
+            })
+            .collect();
+        let system = NormalEquations {
+            hessian,
+            gradient,
+            points,
+        };
+
+        // Dense system with the poses and the three inverse depths.
+        let mut full_hessian = DMatrix::zeros(15, 15);
+        let mut full_gradient = DVector::zeros(15);
+        full_hessian
+            .slice_mut((0, 0), (12, 12))
+            .copy_from(&system.hessian);
+        full_gradient.rows_mut(0, 12).copy_from(&system.gradient);
+        for (k, block) in system.points.iter().enumerate() {
+            full_hessian[(12 + k, 12 + k)] = block.hessian;
+            full_hessian
+                .slice_mut((0, 12 + k), (12, 1))
+                .copy_from(&block.cross);
+            full_hessian
+                .slice_mut((12 + k, 0), (1, 12))
+                .copy_from(&block.cross.transpose());
+            full_gradient[12 + k] = block.gradient;
+        }
+        let full_delta = full_hessian.cholesky().unwrap().solve(&full_gradient);
+
+        let (reduced_hessian, reduced_gradient) = schur_complement(&system, 0.0);
+        let delta = reduced_hessian.cholesky().unwrap().solve(&reduced_gradient);
+        assert!(approx::relative_eq!(
+            delta,
+            full_delta.rows(0, 12).into_owned(),
+            epsilon = 1e-4
+        ));
+        for (k, block) in system.points.iter().enumerate() {
+            let delta_idepth = (block.gradient - block.cross.dot(&delta)) / block.hessian;
+            assert!(approx::relative_eq!(
+                delta_idepth,
+                full_delta[12 + k],
+                epsilon = 1e-4
+            ));
+        }
+    }
+
+    // PROPERTY TESTS ################################################
+
+    #[quickcheck_macros::quickcheck]
+    #[allow(clippy::many_single_char_names)]
+    fn residual_jacobians_ok(x: Float, y: Float, d: Float, t: Float, w: Float, gu: Float) -> bool {
+        let intrinsics = Intrinsics {
+            principal_point: (160.0, 120.0),
+            focal: (300.0, 300.0),
+            skew: 0.0,
+        };
+        let pixel = Point2::new(160.0 + 100.0 * bounded(x), 120.0 + 80.0 * bounded(y));
+        let d = 0.3 + 0.5 * bounded(d).abs();
+        let gradient = Vec2::new(bounded(gu), 0.5);
+        let pose_host = Iso3::new(Vec3::new(0.1, 0.0, -0.1), Vec3::new(0.0, 0.05, 0.0));
+        let pose_target = Iso3::new(
+            Vec3::new(0.2 * bounded(t), -0.1, 0.1),
+            Vec3::new(0.1 * bounded(w), 0.0, -0.05),
+        );
+        let ray = CameraModel::unproject(&intrinsics, &pixel).unwrap();
+        let ray_world = pose_host.rotation * ray;
+        let x_world = |d: Float| pose_host * Point3::from(ray / d);
+        let x_target = pose_target.inverse() * x_world(d);
+        let (jac_pose, jac_idepth) = residual_jacobians(
+            &intrinsics,
+            &pose_target,
+            &x_target,
+            &x_world(d),
+            &ray_world,
+            d,
+            gradient,
+        );
+
+        // Residual linearized with the image gradient.
+        let residual = |pose: &Iso3, d: Float| {
+            let uv = CameraModel::project(&intrinsics, &(pose.inverse() * x_world(d))).unwrap();
+            gradient.dot(&uv.coords)
+        };
+        let h = 1e-2;
+        let numeric_pose = Vec6::from_fn(|k, _| {
+            let mut delta = Vec6::zeros();
+            delta[k] = h;
+            let r_plus = residual(&(se3::exp(delta) * pose_target), d);
+            let r_minus = residual(&(se3::exp(-delta) * pose_target), d);
+            (r_plus - r_minus) / (2.0 * h)
+        });
+        let h_idepth = 1e-2 * d;
+        let numeric_idepth = (residual(&pose_target, d + h_idepth)
+            - residual(&pose_target, d - h_idepth))
+            / (2.0 * h_idepth);
+        approx::relative_eq!(
+            jac_pose,
+            numeric_pose,
+            epsilon = EPSILON_FINITE_DIFF,
+            max_relative = 1e-2
+        ) && approx::relative_eq!(
+            jac_idepth,
+            numeric_idepth,
+            epsilon = EPSILON_FINITE_DIFF,
+            max_relative = 1e-2
+        )
+    }
+}
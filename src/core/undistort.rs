@@ -0,0 +1,273 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Undistortion and stereo rectification of images, with precomputed remap tables.
+//!
+//! A remap table gives, for each pixel of an ideal pinhole camera,
+//! the coordinates of the same ray in the image of a distorted camera.
+//! It is computed once per camera, and applied to each new image.
+//! Intensity images are sampled with a bilinear interpolation,
+//! and depth images with the nearest neighbor, to avoid mixing depths across edges.
+
+use nalgebra::{DMatrix, Rotation3, UnitQuaternion};
+
+use crate::core::camera::{CameraModel, Intrinsics};
+use crate::misc::helper;
+use crate::misc::type_aliases::{Float, Iso3, Mat3, Point2, Point3, Vec3};
+
+/// Pixel of the source (distorted) image corresponding to a target pixel.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SourcePixel {
+    /// Coordinates `(x, y)` in the source image.
+    pub coordinates: (Float, Float),
+    /// Factor converting a depth of the source camera, in the convention of its model,
+    /// into the `z` depth of the target pinhole camera.
+    pub depth_factor: Float,
+}
+
+/// Remap table from a source camera to a target pinhole camera.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Remap {
+    /// Intrinsic parameters of the target pinhole camera.
+    pub target: Intrinsics,
+    /// Source pixel of each target pixel,
+    /// `None` if its ray cannot be projected in the source camera.
+    pub map: DMatrix<Option<SourcePixel>>,
+}
+
+/// Remap tables and parameters of a rectified stereo pair.
+#[derive(Clone, PartialEq, Debug)]
+pub struct StereoRectification {
+    /// Remap table of the left camera.
+    pub left: Remap,
+    /// Remap table of the right camera.
+    pub right: Remap,
+    /// Rotation of the rectified cameras frame in the left camera frame.
+    pub rotation: UnitQuaternion<Float>,
+    /// Distance between the two cameras, along the x axis of the rectified cameras.
+    pub baseline: Float,
+}
+
+/// Pinhole intrinsics of an image of shape `(nb_rows, nb_cols)`,
+/// with a given focal length (in pixels) and the principal point at the image center.
+#[allow(clippy::cast_precision_loss)]
+pub fn pinhole_with_focal((nb_rows, nb_cols): (usize, usize), focal: Float) -> Intrinsics {
+    Intrinsics {
+        principal_point: (
+            0.5 * (nb_cols as Float - 1.0),
+            0.5 * (nb_rows as Float - 1.0),
+        ),
+        focal: (focal, focal),
+        skew: 0.0,
+    }
+}
+
+/// Pinhole intrinsics of an image of shape `(nb_rows, nb_cols)`,
+/// with a given horizontal field of view (in radians, below pi)
+/// and the principal point at the image center.
+#[allow(clippy::cast_precision_loss)]
+pub fn pinhole_with_fov(shape: (usize, usize), horizontal_fov: Float) -> Intrinsics {
+    let focal = 0.5 * shape.1 as Float / (0.5 * horizontal_fov).tan();
+    pinhole_with_focal(shape, focal)
+}
+
+impl Remap {
+    /// Remap table from a source camera to a target pinhole camera with the same orientation,
+    /// for target images of shape `(nb_rows, nb_cols)`.
+    pub fn new<C: CameraModel>(source: &C, target: Intrinsics, shape: (usize, usize)) -> Self {
+        Self::with_rotation(source, target, shape, &UnitQuaternion::identity())
+    }
+
+    /// Remap table from a source camera to a target pinhole camera,
+    /// given the rotation of the target camera frame in the source camera frame.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn with_rotation<C: CameraModel>(
+        source: &C,
+        target: Intrinsics,
+        (nb_rows, nb_cols): (usize, usize),
+        rotation: &UnitQuaternion<Float>,
+    ) -> Self {
+        let map = DMatrix::from_fn(nb_rows, nb_cols, |y, x| {
+            let ray = target.back_project(Point2::new(x as Float, y as Float), 1.0);
+            let point = Point3::from(rotation * ray.coords);
+            let pixel = source.project(&point)?;
+            Some(SourcePixel {
+                coordinates: (pixel.x, pixel.y),
+                depth_factor: 1.0 / source.depth(&point),
+            })
+        });
+        Self { target, map }
+    }
+
+    /// Remap an intensity image, with a bilinear interpolation.
+    /// Pixels outside of the source image are black.
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    pub fn apply(&self, image: &DMatrix<u8>) -> DMatrix<u8> {
+        self.map.map(|source| {
+            source
+                .and_then(|s| helper::interpolate(s.coordinates.0, s.coordinates.1, image))
+                .map_or(0, |intensity| intensity.round() as u8)
+        })
+    }
+
+    /// Remap a depth image, with the nearest neighbor.
+    /// Depths are converted into `z` depths of the target pinhole camera.
+    /// Pixels outside of the source image, or without depth, have a depth of 0.
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn apply_depth(&self, depth_map: &DMatrix<u16>) -> DMatrix<u16> {
+        let (nb_rows, nb_cols) = depth_map.shape();
+        self.map.map(|source| match source {
+            Some(s) => {
+                let (x, y) = (s.coordinates.0.round(), s.coordinates.1.round());
+                if x < 0.0 || x >= nb_cols as Float || y < 0.0 || y >= nb_rows as Float {
+                    return 0;
+                }
+                let depth = Float::from(depth_map[(y as usize, x as usize)]);
+                (depth * s.depth_factor).round().min(Float::from(u16::MAX)) as u16
+            }
+            None => 0,
+        })
+    }
+}
+
+/// Rectify a stereo pair, given the pose of the right camera in the left camera frame.
+///
+/// Both rectified cameras have the `target` intrinsics and images of shape `(nb_rows, nb_cols)`.
+/// Their x axis is the baseline, and their optical axis is orthogonal to it,
+/// as close as possible to the mean optical axis of both cameras,
+/// such that epipolar lines are image rows, as expected by the `stereo` module.
+pub fn stereo_rectify<L: CameraModel, R: CameraModel>(
+    left: &L,
+    right: &R,
+    right_pose: &Iso3,
+    target: Intrinsics,
+    shape: (usize, usize),
+) -> StereoRectification {
+    let baseline = right_pose.translation.vector;
+    let x_axis = baseline.normalize();
+    let mean_z_axis = Vec3::z() + right_pose.rotation * Vec3::z();
+    let y_axis = mean_z_axis.cross(&x_axis).normalize();
+    let z_axis = x_axis.cross(&y_axis);
+    let basis = Mat3::from_columns(&[x_axis, y_axis, z_axis]);
+    let rotation = UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(basis));
+    StereoRectification {
+        left: Remap::with_rotation(left, target.clone(), shape, &rotation),
+        right: Remap::with_rotation(
+            right,
+            target,
+            shape,
+            &(right_pose.rotation.inverse() * rotation),
+        ),
+        rotation,
+        baseline: baseline.norm(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::core::camera::{KannalaBrandt, RadialTangential};
+    use crate::misc::helper::bounded;
+    use approx;
+    use quickcheck_macros;
+
+    const SHAPE: (usize, usize) = (48, 64);
+    const EPSILON: Float = 1e-3;
+
+    #[test]
+    #[allow(clippy::cast_possible_truncation)]
+    fn identity_remap() {
+        let camera = pinhole_with_focal(SHAPE, 50.0);
+        let remap = Remap::new(&camera, camera.clone(), SHAPE);
+        let image = DMatrix::from_fn(SHAPE.0, SHAPE.1, |y, x| (3 * x + 5 * y) as u8);
+        let remapped = remap.apply(&image);
+        // Bilinear interpolation is not defined on the last two rows and columns.
+        let (nb_rows, nb_cols) = (SHAPE.0 - 2, SHAPE.1 - 2);
+        assert_eq!(
+            remapped.slice((0, 0), (nb_rows, nb_cols)),
+            image.slice((0, 0), (nb_rows, nb_cols))
+        );
+    }
+
+    #[test]
+    #[allow(clippy::cast_precision_loss)]
+    fn fisheye_depth_to_pinhole_depth() {
+        // Sphere of radius 2 around the camera, seen by a fisheye with distances as depths.
+        let source = gen_kannala_brandt();
+        let target = pinhole_with_focal(SHAPE, 30.0);
+        let depth_map = DMatrix::repeat(SHAPE.0, SHAPE.1, 2000);
+        let remap = Remap::new(&source, target.clone(), SHAPE);
+        let remapped = remap.apply_depth(&depth_map);
+        let mut nb_valid = 0;
+        for y in 0..SHAPE.0 {
+            for x in 0..SHAPE.1 {
+                let depth = remapped[(y, x)];
+                if depth != 0 {
+                    let ray = target.back_project(Point2::new(x as Float, y as Float), 1.0);
+                    let expected = 2000.0 / ray.coords.norm();
+                    assert!((Float::from(depth) - expected).abs() <= 1.0);
+                    nb_valid += 1;
+                }
+            }
+        }
+        assert!(nb_valid > SHAPE.0 * SHAPE.1 / 2);
+    }
+
+    #[test]
+    fn stereo_rectified_rows() {
+        let rotation = UnitQuaternion::from_euler_angles(0.02, -0.05, 0.01);
+        let right_pose = Iso3::from_parts(Vec3::new(0.11, 0.005, -0.002).into(), rotation);
+        let camera = gen_radial_tangential();
+        let rectification =
+            stereo_rectify(&camera, &camera, &right_pose, camera.pinhole.clone(), SHAPE);
+        let point = Vec3::new(0.3, -0.2, 1.5);
+        let left = rectification.rotation.inverse() * point;
+        let right = rectification.rotation.inverse() * (point - right_pose.translation.vector);
+        assert!(approx::relative_eq!(left.y, right.y, epsilon = EPSILON));
+        assert!(approx::relative_eq!(left.z, right.z, epsilon = EPSILON));
+        assert!(approx::relative_eq!(
+            left.x - right.x,
+            rectification.baseline,
+            epsilon = EPSILON
+        ));
+    }
+
+    // PROPERTY TESTS ################################################
+
+    #[quickcheck_macros::quickcheck]
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn remap_same_ray(x: Float, y: Float) -> bool {
+        let source = gen_radial_tangential();
+        let target = pinhole_with_focal(SHAPE, 40.0);
+        let remap = Remap::new(&source, target.clone(), SHAPE);
+        let col = ((0.5 + 0.5 * bounded(x)) * (SHAPE.1 - 1) as Float) as usize;
+        let row = ((0.5 + 0.5 * bounded(y)) * (SHAPE.0 - 1) as Float) as usize;
+        let target_ray = target.back_project(Point2::new(col as Float, row as Float), 1.0);
+        remap.map[(row, col)]
+            .and_then(|s| source.unproject(&Point2::new(s.coordinates.0, s.coordinates.1)))
+            .is_some_and(|ray| approx::relative_eq!(ray, target_ray.coords, epsilon = EPSILON))
+    }
+
+    // GENERATORS ####################################################
+
+    fn gen_radial_tangential() -> RadialTangential {
+        RadialTangential {
+            pinhole: pinhole_with_focal(SHAPE, 45.0),
+            radial: (-0.28, 0.07, 0.0),
+            tangential: (1e-4, -2e-4),
+        }
+    }
+
+    fn gen_kannala_brandt() -> KannalaBrandt {
+        KannalaBrandt {
+            pinhole: pinhole_with_focal(SHAPE, 20.0),
+            coefficients: (0.0034, 0.0007, -0.002, 0.0002),
+        }
+    }
+}
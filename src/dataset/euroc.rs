@@ -0,0 +1,92 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Helper functions to handle the EuRoC MAV dataset (ASL format).
+
+use nalgebra as na;
+
+use crate::core::imu::{ImuMeasurement, Noise};
+use crate::misc::type_aliases::{Iso3, Vec3};
+
+/// Noise densities of the ADIS16448 IMU, given in `imu0/sensor.yaml`.
+pub const IMU_NOISE: Noise = Noise {
+    gyro: 1.696_8e-4,
+    accel: 2.0e-3,
+};
+
+/// Pose of the left camera (cam0) in the IMU (body) frame,
+/// given as `T_BS` in `cam0/sensor.yaml`.
+#[allow(clippy::excessive_precision)]
+#[allow(clippy::unreadable_literal)]
+pub fn cam0_to_body() -> Iso3 {
+    #[rustfmt::skip]
+    let rotation = na::Matrix3::new(
+        0.0148655429818, -0.999880929698, 0.00414029679422,
+        0.999557249008, 0.0149672133247, 0.025715529948,
+        -0.0257744366974, 0.00375618835797, 0.999660727178,
+    );
+    let translation = na::Translation3::new(-0.0216401454975, -0.064676986768, 0.00981073058949);
+    let rotation =
+        na::UnitQuaternion::from_rotation_matrix(&na::Rotation3::from_matrix_unchecked(rotation));
+    Iso3::from_parts(translation, rotation)
+}
+
+/// Parse useful files of a dataset in the EuRoC (ASL) format.
+pub mod parse {
+    use super::*;
+    use nom::{
+        alt, anychar, digit, do_parse, float, many0, map, map_res, named, tag, types::CompleteStr,
+    };
+
+    /// Parse an IMU file (`imu0/data.csv`) into a vector of `ImuMeasurement`.
+    /// Timestamps are converted from nanoseconds to seconds.
+    pub fn imu(file_content: &str) -> Result<Vec<ImuMeasurement>, String> {
+        let mut measurements = Vec::new();
+        for line in file_content.lines() {
+            match imu_line(CompleteStr(line)) {
+                Ok((_, Some(m))) => measurements.push(m),
+                Ok(_) => (),
+                Err(_) => return Err(format!("Parsing error at line: {}", line)),
+            }
+        }
+        Ok(measurements)
+    }
+
+    // nom parsers #############################################################
+
+    // IMU line is either a comment or a timestamp, angular velocity and acceleration.
+    named!(imu_line<CompleteStr, Option<ImuMeasurement> >,
+        alt!( map!(comment, |_| None) | map!(imu_measurement, Some) )
+    );
+
+    // Parse a comment.
+    named!(comment<CompleteStr,()>,
+        do_parse!( tag!("#") >> many0!(anychar) >> ())
+    );
+
+    // Parse `timestamp,wx,wy,wz,ax,ay,az`.
+    named!(imu_measurement<CompleteStr, ImuMeasurement>,
+        do_parse!(
+            t: timestamp >> tag!(",") >>
+            gyro: vec3 >> tag!(",") >>
+            accel: vec3 >>
+            (ImuMeasurement { timestamp: t, gyro, accel })
+        )
+    );
+
+    // Parse a timestamp in nanoseconds, into seconds.
+    named!(timestamp<CompleteStr, f64>,
+        map_res!(digit, |s: CompleteStr| s.parse::<u64>().map(|ns| ns as f64 * 1e-9))
+    );
+
+    // Parse comma separated components of a 3D vector.
+    named!(vec3<CompleteStr, Vec3>,
+        do_parse!(
+            x: float >> tag!(",") >>
+            y: float >> tag!(",") >>
+            z: float >>
+            (Vec3::new(x, y, z))
+        )
+    );
+} // pub mod parse
@@ -4,4 +4,6 @@
 
 //! Helpers modules for managing known RGB-D datasets.
 
+pub mod euroc;
+pub mod tum_mono;
 pub mod tum_rgbd;
@@ -0,0 +1,93 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Helper functions to handle the TUM monoVO dataset.
+
+use nalgebra::DMatrix;
+use std::{error::Error, fs, path::Path};
+
+use crate::core::photometric::Calibration;
+use crate::misc::helper;
+use crate::misc::type_aliases::Float;
+
+/// Timestamp and exposure of a frame, given in `times.txt`.
+#[derive(Debug)]
+pub struct FrameTime {
+    /// Index of the frame, as in the image file name.
+    pub id: usize,
+    /// Timestamp of the frame (in seconds).
+    pub timestamp: f64,
+    /// Exposure time of the frame (in milliseconds).
+    pub exposure: Float,
+}
+
+/// Read the photometric calibration of a sequence,
+/// from its inverse response (`pcalib.txt`) and its 16 bits vignette (`vignette.png`).
+pub fn read_photometric_calibration<P: AsRef<Path>, Q: AsRef<Path>>(
+    pcalib_path: P,
+    vignette_path: Q,
+) -> Result<Calibration, Box<dyn Error>> {
+    let inverse_response = parse::inverse_response(&fs::read_to_string(pcalib_path)?)?;
+    let (width, height, vignette_u16) = helper::read_png_16bits(vignette_path)?;
+    let vignette = DMatrix::from_row_slice(height, width, vignette_u16.as_slice()).map(Float::from);
+    Calibration::new(inverse_response, vignette).map_err(|s| s.into())
+}
+
+/// Parse useful files of a sequence in the TUM monoVO format.
+pub mod parse {
+    use super::*;
+    use nom::{
+        alt, anychar, digit, do_parse, double, float, many0, many1, map, map_res, multispace,
+        named, opt, space, tag, types::CompleteStr,
+    };
+
+    /// Parse an inverse response file (`pcalib.txt`),
+    /// the 256 irradiances of each intensity, separated by white spaces.
+    pub fn inverse_response(file_content: &str) -> Result<Vec<Float>, String> {
+        match irradiances(CompleteStr(file_content)) {
+            Ok((rest, values)) if rest.trim().is_empty() => Ok(values),
+            _ => Err("Parsing error of the inverse response".to_string()),
+        }
+    }
+
+    /// Parse a times file (`times.txt`) into a vector of `FrameTime`.
+    pub fn times(file_content: &str) -> Result<Vec<FrameTime>, String> {
+        let mut frames = Vec::new();
+        for line in file_content.lines() {
+            match times_line(CompleteStr(line)) {
+                Ok((_, Some(frame))) => frames.push(frame),
+                Ok(_) => (),
+                Err(_) => return Err(format!("Parsing error at line: {}", line)),
+            }
+        }
+        Ok(frames)
+    }
+
+    // nom parsers #############################################################
+
+    // Parse white space separated irradiances.
+    named!(irradiances<CompleteStr, Vec<Float> >,
+        many1!(do_parse!( opt!(multispace) >> value: float >> (value) ))
+    );
+
+    // Times line is either a comment or a frame id, timestamp and exposure.
+    named!(times_line<CompleteStr, Option<FrameTime> >,
+        alt!( map!(comment, |_| None) | map!(frame_time, Some) )
+    );
+
+    // Parse a comment.
+    named!(comment<CompleteStr,()>,
+        do_parse!( tag!("#") >> many0!(anychar) >> ())
+    );
+
+    // Parse `id timestamp exposure`.
+    named!(frame_time<CompleteStr, FrameTime>,
+        do_parse!(
+            id: map_res!(digit, |s: CompleteStr| s.parse::<usize>()) >> space >>
+            timestamp: double >> space >>
+            exposure: float >>
+            (FrameTime { id, timestamp, exposure })
+        )
+    );
+} // pub mod parse
@@ -5,5 +5,6 @@
 //! Math-related modules.
 
 pub mod optimizer;
+pub mod robust;
 pub mod se3;
 pub mod so3;
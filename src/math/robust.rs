@@ -0,0 +1,215 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Robust loss functions (M-estimators) for iteratively reweighted least squares.
+//!
+//! Each loss `rho` is normalized such that `rho(r) ~ r^2` for small residuals,
+//! and its associated weight is `w(r) = rho'(r) / (2 r)`.
+//! Hence a plain least squares problem corresponds to `rho(r) = r^2` and `w(r) = 1`.
+//!
+//! Interesting reads:
+//! - Zhang, Parameter estimation techniques: a tutorial with application to conic fitting.
+//! - Kerl et al., Robust odometry estimation for RGB-D cameras (DVO), ICRA 2013.
+
+use crate::misc::type_aliases::Float;
+
+/// A robust loss that can be plugged in an IRLS optimizer.
+pub trait RobustLoss {
+    /// Estimate the scale (variance) of the residuals distribution.
+    /// Most losses have fixed thresholds and do not need it, so it defaults to 1.
+    ///
+    /// Optimizers estimate it with the residuals of their initial model only,
+    /// and keep it fixed afterwards such that energies of successive models are comparable.
+    fn scale(&self, _residuals: &[Float]) -> Float {
+        1.0
+    }
+
+    /// Energy associated with a residual, given the residuals scale.
+    fn rho(&self, r: Float, scale: Float) -> Float;
+
+    /// IRLS weight associated with a residual, given the residuals scale.
+    fn weight(&self, r: Float, scale: Float) -> Float;
+}
+
+/// Plain least squares: `rho(r) = r^2`.
+pub struct Squared;
+
+impl RobustLoss for Squared {
+    fn rho(&self, r: Float, _scale: Float) -> Float {
+        r * r
+    }
+
+    fn weight(&self, _r: Float, _scale: Float) -> Float {
+        1.0
+    }
+}
+
+/// Huber loss, quadratic below the threshold and linear above.
+pub struct Huber {
+    /// Threshold (in residual unit) between the quadratic and linear parts.
+    pub threshold: Float,
+}
+
+impl RobustLoss for Huber {
+    fn rho(&self, r: Float, _scale: Float) -> Float {
+        let k = self.threshold;
+        let r_abs = r.abs();
+        if r_abs <= k {
+            r * r
+        } else {
+            2.0 * k * r_abs - k * k
+        }
+    }
+
+    fn weight(&self, r: Float, _scale: Float) -> Float {
+        let r_abs = r.abs();
+        if r_abs <= self.threshold {
+            1.0
+        } else {
+            self.threshold / r_abs
+        }
+    }
+}
+
+/// Tukey biweight loss, completely ignoring residuals above the threshold.
+pub struct Tukey {
+    /// Residuals above this threshold (in residual unit) get a zero weight.
+    pub threshold: Float,
+}
+
+impl RobustLoss for Tukey {
+    fn rho(&self, r: Float, _scale: Float) -> Float {
+        let c_2 = self.threshold * self.threshold;
+        if r.abs() <= self.threshold {
+            c_2 / 3.0 * (1.0 - (1.0 - r * r / c_2).powi(3))
+        } else {
+            c_2 / 3.0
+        }
+    }
+
+    fn weight(&self, r: Float, _scale: Float) -> Float {
+        if r.abs() <= self.threshold {
+            (1.0 - r * r / (self.threshold * self.threshold)).powi(2)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Cauchy (Lorentzian) loss.
+pub struct Cauchy {
+    /// Residual (in residual unit) at which the weight is halved.
+    pub threshold: Float,
+}
+
+impl RobustLoss for Cauchy {
+    fn rho(&self, r: Float, _scale: Float) -> Float {
+        let c_2 = self.threshold * self.threshold;
+        c_2 * (r * r / c_2).ln_1p()
+    }
+
+    fn weight(&self, r: Float, _scale: Float) -> Float {
+        1.0 / (1.0 + r * r / (self.threshold * self.threshold))
+    }
+}
+
+/// Student t-distribution loss, with a scale estimated from the residuals as in DVO.
+pub struct StudentT {
+    /// Degrees of freedom of the distribution, 5.0 in DVO.
+    pub dof: Float,
+}
+
+/// Max number of fixed point iterations for the t-distribution scale estimation.
+const T_SCALE_MAX_ITERATIONS: usize = 10;
+/// Relative precision at which the t-distribution scale estimation stops.
+const T_SCALE_EPSILON: Float = 1e-3;
+
+impl RobustLoss for StudentT {
+    /// Fixed point iterations of `s = 1/n * sum( r^2 * (dof + 1) / (dof + r^2 / s) )`.
+    #[allow(clippy::cast_precision_loss)]
+    fn scale(&self, residuals: &[Float]) -> Float {
+        if residuals.is_empty() {
+            return 1.0;
+        }
+        let nb_residuals = residuals.len() as Float;
+        let mut scale = residuals.iter().map(|r| r * r).sum::<Float>() / nb_residuals;
+        for _ in 0..T_SCALE_MAX_ITERATIONS {
+            if scale <= Float::EPSILON {
+                return Float::EPSILON;
+            }
+            let new_scale = residuals
+                .iter()
+                .map(|r| r * r * self.weight(*r, scale))
+                .sum::<Float>()
+                / nb_residuals;
+            let converged = (new_scale - scale).abs() < T_SCALE_EPSILON * scale;
+            scale = new_scale;
+            if converged {
+                break;
+            }
+        }
+        scale.max(Float::EPSILON)
+    }
+
+    fn rho(&self, r: Float, scale: Float) -> Float {
+        scale * (self.dof + 1.0) * (r * r / (self.dof * scale)).ln_1p()
+    }
+
+    fn weight(&self, r: Float, scale: Float) -> Float {
+        (self.dof + 1.0) / (self.dof + r * r / scale)
+    }
+}
+
+// TESTS #############################################################
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use approx;
+    use quickcheck_macros;
+
+    const EPSILON: Float = 1e-3;
+
+    #[test]
+    fn squared_is_least_squares() {
+        assert_eq!(9.0, Squared.rho(3.0, 1.0));
+        assert_eq!(1.0, Squared.weight(3.0, 1.0));
+    }
+
+    #[test]
+    fn t_scale_of_zero_residuals() {
+        let t = StudentT { dof: 5.0 };
+        assert!(t.scale(&[]) > 0.0);
+        assert!(t.scale(&[0.0, 0.0]) > 0.0);
+    }
+
+    // PROPERTY TESTS ################################################
+
+    #[quickcheck_macros::quickcheck]
+    fn quadratic_near_zero(r: Float) -> bool {
+        let r = r.abs().min(1.0) * 1e-2;
+        let losses: [&dyn RobustLoss; 3] = [
+            &Huber { threshold: 1.0 },
+            &Tukey { threshold: 1.0 },
+            &Cauchy { threshold: 1.0 },
+        ];
+        losses
+            .iter()
+            .all(|l| approx::relative_eq!(l.rho(r, 1.0), r * r, epsilon = EPSILON))
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn weights_are_bounded(r: Float) -> bool {
+        let losses: [&dyn RobustLoss; 3] = [
+            &Huber { threshold: 5.0 },
+            &Tukey { threshold: 5.0 },
+            &Cauchy { threshold: 5.0 },
+        ];
+        losses.iter().all(|l| {
+            let w = l.weight(r, 1.0);
+            (0.0..=1.0).contains(&w)
+        })
+    }
+}
@@ -10,11 +10,11 @@
 //!     - details: <http://ethaneade.com/lie.pdf>
 //!     - summary: <http://ethaneade.com/lie_groups.pdf>
 
-use nalgebra::{Quaternion, Translation3, UnitQuaternion};
+use nalgebra::{Quaternion, Translation3, UnitQuaternion, U3};
 use std::f32::consts::PI;
 
 use crate::math::so3;
-use crate::misc::type_aliases::{Float, Iso3, Mat3, Mat4, Vec3, Vec6};
+use crate::misc::type_aliases::{Float, Iso3, Mat3, Mat4, Mat6, Vec3, Vec6};
 
 const EPSILON_TAYLOR_SERIES: Float = 1e-2;
 const EPSILON_TAYLOR_SERIES_2: Float = EPSILON_TAYLOR_SERIES * EPSILON_TAYLOR_SERIES;
@@ -128,6 +128,18 @@ pub fn log(iso: Iso3) -> Twist {
     }
 }
 
+/// Adjoint matrix of a rigid body motion, such that
+/// `exp(adjoint(iso) * xi) == iso * exp(xi) * iso.inverse()`.
+pub fn adjoint(iso: Iso3) -> Mat6 {
+    let rotation = iso.rotation.to_rotation_matrix().into_inner();
+    let t_rotation = so3::hat(iso.translation.vector) * rotation;
+    let mut adj = Mat6::zeros();
+    adj.fixed_slice_mut::<U3, U3>(0, 0).copy_from(&rotation);
+    adj.fixed_slice_mut::<U3, U3>(0, 3).copy_from(&t_rotation);
+    adj.fixed_slice_mut::<U3, U3>(3, 3).copy_from(&rotation);
+    adj
+}
+
 // TESTS #############################################################
 
 #[cfg(test)]
@@ -172,6 +184,24 @@ mod tests {
         )
     }
 
+    #[quickcheck_macros::quickcheck]
+    fn adjoint_conjugation(
+        t1: Float,
+        t2: Float,
+        t3: Float,
+        a1: Float,
+        a2: Float,
+        a3: Float,
+    ) -> bool {
+        let rigid_motion = gen_rigid_motion(t1, t2, t3, a1, a2, a3);
+        let xi = Vec6::new(0.1, -0.2, 0.3, 0.05, 0.1, -0.02);
+        approx::relative_eq!(
+            exp(adjoint(rigid_motion) * xi),
+            rigid_motion * exp(xi) * rigid_motion.inverse(),
+            epsilon = EPSILON_ROUNDTRIP_APPROX * (1.0 + rigid_motion.translation.vector.norm())
+        )
+    }
+
     // GENERATORS ####################################################
 
     fn gen_rigid_motion(t1: Float, t2: Float, t3: Float, a1: Float, a2: Float, a3: Float) -> Iso3 {
@@ -18,8 +18,11 @@ use crate::misc::type_aliases::{Float, Mat3, Vec3};
 /// Threshold for using Taylor series in computations.
 const EPSILON_TAYLOR_SERIES: Float = 1e-2;
 const EPSILON_TAYLOR_SERIES_2: Float = EPSILON_TAYLOR_SERIES * EPSILON_TAYLOR_SERIES;
+const _1_6: Float = 1.0 / 6.0;
 const _1_8: Float = 0.125;
+const _1_24: Float = 1.0 / 24.0;
 const _1_48: Float = 1.0 / 48.0;
+const _1_120: Float = 1.0 / 120.0;
 
 /// Hat operator.
 /// Goes from so3 parameterization to so3 element (skew-symmetric matrix).
@@ -98,6 +101,22 @@ pub fn log(rotation: UnitQuaternion<Float>) -> Vec3 {
     }
 }
 
+/// Right jacobian of SO3, such that
+/// `exp(w + dw) ~ exp(w) * exp(right_jacobian(w) * dw)` for small `dw`.
+pub fn right_jacobian(w: Vec3) -> Mat3 {
+    let theta_2 = w.norm_squared();
+    let (coef_1, coef_2) = if theta_2 < EPSILON_TAYLOR_SERIES_2 {
+        (0.5 - _1_24 * theta_2, _1_6 - _1_120 * theta_2) // TAYLOR
+    } else {
+        let theta = theta_2.sqrt();
+        (
+            (1.0 - theta.cos()) / theta_2,
+            (theta - theta.sin()) / (theta_2 * theta),
+        )
+    };
+    Mat3::identity() - coef_1 * hat(w) + coef_2 * hat_2(w)
+}
+
 // TESTS #############################################################
 
 #[cfg(test)]
@@ -117,6 +136,16 @@ mod tests {
         assert_eq!(w, log(exp(w)));
     }
 
+    #[test]
+    fn right_jacobian_first_order() {
+        let dw = Vec3::new(1e-3, 2e-3, -1e-3);
+        for w in [Vec3::new(0.3, -0.2, 0.5), Vec3::new(1e-3, 0.0, -2e-3)].iter() {
+            let rotation = exp(w + dw);
+            let approx_rotation = exp(*w) * exp(right_jacobian(*w) * dw);
+            assert!(rotation.angle_to(&approx_rotation) < 1e-5);
+        }
+    }
+
     // PROPERTY TESTS ################################################
 
     #[quickcheck_macros::quickcheck]
@@ -9,6 +9,8 @@ use nalgebra::{DMatrix, Scalar};
 use png::{self, HasParameters};
 use std::{self, fs::File, io::Cursor, path::Path};
 
+use crate::misc::type_aliases::Float;
+
 /// Read a 16 bit gray png image from a file.
 pub fn read_png_16bits<P: AsRef<Path>>(
     file_path: P,
@@ -53,3 +55,66 @@ where
 {
     (x / y, x % y)
 }
+
+/// Simple linear interpolation of a pixel with floating point coordinates.
+/// Return `None` if the point is outside of the image boundaries.
+#[allow(clippy::many_single_char_names)]
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+#[allow(clippy::cast_precision_loss)]
+pub fn interpolate(x: Float, y: Float, image: &DMatrix<u8>) -> Option<Float> {
+    let (height, width) = image.shape();
+    let u = x.floor();
+    let v = y.floor();
+    if u >= 0.0 && u < (width - 2) as Float && v >= 0.0 && v < (height - 2) as Float {
+        let u_0 = u as usize;
+        let v_0 = v as usize;
+        let u_1 = u_0 + 1;
+        let v_1 = v_0 + 1;
+        let vu_00 = Float::from(image[(v_0, u_0)]);
+        let vu_10 = Float::from(image[(v_1, u_0)]);
+        let vu_01 = Float::from(image[(v_0, u_1)]);
+        let vu_11 = Float::from(image[(v_1, u_1)]);
+        let a = x - u;
+        let b = y - v;
+        Some(
+            (1.0 - b) * (1.0 - a) * vu_00
+                + b * (1.0 - a) * vu_10
+                + (1.0 - b) * a * vu_01
+                + b * a * vu_11,
+        )
+    } else {
+        None
+    }
+}
+
+/// Bound a random input of property tests in `(-1, 1)`,
+/// mapping non-finite values to 0.
+#[cfg(test)]
+pub fn bounded(v: Float) -> Float {
+    if v.is_finite() {
+        (v % 10.0) / 10.0
+    } else {
+        0.0
+    }
+}
+
+/// Smoothed pseudo-random texture without repetitive patterns, for tests.
+#[cfg(test)]
+#[allow(clippy::cast_possible_truncation)]
+pub fn gen_texture(nb_rows: usize, nb_cols: usize) -> DMatrix<u8> {
+    let mut seed: u32 = 42;
+    let noise = DMatrix::from_fn(nb_rows, nb_cols, |_, _| {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        Float::from((seed >> 16) as u8)
+    });
+    DMatrix::from_fn(nb_rows, nb_cols, |y, x| {
+        let mut sum = 0.0;
+        for dy in 0..3 {
+            for dx in 0..3 {
+                sum += noise[((y + dy).min(nb_rows - 1), (x + dx).min(nb_cols - 1))];
+            }
+        }
+        (sum / 9.0) as u8
+    })
+}
@@ -14,17 +14,29 @@ pub type Point2 = na::Point2<Float>;
 /// A point with three Float coordinates.
 pub type Point3 = na::Point3<Float>;
 
+/// A vector with two Float coordinates.
+pub type Vec2 = na::Vector2<Float>;
 /// A vector with three Float coordinates.
 pub type Vec3 = na::Vector3<Float>;
 /// A vector with six Float coordinates.
 pub type Vec6 = na::Vector6<Float>;
+/// A vector with eight Float coordinates.
+pub type Vec8 = na::VectorN<Float, na::U8>;
 
+/// A 2x2 matrix of Floats.
+pub type Mat2 = na::Matrix2<Float>;
+/// A 2x3 matrix of Floats.
+pub type Mat23 = na::Matrix2x3<Float>;
 /// A 3x3 matrix of Floats.
 pub type Mat3 = na::Matrix3<Float>;
 /// A 4x4 matrix of Floats.
 pub type Mat4 = na::Matrix4<Float>;
 /// A 6x6 matrix of Floats.
 pub type Mat6 = na::Matrix6<Float>;
+/// A 8x8 matrix of Floats.
+pub type Mat8 = na::MatrixN<Float, na::U8>;
+/// A 9x9 matrix of Floats.
+pub type Mat9 = na::MatrixN<Float, na::U9>;
 
 /// A direct 3D isometry, also known as rigid body motion.
 pub type Iso3 = na::Isometry3<Float>;